@@ -0,0 +1,531 @@
+use {
+    crate::{
+        calendar::{add_months, month_index, sub_months, SECONDS_PER_DAY},
+        error::SubscriptionError,
+        fee::calculate_fee,
+        state::{BillingInterval, Plan, PlanPolicy, SplitRecipient},
+    },
+    solana_program::program_error::ProgramError,
+};
+
+/// Returns the price of `plan` in effect at `now`.
+pub fn plan_amount(plan: &Plan, now: i64) -> u64 {
+    match plan.pending_amount_time {
+        0 => plan.amount,
+        time if now >= time => plan.pending_amount,
+        _ => plan.amount,
+    }
+}
+
+/// Returns the price change scheduled on `plan` that isn't in effect yet at
+/// `now`, as `(amount, effective_time)`.
+pub fn pending_price(plan: &Plan, now: i64) -> Option<(u64, i64)> {
+    match plan.pending_amount_time {
+        0 => None,
+        time if now >= time => None,
+        time => Some((plan.pending_amount, time)),
+    }
+}
+
+/// Returns the length of the shortest period billed by `interval`: a
+/// February for calendar months and a common year for calendar years.
+pub fn shortest_period(interval: BillingInterval) -> i64 {
+    match interval {
+        BillingInterval::Duration => 1,
+        BillingInterval::Month => 28 * SECONDS_PER_DAY,
+        BillingInterval::Year => 365 * SECONDS_PER_DAY,
+    }
+}
+
+/// Returns the time `periods` billing periods after `time`.
+///
+/// Periods of `duration` seconds are simply added, calendar periods land on
+/// `anchor_day`, or the last day of months that are too short.
+pub fn advance_periods(
+    interval: BillingInterval,
+    duration: i64,
+    anchor_day: u8,
+    time: i64,
+    periods: u32,
+) -> Result<i64, ProgramError> {
+    let months = match interval {
+        BillingInterval::Duration => {
+            return duration
+                .checked_mul(periods as i64)
+                .and_then(|length| time.checked_add(length))
+                .ok_or_else(|| SubscriptionError::Overflow.into());
+        }
+        BillingInterval::Month => periods,
+        BillingInterval::Year => periods.checked_mul(12).ok_or(SubscriptionError::Overflow)?,
+    };
+    add_months(time, months, anchor_day)
+}
+
+/// Returns the time `periods` billing periods before `time`, the inverse of
+/// `advance_periods` for times on the schedule.
+pub fn retreat_periods(
+    interval: BillingInterval,
+    duration: i64,
+    anchor_day: u8,
+    time: i64,
+    periods: u32,
+) -> Result<i64, ProgramError> {
+    let months = match interval {
+        BillingInterval::Duration => {
+            return duration
+                .checked_mul(periods as i64)
+                .and_then(|length| time.checked_sub(length))
+                .ok_or_else(|| SubscriptionError::Overflow.into());
+        }
+        BillingInterval::Month => periods,
+        BillingInterval::Year => periods.checked_mul(12).ok_or(SubscriptionError::Overflow)?,
+    };
+    sub_months(time, months, anchor_day)
+}
+
+/// Counts the renewals due by `now` for a subscription next due at `next`,
+/// that is `next` itself and every period after it that started by `now`.
+pub fn periods_due(
+    interval: BillingInterval,
+    duration: i64,
+    anchor_day: u8,
+    next: i64,
+    now: i64,
+) -> Result<u32, ProgramError> {
+    if now < next {
+        return Ok(0);
+    }
+    let elapsed = now.checked_sub(next).ok_or(SubscriptionError::Overflow)?;
+    let missed = match interval {
+        BillingInterval::Duration if duration <= 0 => {
+            return Err(ProgramError::InvalidArgument);
+        }
+        BillingInterval::Duration => elapsed / duration,
+        // calendar estimates can be one period ahead of the anchor day
+        calendar => {
+            let months_per_period = match calendar {
+                BillingInterval::Year => 12,
+                _ => 1,
+            };
+            let estimate = (month_index(now) - month_index(next)) / months_per_period;
+            let estimate = u32::try_from(estimate).map_err(|_| SubscriptionError::Overflow)?;
+            if estimate > 0
+                && advance_periods(interval, duration, anchor_day, next, estimate)? > now
+            {
+                estimate as i64 - 1
+            } else {
+                estimate as i64
+            }
+        }
+    };
+    missed
+        .checked_add(1)
+        .and_then(|due| u32::try_from(due).ok())
+        .ok_or_else(|| SubscriptionError::Overflow.into())
+}
+
+/// Calculates the price of `periods` periods at `amount` paid up front, less
+/// the best prepay discount in `policy` they qualify for, rounded down.
+pub fn prepay_amount(policy: &PlanPolicy, amount: u64, periods: u32) -> Result<u64, ProgramError> {
+    let total = amount
+        .checked_mul(periods as u64)
+        .ok_or(SubscriptionError::Overflow)?;
+    let basis_points = policy
+        .prepay_discounts
+        .iter()
+        .filter(|discount| discount.min_periods != 0 && discount.min_periods <= periods)
+        .map(|discount| discount.basis_points)
+        .max()
+        .unwrap_or(0);
+    Ok(total - calculate_fee(total, basis_points as u64)?)
+}
+
+/// Returns the split recipients in use, which always come first.
+pub fn split_recipients(splits: &[SplitRecipient]) -> &[SplitRecipient] {
+    let count = splits
+        .iter()
+        .take_while(|split| split.basis_points != 0)
+        .count();
+    &splits[..count]
+}
+
+/// Splits `amount` between `recipients` by their basis points, rounding each
+/// share down, with the rounding dust going to the first recipient.
+pub fn split_amount(recipients: &[SplitRecipient], amount: u64) -> Result<Vec<u64>, ProgramError> {
+    let mut shares = recipients
+        .iter()
+        .map(|split| calculate_fee(amount, split.basis_points as u64))
+        .collect::<Result<Vec<u64>, ProgramError>>()?;
+    let total = shares
+        .iter()
+        .try_fold(0u64, |total, share| total.checked_add(*share))
+        .ok_or(SubscriptionError::Overflow)?;
+    if let Some(first) = shares.first_mut() {
+        let dust = amount
+            .checked_sub(total)
+            .ok_or(SubscriptionError::InvalidSplit)?;
+        *first += dust;
+    }
+    Ok(shares)
+}
+
+/// Calculates the referrer's commission on a renewal charging `periods`
+/// periods at `amount`, rounded down. Only periods within the first
+/// `referral_renewals` renewals are commissioned, all of them if 0, and
+/// `renewal_count` renewals have already been made.
+pub fn referral_commission(
+    basis_points: u16,
+    referral_renewals: u32,
+    renewal_count: u64,
+    amount: u64,
+    periods: u32,
+) -> Result<u64, ProgramError> {
+    let referred_amount = amount
+        .checked_mul(referred_periods(referral_renewals, renewal_count, periods))
+        .ok_or(SubscriptionError::Overflow)?;
+    calculate_fee(referred_amount, basis_points as u64)
+}
+
+/// Calculates the referrer's commission on `periods` periods prepaid for
+/// `prepaid_amount` in total, rounded down. The commissioned periods are
+/// counted as for `referral_commission`, each worth an equal share of the
+/// prepaid amount.
+pub fn prepay_referral_commission(
+    basis_points: u16,
+    referral_renewals: u32,
+    renewal_count: u64,
+    prepaid_amount: u64,
+    periods: u32,
+) -> Result<u64, ProgramError> {
+    if periods == 0 {
+        return Ok(0);
+    }
+    let referred_amount = prepaid_amount as u128
+        * referred_periods(referral_renewals, renewal_count, periods) as u128
+        / periods as u128;
+    calculate_fee(referred_amount as u64, basis_points as u64)
+}
+
+/// Returns how many of `periods` periods fall within the first
+/// `referral_renewals` renewals, all of them if 0, after `renewal_count`.
+fn referred_periods(referral_renewals: u32, renewal_count: u64, periods: u32) -> u64 {
+    if referral_renewals == 0 {
+        periods as u64
+    } else {
+        std::cmp::min(
+            periods as u64,
+            (referral_renewals as u64).saturating_sub(renewal_count),
+        )
+    }
+}
+
+/// Calculates what `remaining` seconds of a period costing `amount` every
+/// `duration` seconds are worth, rounding down.
+///
+/// `remaining` is clamped to a single period, and nothing is owed for a
+/// period that is already over.
+pub fn prorate(amount: u64, remaining: i64, duration: i64) -> Result<u64, ProgramError> {
+    if duration <= 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let remaining = remaining.clamp(0, duration);
+    let value = (amount as u128)
+        .checked_mul(remaining as u128)
+        .ok_or(SubscriptionError::Overflow)?
+        / duration as u128;
+    u64::try_from(value).map_err(|_| SubscriptionError::Overflow.into())
+}
+
+/// Calculates how many seconds `credit` buys at `amount` every `duration`
+/// seconds, rounding down.
+pub fn credit_to_time(credit: u64, amount: u64, duration: i64) -> Result<i64, ProgramError> {
+    if amount == 0 || duration <= 0 {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let time = (credit as u128)
+        .checked_mul(duration as u128)
+        .ok_or(SubscriptionError::Overflow)?
+        / amount as u128;
+    i64::try_from(time).map_err(|_| SubscriptionError::Overflow.into())
+}
+
+#[cfg(test)]
+mod test {
+    use {
+        super::*,
+        crate::state::{
+            CatchUpPolicy, FeePolicy, PrepayDiscount, MAX_SPLIT_RECIPIENTS, PLAN_NAME_LEN,
+            PLAN_URI_LEN, PREPAY_DISCOUNT_TIERS,
+        },
+        solana_program::pubkey::Pubkey,
+    };
+
+    fn plan(amount: u64, pending_amount: u64, pending_amount_time: i64) -> Plan {
+        Plan {
+            bump: 0,
+            active: true,
+            payee: Pubkey::default(),
+            id: 0,
+            deposit_mint: Pubkey::default(),
+            amount,
+            duration: 10,
+            interval: BillingInterval::Duration,
+            fee: FeePolicy {
+                fixed: 0,
+                basis_points: 0,
+            },
+            policy: PlanPolicy {
+                allow_pause: false,
+                grace_period: 0,
+                trial_periods: 0,
+                catch_up: CatchUpPolicy::Reanchor,
+                prepay_discounts: [PrepayDiscount {
+                    min_periods: 0,
+                    basis_points: 0,
+                }; PREPAY_DISCOUNT_TIERS],
+                referral_basis_points: 0,
+                referral_renewals: 0,
+            },
+            splits: [SplitRecipient::default(); MAX_SPLIT_RECIPIENTS],
+            pending_amount,
+            pending_amount_time,
+            count: 0,
+            name: [0; PLAN_NAME_LEN],
+            uri: [0; PLAN_URI_LEN],
+        }
+    }
+
+    #[test]
+    fn test_plan_amount() {
+        assert_eq!(plan_amount(&plan(200, 0, 0), 100), 200);
+        assert_eq!(plan_amount(&plan(200, 300, 50), 49), 200);
+        assert_eq!(plan_amount(&plan(200, 300, 50), 50), 300);
+        assert_eq!(plan_amount(&plan(200, 300, 50), 51), 300);
+    }
+
+    #[test]
+    fn test_pending_price() {
+        assert_eq!(pending_price(&plan(200, 0, 0), 100), None);
+        assert_eq!(pending_price(&plan(200, 300, 50), 49), Some((300, 50)));
+        assert_eq!(pending_price(&plan(200, 300, 50), 50), None);
+    }
+
+    #[test]
+    fn test_advance_periods() {
+        // 2024-01-31T12:00:00Z and 2025-01-31T12:00:00Z
+        let jan_31_2024 = 1_706_702_400;
+        let jan_31_2025 = 1_738_324_800;
+        assert_eq!(
+            advance_periods(BillingInterval::Duration, 10, 31, 100, 3),
+            Ok(130)
+        );
+        assert_eq!(
+            advance_periods(BillingInterval::Month, 10, 31, jan_31_2024, 12),
+            Ok(jan_31_2025)
+        );
+        assert_eq!(
+            advance_periods(BillingInterval::Year, 10, 31, jan_31_2024, 1),
+            Ok(jan_31_2025)
+        );
+        assert_eq!(
+            advance_periods(BillingInterval::Duration, i64::MAX, 1, 1, 1),
+            Err(SubscriptionError::Overflow.into())
+        );
+    }
+
+    #[test]
+    fn test_shortest_period() {
+        // 2023-02-01T00:00:00Z and 2023-01-01T00:00:00Z
+        let feb_1_2023 = 1_675_209_600;
+        let jan_1_2023 = 1_672_531_200;
+        for (interval, start) in [
+            (BillingInterval::Month, feb_1_2023),
+            (BillingInterval::Year, jan_1_2023),
+        ] {
+            assert_eq!(
+                advance_periods(interval, 0, 1, start, 1),
+                Ok(start + shortest_period(interval))
+            );
+        }
+        assert_eq!(shortest_period(BillingInterval::Duration), 1);
+    }
+
+    #[test]
+    fn test_retreat_periods() {
+        // 2024-01-31T12:00:00Z, 2024-02-29T12:00:00Z and 2025-01-31T12:00:00Z
+        let jan_31_2024 = 1_706_702_400;
+        let feb_29_2024 = 1_709_208_000;
+        let jan_31_2025 = 1_738_324_800;
+        assert_eq!(
+            retreat_periods(BillingInterval::Duration, 10, 31, 130, 3),
+            Ok(100)
+        );
+        assert_eq!(
+            retreat_periods(BillingInterval::Month, 10, 31, feb_29_2024, 1),
+            Ok(jan_31_2024)
+        );
+        assert_eq!(
+            retreat_periods(BillingInterval::Year, 10, 31, jan_31_2025, 1),
+            Ok(jan_31_2024)
+        );
+        assert_eq!(
+            retreat_periods(BillingInterval::Duration, i64::MAX, 1, -2, 1),
+            Err(SubscriptionError::Overflow.into())
+        );
+    }
+
+    #[test]
+    fn test_periods_due() {
+        let due = |next, now| periods_due(BillingInterval::Duration, 10, 1, next, now);
+        assert_eq!(due(100, 99), Ok(0));
+        assert_eq!(due(100, 100), Ok(1));
+        assert_eq!(due(100, 109), Ok(1));
+        assert_eq!(due(100, 110), Ok(2));
+        assert_eq!(due(100, 135), Ok(4));
+        assert_eq!(
+            periods_due(BillingInterval::Duration, 1, 1, 0, i64::MAX),
+            Err(SubscriptionError::Overflow.into())
+        );
+    }
+
+    #[test]
+    fn test_periods_due_calendar() {
+        // 2024-01-31T12:00:00Z, 2024-02-29T12:00:00Z, 2024-03-31T12:00:00Z
+        // and 2025-01-31T12:00:00Z
+        let jan_31_2024 = 1_706_702_400;
+        let feb_29_2024 = 1_709_208_000;
+        let mar_31_2024 = 1_711_886_400;
+        let jan_31_2025 = 1_738_324_800;
+        let months = |next, now| periods_due(BillingInterval::Month, 10, 31, next, now);
+        assert_eq!(months(jan_31_2024, feb_29_2024 - 1), Ok(1));
+        assert_eq!(months(jan_31_2024, feb_29_2024), Ok(2));
+        assert_eq!(months(jan_31_2024, mar_31_2024 - 1), Ok(2));
+        assert_eq!(months(jan_31_2024, mar_31_2024), Ok(3));
+        assert_eq!(months(feb_29_2024, mar_31_2024), Ok(2));
+        assert_eq!(months(jan_31_2024, jan_31_2025), Ok(13));
+
+        let years = |next, now| periods_due(BillingInterval::Year, 10, 31, next, now);
+        assert_eq!(years(jan_31_2024, jan_31_2025 - 1), Ok(1));
+        assert_eq!(years(jan_31_2024, jan_31_2025), Ok(2));
+    }
+
+    #[test]
+    fn test_prepay_amount() {
+        let mut policy = plan(200, 0, 0).policy;
+        assert_eq!(prepay_amount(&policy, 200, 12), Ok(2_400));
+
+        policy.prepay_discounts[0] = PrepayDiscount {
+            min_periods: 12,
+            basis_points: 1_500,
+        };
+        policy.prepay_discounts[1] = PrepayDiscount {
+            min_periods: 3,
+            basis_points: 500,
+        };
+        assert_eq!(prepay_amount(&policy, 200, 2), Ok(400));
+        assert_eq!(prepay_amount(&policy, 200, 3), Ok(570));
+        assert_eq!(prepay_amount(&policy, 200, 11), Ok(2_090));
+        assert_eq!(prepay_amount(&policy, 200, 12), Ok(2_040));
+        assert_eq!(prepay_amount(&policy, 199, 3), Ok(568));
+        assert_eq!(
+            prepay_amount(&policy, u64::MAX, 2),
+            Err(SubscriptionError::Overflow.into())
+        );
+    }
+
+    #[test]
+    fn test_split_amount() {
+        let split = |basis_points| SplitRecipient {
+            recipient: Pubkey::new_unique(),
+            basis_points,
+        };
+        let splits = [split(7_000), split(2_000), split(1_000)];
+        assert_eq!(split_recipients(&splits).len(), 3);
+        assert_eq!(split_amount(&splits, 200), Ok(vec![140, 40, 20]));
+        assert_eq!(split_amount(&splits, 199), Ok(vec![141, 39, 19]));
+        assert_eq!(split_amount(&splits, 1), Ok(vec![1, 0, 0]));
+
+        let splits = [split(5_000), split(5_000), SplitRecipient::default()];
+        assert_eq!(split_recipients(&splits).len(), 2);
+        assert_eq!(split_amount(split_recipients(&splits), 3), Ok(vec![2, 1]));
+        assert_eq!(split_amount(&[], 3), Ok(vec![]));
+    }
+
+    #[test]
+    fn test_referral_commission() {
+        // forever
+        assert_eq!(referral_commission(1_000, 0, 0, 200, 1), Ok(20));
+        assert_eq!(referral_commission(1_000, 0, 100, 200, 3), Ok(60));
+        assert_eq!(referral_commission(1_000, 0, 0, 199, 1), Ok(19));
+
+        // first 3 renewals
+        assert_eq!(referral_commission(1_000, 3, 2, 200, 1), Ok(20));
+        assert_eq!(referral_commission(1_000, 3, 1, 200, 3), Ok(40));
+        assert_eq!(referral_commission(1_000, 3, 3, 200, 1), Ok(0));
+        assert_eq!(referral_commission(1_000, 3, 5, 200, 1), Ok(0));
+
+        assert_eq!(referral_commission(0, 0, 0, 200, 1), Ok(0));
+        assert_eq!(
+            referral_commission(1_000, 0, 0, u64::MAX, 2),
+            Err(SubscriptionError::Overflow.into())
+        );
+    }
+
+    #[test]
+    fn test_prepay_referral_commission() {
+        // forever, on the discounted price
+        assert_eq!(prepay_referral_commission(1_000, 0, 0, 540, 3), Ok(54));
+        assert_eq!(prepay_referral_commission(1_000, 0, 100, 540, 3), Ok(54));
+
+        // first 3 renewals, one of them already made
+        assert_eq!(prepay_referral_commission(1_000, 3, 1, 540, 3), Ok(36));
+        assert_eq!(prepay_referral_commission(1_000, 3, 3, 540, 3), Ok(0));
+
+        assert_eq!(prepay_referral_commission(0, 0, 0, 540, 3), Ok(0));
+        assert_eq!(prepay_referral_commission(1_000, 0, 0, 540, 0), Ok(0));
+        assert_eq!(
+            prepay_referral_commission(1_000, 0, 0, u64::MAX, u32::MAX),
+            Ok(u64::MAX / 10)
+        );
+    }
+
+    #[test]
+    fn test_prorate() {
+        assert_eq!(prorate(200, 5, 10), Ok(100));
+        assert_eq!(prorate(200, 10, 10), Ok(200));
+        assert_eq!(prorate(200, 0, 10), Ok(0));
+        assert_eq!(prorate(u64::MAX, i64::MAX, i64::MAX), Ok(u64::MAX));
+    }
+
+    #[test]
+    fn test_prorate_rounds_down() {
+        assert_eq!(prorate(200, 1, 3), Ok(66));
+        assert_eq!(prorate(1, 9, 10), Ok(0));
+    }
+
+    #[test]
+    fn test_prorate_clamps_remaining() {
+        assert_eq!(prorate(200, -5, 10), Ok(0));
+        assert_eq!(prorate(200, 25, 10), Ok(200));
+    }
+
+    #[test]
+    fn test_prorate_invalid_duration() {
+        assert_eq!(prorate(200, 5, 0), Err(ProgramError::InvalidArgument));
+    }
+
+    #[test]
+    fn test_credit_to_time() {
+        assert_eq!(credit_to_time(100, 200, 10), Ok(5));
+        assert_eq!(credit_to_time(0, 200, 10), Ok(0));
+        assert_eq!(credit_to_time(399, 200, 10), Ok(19));
+        assert_eq!(
+            credit_to_time(u64::MAX, 1, i64::MAX),
+            Err(SubscriptionError::Overflow.into())
+        );
+        assert_eq!(
+            credit_to_time(100, 0, 10),
+            Err(ProgramError::InvalidArgument)
+        );
+    }
+}
@@ -0,0 +1,223 @@
+use {crate::error::SubscriptionError, solana_program::program_error::ProgramError};
+
+/// Seconds in a UTC day, leap seconds aren't counted by unix time.
+pub const SECONDS_PER_DAY: i64 = 86_400;
+
+/// Returns whether `year` is a leap year in the Gregorian calendar.
+pub fn is_leap_year(year: i64) -> bool {
+    year % 4 == 0 && (year % 100 != 0 || year % 400 == 0)
+}
+
+/// Returns the number of days in `month` (1 to 12) of `year`.
+pub fn days_in_month(year: i64, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+/// Converts days since the unix epoch to a `(year, month, day)` date.
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    // shifted so years start in March and leap days fall last
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1_460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = (day_of_year - (153 * shifted_month + 2) / 5 + 1) as u32;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    } as u32;
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+/// Converts a `(year, month, day)` date to days since the unix epoch.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year.rem_euclid(400);
+    let shifted_month = if month > 2 { month - 3 } else { month + 9 } as i64;
+    let day_of_year = (153 * shifted_month + 2) / 5 + day as i64 - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    era * 146_097 + day_of_era - 719_468
+}
+
+/// Returns the day of the month, 1 to 31, that `time` falls on.
+pub fn day_of_month(time: i64) -> u8 {
+    let (_, _, day) = civil_from_days(time.div_euclid(SECONDS_PER_DAY));
+    day as u8
+}
+
+/// Returns the number of calendar months from the start of year 0 to the
+/// month `time` falls in.
+pub fn month_index(time: i64) -> i64 {
+    let (year, month, _) = civil_from_days(time.div_euclid(SECONDS_PER_DAY));
+    year * 12 + month as i64 - 1
+}
+
+/// Returns the time `months` calendar months after `time`, on `anchor_day`
+/// of that month at the same time of day.
+///
+/// Months shorter than `anchor_day` are clamped to their last day, so a
+/// subscription anchored on the 31st renews on the 30th in April and the
+/// 28th or 29th in February, and on the 31st again in May.
+pub fn add_months(time: i64, months: u32, anchor_day: u8) -> Result<i64, ProgramError> {
+    shift_months(time, months as i64, anchor_day)
+}
+
+/// Returns the time `months` calendar months before `time`, on `anchor_day`
+/// of that month at the same time of day, clamped like `add_months`.
+pub fn sub_months(time: i64, months: u32, anchor_day: u8) -> Result<i64, ProgramError> {
+    shift_months(time, -(months as i64), anchor_day)
+}
+
+fn shift_months(time: i64, months: i64, anchor_day: u8) -> Result<i64, ProgramError> {
+    if anchor_day == 0 || anchor_day > 31 {
+        return Err(ProgramError::InvalidArgument);
+    }
+    let days = time.div_euclid(SECONDS_PER_DAY);
+    let seconds = time.rem_euclid(SECONDS_PER_DAY);
+    let (year, month, _) = civil_from_days(days);
+
+    let total_months = year
+        .checked_mul(12)
+        .and_then(|total| total.checked_add(month as i64 - 1 + months))
+        .ok_or(SubscriptionError::Overflow)?;
+    let year = total_months.div_euclid(12);
+    let month = total_months.rem_euclid(12) as u32 + 1;
+    let day = std::cmp::min(anchor_day as u32, days_in_month(year, month));
+
+    days_from_civil(year, month, day)
+        .checked_mul(SECONDS_PER_DAY)
+        .and_then(|time| time.checked_add(seconds))
+        .ok_or_else(|| SubscriptionError::Overflow.into())
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    // 2024-01-31T12:00:00Z
+    const JAN_31_2024: i64 = 1_706_702_400;
+    // 2024-02-29T12:00:00Z
+    const FEB_29_2024: i64 = 1_709_208_000;
+    // 2024-03-31T12:00:00Z
+    const MAR_31_2024: i64 = 1_711_886_400;
+    // 2024-04-30T12:00:00Z
+    const APR_30_2024: i64 = 1_714_478_400;
+    // 2025-02-28T12:00:00Z
+    const FEB_28_2025: i64 = 1_740_744_000;
+    // 2028-02-29T12:00:00Z
+    const FEB_29_2028: i64 = 1_835_438_400;
+
+    #[test]
+    fn test_is_leap_year() {
+        assert!(is_leap_year(2024));
+        assert!(is_leap_year(2000));
+        assert!(!is_leap_year(2023));
+        assert!(!is_leap_year(1900));
+        assert!(!is_leap_year(2100));
+    }
+
+    #[test]
+    fn test_days_in_month() {
+        assert_eq!(days_in_month(2024, 2), 29);
+        assert_eq!(days_in_month(2023, 2), 28);
+        assert_eq!(days_in_month(2000, 2), 29);
+        assert_eq!(days_in_month(1900, 2), 28);
+        assert_eq!(days_in_month(2023, 4), 30);
+        assert_eq!(days_in_month(2023, 12), 31);
+    }
+
+    #[test]
+    fn test_civil_round_trip() {
+        assert_eq!(civil_from_days(0), (1970, 1, 1));
+        assert_eq!(civil_from_days(-1), (1969, 12, 31));
+        assert_eq!(days_from_civil(2024, 2, 29), 19_782);
+        for days in -800_000..800_000 {
+            let (year, month, day) = civil_from_days(days);
+            assert_eq!(days_from_civil(year, month, day), days);
+        }
+    }
+
+    #[test]
+    fn test_day_of_month() {
+        assert_eq!(day_of_month(0), 1);
+        assert_eq!(day_of_month(-1), 31);
+        assert_eq!(day_of_month(JAN_31_2024), 31);
+        assert_eq!(day_of_month(FEB_29_2024), 29);
+    }
+
+    #[test]
+    fn test_month_index() {
+        assert_eq!(month_index(0), 1970 * 12);
+        assert_eq!(month_index(-1), 1969 * 12 + 11);
+        assert_eq!(month_index(FEB_29_2024) - month_index(JAN_31_2024), 1);
+        assert_eq!(month_index(FEB_29_2028) - month_index(FEB_29_2024), 48);
+    }
+
+    #[test]
+    fn test_add_months_clamps_to_month_end() {
+        assert_eq!(add_months(JAN_31_2024, 1, 31), Ok(FEB_29_2024));
+        assert_eq!(add_months(FEB_29_2024, 1, 31), Ok(MAR_31_2024));
+        assert_eq!(add_months(MAR_31_2024, 1, 31), Ok(APR_30_2024));
+        assert_eq!(add_months(JAN_31_2024, 3, 31), Ok(APR_30_2024));
+    }
+
+    #[test]
+    fn test_add_months_leap_years() {
+        assert_eq!(add_months(FEB_29_2024, 12, 29), Ok(FEB_28_2025));
+        assert_eq!(add_months(FEB_29_2024, 48, 29), Ok(FEB_29_2028));
+        assert_eq!(add_months(FEB_28_2025, 36, 29), Ok(FEB_29_2028));
+    }
+
+    #[test]
+    fn test_add_months_across_years_and_epoch() {
+        // 1969-12-15T06:00:00Z to 1970-01-15T06:00:00Z
+        assert_eq!(
+            add_months(-1_447_200, 1, 15),
+            Ok(14 * SECONDS_PER_DAY + 6 * 3_600)
+        );
+        assert_eq!(add_months(JAN_31_2024, 0, 31), Ok(JAN_31_2024));
+    }
+
+    #[test]
+    fn test_sub_months() {
+        assert_eq!(sub_months(MAR_31_2024, 1, 31), Ok(FEB_29_2024));
+        assert_eq!(sub_months(APR_30_2024, 1, 31), Ok(MAR_31_2024));
+        assert_eq!(sub_months(FEB_29_2028, 48, 29), Ok(FEB_29_2024));
+        assert_eq!(sub_months(FEB_28_2025, 12, 29), Ok(FEB_29_2024));
+        // 1970-01-15T06:00:00Z to 1969-12-15T06:00:00Z
+        assert_eq!(
+            sub_months(14 * SECONDS_PER_DAY + 6 * 3_600, 1, 15),
+            Ok(-1_447_200)
+        );
+        assert_eq!(
+            sub_months(i64::MIN, 1, 1),
+            Err(SubscriptionError::Overflow.into())
+        );
+    }
+
+    #[test]
+    fn test_add_months_invalid() {
+        assert_eq!(
+            add_months(JAN_31_2024, 1, 0),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(
+            add_months(JAN_31_2024, 1, 32),
+            Err(ProgramError::InvalidArgument)
+        );
+        assert_eq!(
+            add_months(i64::MAX, 1, 1),
+            Err(SubscriptionError::Overflow.into())
+        );
+    }
+}
@@ -12,6 +12,40 @@ pub enum SubscriptionError {
     InvalidReceiver,
     #[error("Already expired.")]
     AlreadyExpired,
+    #[error("Caller does not hold the subscription ownership token.")]
+    NotOwner,
+    #[error("Withdrawal would leave insufficient funds for the next renewal.")]
+    InsufficientReserve,
+    #[error("Subscription index is past the next unused index.")]
+    InvalidIndex,
+    #[error("Starting deposit does not cover the renewal fee.")]
+    InsufficientStartAmount,
+    #[error("Arithmetic overflow.")]
+    Overflow,
+    #[error("Renewal fee exceeds subscription amount.")]
+    InvalidFee,
+    #[error("Plan is retired.")]
+    PlanRetired,
+    #[error("Subscription is paused.")]
+    Paused,
+    #[error("Subscription is not paused.")]
+    NotPaused,
+    #[error("Plan does not allow pausing.")]
+    PauseNotAllowed,
+    #[error("Invalid plan policy.")]
+    InvalidPolicy,
+    #[error("Price change must be at least one period away.")]
+    InsufficientNotice,
+    #[error("Amount exceeds the subscriber's maximum per period.")]
+    AmountAboveMax,
+    #[error("Extension goes past the subscription's fixed term.")]
+    TermExceeded,
+    #[error("Invalid revenue split.")]
+    InvalidSplit,
+    #[error("Renewal is due.")]
+    RenewalDue,
+    #[error("Subscription is prepaid past the current period.")]
+    Prepaid,
 }
 
 impl From<SubscriptionError> for ProgramError {
@@ -0,0 +1,144 @@
+use {
+    crate::{error::SubscriptionError, state::FeePolicy},
+    solana_program::{msg, program_error::ProgramError},
+};
+
+/// Basis points in one whole, i.e. 100%.
+pub const BASIS_POINTS_DENOMINATOR: u64 = 10_000;
+
+/// Fee paid to renewers, currently fixed to 1% of amount.
+pub const FEE_BASIS_POINTS: u64 = 100;
+
+/// Calculates `basis_points` of `amount`, rounding down.
+///
+/// Computed in `u128` so that any `u64` amount can be multiplied without
+/// losing precision. Fails if the result doesn't fit in a `u64`, which can
+/// only happen with more than 100% worth of basis points.
+pub fn calculate_fee(amount: u64, basis_points: u64) -> Result<u64, ProgramError> {
+    let fee = (amount as u128)
+        .checked_mul(basis_points as u128)
+        .ok_or(SubscriptionError::Overflow)?
+        / BASIS_POINTS_DENOMINATOR as u128;
+    u64::try_from(fee).map_err(|_| SubscriptionError::Overflow.into())
+}
+
+/// Calculates the renewal fee paid to the caller for `amount`.
+pub fn renewal_fee(amount: u64) -> Result<u64, ProgramError> {
+    calculate_fee(amount, FEE_BASIS_POINTS)
+}
+
+/// Calculates the fee paid to the caller under a subscription's `policy`.
+pub fn crank_fee(policy: &FeePolicy, amount: u64) -> Result<u64, ProgramError> {
+    policy
+        .fixed
+        .checked_add(calculate_fee(amount, policy.basis_points as u64)?)
+        .ok_or_else(|| SubscriptionError::Overflow.into())
+}
+
+/// Checks that `policy` never charges more than `amount` per renewal.
+pub fn check_fee_policy(policy: &FeePolicy, amount: u64) -> Result<u64, ProgramError> {
+    let fee = crank_fee(policy, amount)?;
+    if policy.basis_points as u64 > BASIS_POINTS_DENOMINATOR || fee > amount {
+        msg!("Fee {} exceeds amount {}", fee, amount);
+        return Err(SubscriptionError::InvalidFee.into());
+    }
+    Ok(fee)
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    #[test]
+    fn test_calculate_fee() {
+        assert_eq!(calculate_fee(0, FEE_BASIS_POINTS), Ok(0));
+        assert_eq!(calculate_fee(200, FEE_BASIS_POINTS), Ok(2));
+        assert_eq!(calculate_fee(10_000, 1), Ok(1));
+        assert_eq!(calculate_fee(200, 0), Ok(0));
+    }
+
+    #[test]
+    fn test_calculate_fee_rounds_down() {
+        assert_eq!(calculate_fee(99, FEE_BASIS_POINTS), Ok(0));
+        assert_eq!(calculate_fee(199, FEE_BASIS_POINTS), Ok(1));
+        assert_eq!(calculate_fee(9_999, 1), Ok(0));
+    }
+
+    #[test]
+    fn test_calculate_fee_max_amount() {
+        assert_eq!(
+            calculate_fee(u64::MAX, FEE_BASIS_POINTS),
+            Ok(u64::MAX / 100)
+        );
+        assert_eq!(
+            calculate_fee(u64::MAX, BASIS_POINTS_DENOMINATOR),
+            Ok(u64::MAX)
+        );
+        assert_eq!(calculate_fee(u64::MAX, 1), Ok(u64::MAX / 10_000));
+    }
+
+    #[test]
+    fn test_calculate_fee_overflow() {
+        assert_eq!(
+            calculate_fee(u64::MAX, BASIS_POINTS_DENOMINATOR + 1),
+            Err(SubscriptionError::Overflow.into())
+        );
+        assert_eq!(
+            calculate_fee(u64::MAX, u64::MAX),
+            Err(SubscriptionError::Overflow.into())
+        );
+        assert_eq!(
+            calculate_fee(u64::MAX / 2, 2 * BASIS_POINTS_DENOMINATOR),
+            Ok(u64::MAX - 1)
+        );
+    }
+
+    #[test]
+    fn test_crank_fee() {
+        let policy = FeePolicy {
+            fixed: 5,
+            basis_points: 100,
+        };
+        assert_eq!(crank_fee(&policy, 0), Ok(5));
+        assert_eq!(crank_fee(&policy, 200), Ok(7));
+        assert_eq!(crank_fee(&policy, u64::MAX), Ok(u64::MAX / 100 + 5));
+
+        let policy = FeePolicy {
+            fixed: 1,
+            basis_points: BASIS_POINTS_DENOMINATOR as u16,
+        };
+        assert_eq!(
+            crank_fee(&policy, u64::MAX),
+            Err(SubscriptionError::Overflow.into())
+        );
+    }
+
+    #[test]
+    fn test_check_fee_policy() {
+        let policy = FeePolicy {
+            fixed: 5,
+            basis_points: 100,
+        };
+        assert_eq!(check_fee_policy(&policy, 200), Ok(7));
+        assert_eq!(check_fee_policy(&policy, 5), Ok(5));
+        assert_eq!(
+            check_fee_policy(&policy, 4),
+            Err(SubscriptionError::InvalidFee.into())
+        );
+
+        let policy = FeePolicy {
+            fixed: 0,
+            basis_points: BASIS_POINTS_DENOMINATOR as u16 + 1,
+        };
+        assert_eq!(
+            check_fee_policy(&policy, 200),
+            Err(SubscriptionError::InvalidFee.into())
+        );
+    }
+
+    #[test]
+    fn test_renewal_fee() {
+        assert_eq!(renewal_fee(200), Ok(2));
+        assert_eq!(renewal_fee(u64::MAX), Ok(u64::MAX / 100));
+    }
+}
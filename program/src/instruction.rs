@@ -1,3 +1,5 @@
+use crate::metadata::mpl_token_metadata;
+use crate::state::{BillingInterval, FeePolicy, PlanPolicy, SplitRecipient, MAX_SPLIT_RECIPIENTS};
 use borsh::{BorshDeserialize, BorshSerialize};
 use solana_program::{
     instruction::{AccountMeta, Instruction},
@@ -33,28 +35,43 @@ pub enum SubscriptionInstruction {
     },
 
     /// Wrapper on transfer function. Deposits token into deposit vault.
+    /// The payer also pays the deposit mint's transfer fee, so the vault
+    /// receives `amount` in full. For native SOL, lamports are wrapped into
+    /// the payer's token account to cover what it doesn't hold.
     ///
     /// Accounts expected by this instruction:
     ///
     ///   0. `[writable, signer]` payer
     ///   1. `[writable]` payer token account
     ///   2. `[writable]` deposit vault
-    ///   3. `[]` token program for token transfers
+    ///   3. `[]` deposit mint's token program
+    ///   4. `[]` deposit mint
+    ///   5. `[]` system program
     ///
     Deposit { amount: u64 },
 
     /// Wrapper on transfer function. Withdraws token from deposit vault
-    /// as long as caller is rightful owner.
+    /// as long as caller is rightful owner, i.e. holds a token from the
+    /// subscription's current mint.
+    ///
+    /// Refuses to leave less than the next renewal `amount` in the vault of
+    /// an active subscription unless `cancel` is set, in which case any
+    /// amount may be withdrawn and the subscription is deactivated.
     ///
     /// Accounts expected by this instruction:
     ///
-    ///   0. `[writable, signer]` payer
-    ///   1. `[writable]` payer token account
-    ///   2. `[writable]` deposit vault
-    ///   3. `[]` subscription metadata
-    ///   4. `[]` token program for token transfers
+    ///   0. `[signer]` owner
+    ///   1. `[writable]` (PDA) owner deposit token account
+    ///   2. `[]` (PDA) owner subscription token account
+    ///   3. `[writable]` (PDA) deposit vault
+    ///   4. `[writable]` (PDA) subscription metadata
+    ///   5. `[]` token program for token transfers
     ///
-    Withdraw { amount: u64 },
+    Withdraw {
+        amount: u64,
+        count: u64,
+        cancel: bool,
+    },
 
     /// Renews or deactivates a provided subscription.
     ///
@@ -87,19 +104,68 @@ pub enum SubscriptionInstruction {
     ///
     Renew { count: u64 },
 
-    /// Initiates a new active subscription.
+    /// Initiates a new active subscription to a plan.
     ///
     /// Initializes metadata account, initializes deposit vault,
     /// initializes NFT mint, mints first and only NFT to caller,
-    /// freezes mint, initializes counter (if very first subscription),
-    /// increments counter (if new subscription).
+    /// increments the plan's counter (if new subscription).
     ///
     /// Makes first token transfer to payee and sets subscription to active,
     /// deposits starting amount into deposit vault. Starting amount must be at
-    /// least as great as typical fee in order to compensate callers upon expiration
-    /// without withdrawing rent.
+    /// least as great as the renewal fee in order to compensate callers upon
+    /// expiration without withdrawing rent. Amount, duration and renewal fee
+    /// are copied from the plan, which must not be retired.
     ///
     /// Can be called on previously closed subscriptions, or brand new subscriptions.
+    /// `index` must either be the plan's current count, for a brand new
+    /// subscription, or the index of a closed one. A reused index keeps the
+    /// ownership token metadata of the closed subscription until
+    /// `SyncMetadata` refreshes it.
+    ///
+    /// There is no separate `Counter2` account, the plan's `count` takes its
+    /// place. The ownership token isn't frozen and its mint authority isn't
+    /// revoked: the mint only mints while its supply is zero and `Close`
+    /// burns the token, so there is never more than one. Freezing would keep
+    /// holders from transferring the subscription, and revoking the authority
+    /// would keep a closed index from being reused.
+    ///
+    /// `max_amount_per_period` is the most the user agrees to be charged per
+    /// period, and must cover the plan's current price. Renewals above it
+    /// expire the subscription instead.
+    ///
+    /// The subscription can be given a fixed term: it ends after
+    /// `max_renewals` renewals, or at the first renewal at or after
+    /// `end_time`, whichever comes first. Zero means no limit for either. The
+    /// user is refunded whatever is left in the deposit vault when it ends.
+    ///
+    /// A `start_time` in the future schedules the start instead: no payment
+    /// is made and the subscription stays inactive until the first renewal at
+    /// or after it charges the deposit vault, which must hold the first
+    /// payment and two fees. A free trial then starts at `start_time`. Zero
+    /// or a past time starts now.
+    ///
+    /// If the plan offers a free trial and the user hasn't had one for this
+    /// plan yet, no first payment is made and the first renewal is pushed back
+    /// by the trial periods. The trial record is created to mark it as used.
+    ///
+    /// With `delegated`, renewals pull from the user deposit token account
+    /// instead of the deposit vault, once the user approves the subscription
+    /// as its delegate for enough to cover them. `start_amount` then only
+    /// funds the fee for whoever expires the subscription and may be zero. A
+    /// token account has a single delegate, so approving another one stops
+    /// the renewals.
+    ///
+    /// An optional referrer, who can't be the user, is paid the plan's
+    /// referral commission on renewals. Only plans paying one accept it.
+    ///
+    /// Payments in a deposit mint with a transfer fee are grossed up so every
+    /// recipient receives their full share, the user paying the fee. The
+    /// vault is credited `start_amount` less the fee.
+    ///
+    /// For native SOL plans, the user deposit token account is created if
+    /// needed and topped up from the user's lamports to cover the payment and
+    /// `start_amount`. An account created this way is closed again afterwards
+    /// unless it funds delegated renewals, so the user needs no wrapped SOL.
     ///
     /// Accounts expected by this instruction:
     ///
@@ -109,33 +175,84 @@ pub enum SubscriptionInstruction {
     ///   3. `[]` payee - for ata creation
     ///   4. `[writable]` (PDA) payee deposit token account
     ///   5. `[writable]` (PDA) subscription metadata
-    ///   6. `[writable]` (PDA) subscription counter
-    ///   7. `[writable]` (PDA) subscription ownership token mint
-    ///   8. `[writable]` (PDA) deposit vault
-    ///   9. `[]` (PDA) deposit vault mint
-    ///   10. `[]` system program
-    ///   11. `[]` sysvar rent
-    ///   12. `[]` token program
-    ///   13. `[]` associated token program
+    ///   6. `[writable]` (PDA) plan
+    ///   7. `[writable]` (PDA) trial record
+    ///   8. `[writable]` (PDA) subscription ownership token mint
+    ///   9. `[writable]` (PDA) deposit vault
+    ///   10. `[]` (PDA) deposit vault mint
+    ///   11. `[]` system program
+    ///   12. `[]` sysvar rent
+    ///   13. `[]` token program
+    ///   14. `[]` associated token program
+    ///   15. `[]` deposit mint's token program
+    ///   16.. `[]` split recipient, then `[writable]` (PDA) its deposit token
+    ///        account, for each recipient of the plan's split
+    ///   then `[]` (optional) referrer
     ///
     Initialize2 {
-        payee: Pubkey,
-        amount: u64,
-        duration: i64,
         start_amount: u64,
+        index: u64,
+        max_amount_per_period: u64,
+        max_renewals: u64,
+        end_time: i64,
+        start_time: i64,
+        delegated: bool,
     },
 
     /// Renews or deactivates a provided subscriptions.
     ///
     /// Same cases as previous instruction for renew versus expire.
-    /// If time is up and sufficient funds (`subscription.amount`) are present,
-    /// mark active and transfer funds to caller and payee. If time is up and
-    /// deposit vault has insufficient funds, mark inactive and transfer fee
-    /// to caller. Creates token accounts when necessary.
+    /// If time is up and sufficient funds (`subscription.amount` plus the fee,
+    /// leaving one more fee in the vault) are present, mark active and transfer
+    /// funds to caller and payee. If time is up and deposit vault has
+    /// insufficient funds, mark inactive and transfer fee to caller. Creates
+    /// token accounts when necessary. Needs no accounts owned by the subscriber.
     ///
     /// No longer creates new mint upon renewal. No longer closes accounts or
     /// withdraws rent upon expiry.
     ///
+    /// A subscription with a scheduled start is activated by the first renewal
+    /// at or after its start time, which makes the first payment or starts
+    /// its free trial without paying the caller.
+    ///
+    /// If the subscription is set to cancel at period end, or its fixed term
+    /// is over, it is expired instead of renewed: the caller gets the fee and
+    /// the rest of the deposit vault is refunded to the current holder of the
+    /// subscription token.
+    ///
+    /// If the plan's price is above the subscriber's `max_amount_per_period`,
+    /// the subscription is expired instead, with the fee based on the maximum.
+    ///
+    /// If funds are short within the plan's grace period, the subscription is
+    /// flagged past due and stays active with no fee paid. A renewal in the
+    /// grace period keeps the billing anchor. Once the grace period is over,
+    /// the caller is paid to expire it.
+    ///
+    /// A late renewal follows the plan's catch up policy: every missed period
+    /// is charged at once, missed periods are skipped with a single charge, or
+    /// a single charge starts the next period now. The first two keep the
+    /// billing anchor. Reactivations and scheduled starts always start now.
+    ///
+    /// A delegated subscription pulls the payment and fee from the
+    /// subscriber's funding account as its delegate instead, with nothing
+    /// left behind. It expires like an underfunded one when the delegation or
+    /// the balance falls short, paying the caller from the vault if it can.
+    ///
+    /// A referred subscription pays its referrer's commission out of the
+    /// payee's payment for every commissioned period charged. The referrer's
+    /// accounts are needed for as long as they are owed commission.
+    ///
+    /// Payments are grossed up by the deposit mint's transfer fee, if it has
+    /// one, so every recipient receives their full share. The caller's fee
+    /// and refunds are sent as is.
+    ///
+    /// For native SOL plans, lamports sent straight to the deposit vault or
+    /// funding account count towards the renewal, which syncs them first.
+    /// The owner's refund is unwrapped: the caller pays up to a token
+    /// account's rent of it in lamports and is repaid as much in wrapped SOL,
+    /// the rest goes through a temporary account closed to the owner. The
+    /// caller's fee and payments stay wrapped.
+    ///
     /// Accounts expected by this instruction:
     ///
     ///   0. `[writable, signer]` caller
@@ -143,12 +260,24 @@ pub enum SubscriptionInstruction {
     ///   2. `[]` payee - for ata creation
     ///   3. `[writable]` (PDA) payee deposit token account
     ///   4. `[writable]` (PDA) subscription metadata
-    ///   5. `[writable]` (PDA) deposit vault
-    ///   6. `[]` (PDA) deposit vault mint
-    ///   7. `[]` system program
-    ///   8. `[]` sysvar rent program
-    ///   9. `[]` token program
-    ///   10. `[]` associated token program
+    ///   5. `[]` (PDA) plan
+    ///   6. `[writable]` (PDA) deposit vault
+    ///   7. `[]` (PDA) deposit vault mint
+    ///   8. `[]` system program
+    ///   9. `[]` sysvar rent program
+    ///   10. `[]` deposit mint's token program
+    ///   11. `[]` associated token program
+    ///   12.. `[]` split recipient, then `[writable]` (PDA) its deposit token
+    ///        account, for each recipient of the plan's split
+    ///   then `[writable]` (optional) subscriber funding token account -
+    ///        required for delegated subscriptions
+    ///   then `[]` (optional) referrer - required while owed commission
+    ///   and `[writable]` (optional) (PDA) referrer deposit token account
+    ///   then `[writable]` (optional) owner - required when the subscription
+    ///        ends
+    ///   and `[]` (optional) (PDA) owner subscription token account
+    ///   and `[writable]` (optional) (PDA) owner deposit token account, or the
+    ///        subscription's temporary refund account for native SOL
     ///
     Renew2 {},
 
@@ -157,22 +286,247 @@ pub enum SubscriptionInstruction {
     /// Zeroes out subscription data and withdraws rent. Closes
     /// deposit vault and withdraws all funds. Only callable by
     /// owner. Creates caller's token account to withdraw funds
-    /// if necessary.
+    /// if necessary. Burns the ownership token and closes its
+    /// account so the index can be reused by `Initialize2`. Transfer fees
+    /// withheld in the deposit vault are harvested to the deposit mint first.
+    /// A native SOL deposit vault is closed straight to the user instead,
+    /// unwrapping what's left as lamports.
+    ///
+    /// The ownership token's metadata, if `SyncMetadata` created it, is left
+    /// as it is: the token metadata program never closes it, so its rent stays
+    /// with whoever paid for its creation. It shows the last synced name and
+    /// status until the next holder of a reused index calls `SyncMetadata`.
     ///
     /// Accounts expected by this instruction:
     ///
     ///   0. `[writable, signer]` user
-    ///   1. `[writable] user deposit/withdraw token account
-    ///   2. `[]` user subscription ownership token account
-    ///   3. `[writable]` subscription metadata
-    ///   4. `[writable]` deposit vault
-    ///   5. `[]` deposit mint - for ata creation
-    ///   6. `[]` system program
-    ///   7. `[]` sysvar rent program
-    ///   8. `[]` token program
-    ///   9. `[]` associated token program
+    ///   1. `[writable]` user deposit/withdraw token account
+    ///   2. `[writable]` user subscription ownership token account
+    ///   3. `[writable]` (PDA) subscription ownership token mint
+    ///   4. `[writable]` subscription metadata
+    ///   5. `[writable]` deposit vault
+    ///   6. `[]` deposit mint - for ata creation, `[writable]` if it has a
+    ///        transfer fee config, for withheld fees
+    ///   7. `[]` system program
+    ///   8. `[]` sysvar rent program
+    ///   9. `[]` token program
+    ///   10. `[]` associated token program
+    ///   11. `[]` deposit mint's token program
     ///
     Close {},
+
+    /// Creates a plan owned by the payee that subscriptions are created from.
+    ///
+    /// The plan's address is derived from the payee and a payee chosen `id`,
+    /// so a payee's plans can be found by walking their ids. `policy` sets the
+    /// rules subscriptions of the plan follow. `name` and `uri` are limited to
+    /// 32 and 128 bytes respectively.
+    ///
+    /// `interval` bills every `duration` seconds, or by calendar month or
+    /// year on the day of month each subscription started, clamped to the end
+    /// of shorter months. Calendar plans still use `duration` as the nominal
+    /// period length for proration, notice and grace periods, so it must be
+    /// at least 28 days for months and 365 days for years.
+    ///
+    /// `splits` shares every payment between up to three recipients by basis
+    /// points adding up to 10,000, with rounding dust going to the first
+    /// recipient. Unused recipients are left empty at the end, and without
+    /// any the payee is paid in full. Payments then need each recipient
+    /// followed by its deposit token account, in the plan's order.
+    ///
+    /// A policy with `referral_basis_points` pays that share of renewals of
+    /// referred subscriptions to their referrer, out of the payee's payment,
+    /// for the first `referral_renewals` renewals or forever if 0.
+    ///
+    /// The deposit mint can belong to spl-token or spl-token-2022. Mints
+    /// with a transfer fee are accepted, the subscriber paying the fee, but
+    /// not non-transferable mints, mints with a transfer hook or mints whose
+    /// token accounts start frozen. Plans in the native mint are paid in
+    /// SOL, wrapped and unwrapped by the program as needed.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[writable, signer]` payee
+    ///   1. `[writable]` (PDA) plan
+    ///   2. `[]` deposit mint
+    ///   3. `[]` system program
+    ///
+    CreatePlan {
+        id: u64,
+        amount: u64,
+        duration: i64,
+        interval: BillingInterval,
+        fee: FeePolicy,
+        policy: PlanPolicy,
+        splits: [SplitRecipient; MAX_SPLIT_RECIPIENTS],
+        name: String,
+        uri: String,
+    },
+
+    /// Updates a plan's fee, policy and metadata. Only callable by the payee.
+    /// Existing subscriptions keep the fee they were created with, but follow
+    /// the new policy.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[signer]` payee
+    ///   1. `[writable]` (PDA) plan
+    ///
+    UpdatePlan {
+        fee: FeePolicy,
+        policy: PlanPolicy,
+        name: String,
+        uri: String,
+    },
+
+    /// Retires a plan so no new subscriptions can be created from it.
+    /// Existing subscriptions keep renewing. Only callable by the payee.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[signer]` payee
+    ///   1. `[writable]` (PDA) plan
+    ///
+    RetirePlan {},
+
+    /// Pauses a subscription. While paused it cannot be renewed, but keeps
+    /// its index, ownership token and deposit. Only callable by the holder of
+    /// the subscription token, only if the plan allows pausing, and not once
+    /// a renewal is due or past due.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[signer]` owner
+    ///   1. `[]` owner subscription token account
+    ///   2. `[writable]` (PDA) subscription metadata
+    ///   3. `[]` (PDA) plan
+    ///
+    Pause {},
+
+    /// Resumes a paused subscription. The time left in the period when it was
+    /// paused is carried over, so the next renewal is pushed back by the
+    /// length of the pause.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[signer]` owner
+    ///   1. `[]` owner subscription token account
+    ///   2. `[writable]` (PDA) subscription metadata
+    ///
+    Resume {},
+
+    /// Sets or clears the subscription's cancel at period end flag. Only
+    /// callable by the holder of the subscription token. While set, the next
+    /// renewal expires the subscription and refunds the deposit vault to
+    /// whoever holds the subscription token then, so the rest of the paid
+    /// period isn't lost.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[signer]` owner
+    ///   1. `[]` owner subscription token account
+    ///   2. `[writable]` (PDA) subscription metadata
+    ///
+    SetCancelAtPeriodEnd { cancel_at_period_end: bool },
+
+    /// Moves a subscription to another plan of the same payee and deposit
+    /// mint, keeping its address, index and ownership token. Only callable by
+    /// the holder of the subscription token.
+    ///
+    /// The new plan's period starts immediately. What's left of the current
+    /// period is credited against the new price, and the rest is paid from
+    /// the deposit vault, which must keep one renewal fee behind, or from the
+    /// subscriber's funding account if the subscription is delegated. Credit
+    /// past the new price extends the new period instead. Nothing is credited
+    /// for a free trial. Refused while periods prepaid with `Extend` are still
+    /// ahead of the current one, since only a single period can be credited.
+    /// `max_amount_per_period` replaces the owner's maximum and
+    /// must cover the new plan's price. The charge is grossed up by the
+    /// deposit mint's transfer fee.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[signer]` owner
+    ///   1. `[]` owner subscription token account
+    ///   2. `[writable]` (PDA) subscription metadata
+    ///   3. `[]` (PDA) new plan
+    ///   4. `[writable]` (PDA) deposit vault
+    ///   5. `[writable]` (PDA) payee deposit token account
+    ///   6. `[]` deposit mint's token program
+    ///   7. `[]` deposit mint
+    ///   8.. `[]` split recipient, then `[writable]` (PDA) its deposit token
+    ///        account, for each recipient of the new plan's split
+    ///   then `[writable]` (optional) subscriber funding token account -
+    ///        required for delegated subscriptions
+    ///
+    ChangePlan { max_amount_per_period: u64 },
+
+    /// Schedules a new price on a plan, taking effect at `effective_time`,
+    /// which must be at least one period away. Renewals from then on charge
+    /// the new amount, and subscriptions record the pending price when they
+    /// next renew. Replaces a change that isn't in effect yet. Only callable
+    /// by the payee.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[signer]` payee
+    ///   1. `[writable]` (PDA) plan
+    ///
+    SchedulePriceChange { amount: u64, effective_time: i64 },
+
+    /// Prepays `periods` periods of an active, unpaused subscription straight
+    /// to the payee and pushes its next renewal back by as many periods. Charged at
+    /// the plan's current price, which must be within the subscriber's
+    /// maximum, less the best prepay discount the plan offers for that many
+    /// periods. Prepaid periods count as renewals towards a fixed term and
+    /// must all start within it, and the referrer earns their commission on
+    /// those within the referral's renewals. Callable by anyone paying, who
+    /// also pays the deposit mint's transfer fee.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[signer]` payer
+    ///   1. `[writable]` (PDA) payer deposit token account
+    ///   2. `[writable]` (PDA) subscription metadata
+    ///   3. `[]` (PDA) plan
+    ///   4. `[writable]` (PDA) payee deposit token account
+    ///   5. `[]` deposit mint's token program
+    ///   6. `[]` deposit mint
+    ///   7.. `[]` split recipient, then `[writable]` (PDA) its deposit token
+    ///        account, for each recipient of the plan's split
+    ///   then `[]` (optional) referrer - required while owed commission
+    ///   and `[writable]` (optional) (PDA) referrer deposit token account
+    ///
+    Extend { periods: u32 },
+
+    /// Creates or refreshes the Metaplex token metadata of a subscription's
+    /// ownership token, so wallets show it by name. Only callable by the
+    /// holder of the subscription token, the payer funds the metadata account
+    /// when it's created.
+    ///
+    /// The name is the current plan's name and the payee is listed as an
+    /// unverified creator. The URI is the plan's URI with the billing period
+    /// and the subscription's status appended as `period` and `status` query
+    /// parameters, so calling this again after a renewal, pause or expiry
+    /// keeps the status current. The subscription is the update authority.
+    ///
+    /// No master edition is created: it would take over the mint authority,
+    /// and `Initialize2` could then never mint to a reused index.
+    ///
+    /// Accounts expected by this instruction:
+    ///
+    ///   0. `[writable, signer]` payer
+    ///   1. `[signer]` owner
+    ///   2. `[]` owner subscription token account
+    ///   3. `[]` (PDA) subscription metadata
+    ///   4. `[]` (PDA) plan
+    ///   5. `[]` (PDA) subscription ownership token mint
+    ///   6. `[writable]` (PDA) ownership token metadata
+    ///   7. `[]` system program
+    ///   8. `[]` sysvar rent program
+    ///   9. `[]` token metadata program
+    ///
+    SyncMetadata {},
 }
 
 // INSTRUCTION WRAPPERS
@@ -214,23 +568,59 @@ pub fn initialize(
     }
 }
 
+/// Creates a `Deposit` instruction
+pub fn deposit(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    payer_token_account: &Pubkey,
+    deposit_vault: &Pubkey,
+    deposit_mint: &Pubkey,
+    deposit_token_program: &Pubkey,
+    amount: u64,
+) -> Instruction {
+    let data = SubscriptionInstruction::Deposit { amount };
+
+    let accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new(*payer_token_account, false),
+        AccountMeta::new(*deposit_vault, false),
+        AccountMeta::new_readonly(*deposit_token_program, false),
+        AccountMeta::new_readonly(*deposit_mint, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
 /// Creates a `Withdraw` instruction.
 pub fn withdraw(
     program_pubkey: &Pubkey,
-    payer_pubkey: &Pubkey,
+    owner_pubkey: &Pubkey,
     destination_pubkey: &Pubkey,
+    owner_subscription_token_pubkey: &Pubkey,
     vault_pubkey: &Pubkey,
     subscription_pubkey: &Pubkey,
     token_program_id: &Pubkey,
     amount: u64,
+    count: u64,
+    cancel: bool,
 ) -> Result<Instruction, ProgramError> {
-    let data = SubscriptionInstruction::Withdraw { amount };
+    let data = SubscriptionInstruction::Withdraw {
+        amount,
+        count,
+        cancel,
+    };
 
     let accounts = vec![
-        AccountMeta::new(*payer_pubkey, true),
+        AccountMeta::new_readonly(*owner_pubkey, true),
         AccountMeta::new(*destination_pubkey, false),
+        AccountMeta::new_readonly(*owner_subscription_token_pubkey, false),
         AccountMeta::new(*vault_pubkey, false),
-        AccountMeta::new_readonly(*subscription_pubkey, false),
+        AccountMeta::new(*subscription_pubkey, false),
         AccountMeta::new_readonly(*token_program_id, false),
     ];
 
@@ -241,7 +631,52 @@ pub fn withdraw(
     })
 }
 
-/// Creates an `Initialize2` instruction
+/// Creates a `Renew` instruction
+pub fn renew(
+    program_pubkey: &Pubkey,
+    caller_pubkey: &Pubkey,
+    subscription_pubkey: &Pubkey,
+    deposit_mint_pubkey: &Pubkey,
+    deposit_vault_pubkey: &Pubkey,
+    payee_pubkey: &Pubkey,
+    payee_vault_pubkey: &Pubkey,
+    caller_vault_pubkey: &Pubkey,
+    new_mint_pubkey: &Pubkey,
+    payer_new_vault_pubkey: &Pubkey,
+    payer_old_vault_pubkey: &Pubkey,
+    payer_pubkey: &Pubkey,
+    count: u64,
+) -> Instruction {
+    let data = SubscriptionInstruction::Renew { count };
+
+    let accounts = vec![
+        AccountMeta::new(*caller_pubkey, true),
+        AccountMeta::new(*subscription_pubkey, false),
+        AccountMeta::new_readonly(*deposit_mint_pubkey, false),
+        AccountMeta::new(*deposit_vault_pubkey, false),
+        AccountMeta::new_readonly(*payee_pubkey, false),
+        AccountMeta::new(*payee_vault_pubkey, false),
+        AccountMeta::new(*caller_vault_pubkey, false),
+        AccountMeta::new(*new_mint_pubkey, false),
+        AccountMeta::new(*payer_new_vault_pubkey, false),
+        AccountMeta::new_readonly(*payer_old_vault_pubkey, false),
+        AccountMeta::new_readonly(*payer_pubkey, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(spl_token::id(), false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+    ];
+
+    Instruction {
+        program_id: *program_pubkey,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates an `Initialize2` instruction. `split_accounts` is each recipient
+/// of the plan's split and their deposit token account, in the plan's order.
+/// `referrer` is who referred the user, if anyone.
 pub fn initialize2(
     program_id: &Pubkey,
     user: &Pubkey,
@@ -250,29 +685,41 @@ pub fn initialize2(
     payee: &Pubkey,
     payee_deposit_account: &Pubkey,
     subscription: &Pubkey,
-    subscription_counter: &Pubkey,
+    plan: &Pubkey,
+    trial_record: &Pubkey,
     subscription_mint: &Pubkey,
     deposit_vault: &Pubkey,
     deposit_mint: &Pubkey,
-    amount: u64,
-    duration: i64,
+    deposit_token_program: &Pubkey,
     start_amount: u64,
+    index: u64,
+    max_amount_per_period: u64,
+    max_renewals: u64,
+    end_time: i64,
+    start_time: i64,
+    delegated: bool,
+    split_accounts: &[(Pubkey, Pubkey)],
+    referrer: Option<&Pubkey>,
 ) -> Instruction {
     let data = SubscriptionInstruction::Initialize2 {
-        payee: *payee,
-        amount,
-        duration,
         start_amount,
+        index,
+        max_amount_per_period,
+        max_renewals,
+        end_time,
+        start_time,
+        delegated,
     };
 
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new(*user, true),
         AccountMeta::new(*user_deposit_account, false),
         AccountMeta::new(*user_subscription_token_account, false),
         AccountMeta::new_readonly(*payee, false),
         AccountMeta::new(*payee_deposit_account, false),
         AccountMeta::new(*subscription, false),
-        AccountMeta::new(*subscription_counter, false),
+        AccountMeta::new(*plan, false),
+        AccountMeta::new(*trial_record, false),
         AccountMeta::new(*subscription_mint, false),
         AccountMeta::new(*deposit_vault, false),
         AccountMeta::new_readonly(*deposit_mint, false),
@@ -280,7 +727,12 @@ pub fn initialize2(
         AccountMeta::new_readonly(sysvar::rent::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        AccountMeta::new_readonly(*deposit_token_program, false),
     ];
+    push_split_accounts(&mut accounts, split_accounts);
+    if let Some(referrer) = referrer {
+        accounts.push(AccountMeta::new_readonly(*referrer, false));
+    }
 
     Instruction {
         program_id: *program_id,
@@ -294,15 +746,23 @@ pub fn initialize2(
 ///   2. `[]` payee - for ata creation
 ///   3. `[writable]` (PDA) payee deposit token account
 ///   4. `[writable]` (PDA) subscription metadata
-///   5. `[writable]` (PDA) deposit vault
-///   6. `[]` (PDA) deposit vault mint
-///   7. `[]` system program
-///   8. `[]` sysvar rent program
-///   9. `[]` token program
-///   10. `[]` associated token program
+///   5. `[]` (PDA) plan
+///   6. `[writable]` (PDA) deposit vault
+///   7. `[]` (PDA) deposit vault mint
+///   8. `[]` system program
+///   9. `[]` sysvar rent program
+///   10. `[]` deposit mint's token program
+///   11. `[]` associated token program
 ///
 
-/// Creates an `Renew2` instruction
+/// Creates an `Renew2` instruction. `split_accounts` is each recipient of the
+/// plan's split and their deposit token account, in the plan's order.
+/// `funding_account` is the subscriber's token account a delegated
+/// subscription pulls from. `referral_accounts` is the referrer and their deposit token account,
+/// needed while they're owed commission. `refund_accounts` is the holder of
+/// the subscription token, their subscription token account and their
+/// deposit token account, needed when the subscription ends. For native SOL
+/// the deposit token account is the `[b"refund", subscription]` PDA instead.
 pub fn renew2(
     program_id: &Pubkey,
     caller: &Pubkey,
@@ -310,24 +770,171 @@ pub fn renew2(
     payee: &Pubkey,
     payee_deposit_account: &Pubkey,
     subscription: &Pubkey,
+    plan: &Pubkey,
     deposit_vault: &Pubkey,
     deposit_mint: &Pubkey,
+    deposit_token_program: &Pubkey,
+    split_accounts: &[(Pubkey, Pubkey)],
+    funding_account: Option<&Pubkey>,
+    referral_accounts: Option<(&Pubkey, &Pubkey)>,
+    refund_accounts: Option<(&Pubkey, &Pubkey, &Pubkey)>,
 ) -> Instruction {
 
     let data = SubscriptionInstruction::Renew2 {};
 
-    let accounts = vec![
+    let mut accounts = vec![
         AccountMeta::new(*caller, true),
         AccountMeta::new(*caller_deposit_account, false),
         AccountMeta::new_readonly(*payee, false),
         AccountMeta::new(*payee_deposit_account, false),
         AccountMeta::new(*subscription, false),
+        AccountMeta::new_readonly(*plan, false),
         AccountMeta::new(*deposit_vault, false),
         AccountMeta::new_readonly(*deposit_mint, false),
         AccountMeta::new_readonly(system_program::id(), false),
         AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(*deposit_token_program, false),
+        AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+    ];
+    push_split_accounts(&mut accounts, split_accounts);
+    if let Some(funding_account) = funding_account {
+        accounts.push(AccountMeta::new(*funding_account, false));
+    }
+    if let Some((referrer, referrer_deposit_account)) = referral_accounts {
+        accounts.push(AccountMeta::new_readonly(*referrer, false));
+        accounts.push(AccountMeta::new(*referrer_deposit_account, false));
+    }
+    if let Some((owner, owner_subscription_token_account, owner_deposit_account)) = refund_accounts
+    {
+        accounts.push(AccountMeta::new(*owner, false));
+        accounts.push(AccountMeta::new_readonly(
+            *owner_subscription_token_account,
+            false,
+        ));
+        accounts.push(AccountMeta::new(*owner_deposit_account, false));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `Close` instruction
+pub fn close(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    user_deposit_account: &Pubkey,
+    user_subscription_token_account: &Pubkey,
+    subscription_mint: &Pubkey,
+    subscription: &Pubkey,
+    deposit_vault: &Pubkey,
+    deposit_mint: &Pubkey,
+    deposit_token_program: &Pubkey,
+) -> Instruction {
+    let data = SubscriptionInstruction::Close {};
+
+    let accounts = vec![
+        AccountMeta::new(*user, true),
+        AccountMeta::new(*user_deposit_account, false),
+        AccountMeta::new(*user_subscription_token_account, false),
+        AccountMeta::new(*subscription_mint, false),
+        AccountMeta::new(*subscription, false),
+        AccountMeta::new(*deposit_vault, false),
+        AccountMeta::new(*deposit_mint, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
         AccountMeta::new_readonly(spl_token::id(), false),
         AccountMeta::new_readonly(spl_associated_token_account::id(), false),
+        AccountMeta::new_readonly(*deposit_token_program, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `CreatePlan` instruction
+pub fn create_plan(
+    program_id: &Pubkey,
+    payee: &Pubkey,
+    plan: &Pubkey,
+    deposit_mint: &Pubkey,
+    id: u64,
+    amount: u64,
+    duration: i64,
+    interval: BillingInterval,
+    fee: FeePolicy,
+    policy: PlanPolicy,
+    splits: [SplitRecipient; MAX_SPLIT_RECIPIENTS],
+    name: String,
+    uri: String,
+) -> Instruction {
+    let data = SubscriptionInstruction::CreatePlan {
+        id,
+        amount,
+        duration,
+        interval,
+        fee,
+        policy,
+        splits,
+        name,
+        uri,
+    };
+
+    let accounts = vec![
+        AccountMeta::new(*payee, true),
+        AccountMeta::new(*plan, false),
+        AccountMeta::new_readonly(*deposit_mint, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates an `UpdatePlan` instruction
+pub fn update_plan(
+    program_id: &Pubkey,
+    payee: &Pubkey,
+    plan: &Pubkey,
+    fee: FeePolicy,
+    policy: PlanPolicy,
+    name: String,
+    uri: String,
+) -> Instruction {
+    let data = SubscriptionInstruction::UpdatePlan {
+        fee,
+        policy,
+        name,
+        uri,
+    };
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*payee, true),
+        AccountMeta::new(*plan, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `RetirePlan` instruction
+pub fn retire_plan(program_id: &Pubkey, payee: &Pubkey, plan: &Pubkey) -> Instruction {
+    let data = SubscriptionInstruction::RetirePlan {};
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*payee, true),
+        AccountMeta::new(*plan, false),
     ];
 
     Instruction {
@@ -336,3 +943,225 @@ pub fn renew2(
         data: data.try_to_vec().unwrap(),
     }
 }
+
+/// Creates a `Pause` instruction
+pub fn pause(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    owner_subscription_token_account: &Pubkey,
+    subscription: &Pubkey,
+    plan: &Pubkey,
+) -> Instruction {
+    let data = SubscriptionInstruction::Pause {};
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(*owner_subscription_token_account, false),
+        AccountMeta::new(*subscription, false),
+        AccountMeta::new_readonly(*plan, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `Resume` instruction
+pub fn resume(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    owner_subscription_token_account: &Pubkey,
+    subscription: &Pubkey,
+) -> Instruction {
+    let data = SubscriptionInstruction::Resume {};
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(*owner_subscription_token_account, false),
+        AccountMeta::new(*subscription, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `SetCancelAtPeriodEnd` instruction
+pub fn set_cancel_at_period_end(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    owner_subscription_token_account: &Pubkey,
+    subscription: &Pubkey,
+    cancel_at_period_end: bool,
+) -> Instruction {
+    let data = SubscriptionInstruction::SetCancelAtPeriodEnd {
+        cancel_at_period_end,
+    };
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(*owner_subscription_token_account, false),
+        AccountMeta::new(*subscription, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `ChangePlan` instruction. `split_accounts` is each recipient of
+/// the new plan's split and their deposit token account, in the plan's order.
+/// `funding_account` is the subscriber's token account a delegated
+/// subscription pulls from.
+pub fn change_plan(
+    program_id: &Pubkey,
+    owner: &Pubkey,
+    owner_subscription_token_account: &Pubkey,
+    subscription: &Pubkey,
+    new_plan: &Pubkey,
+    deposit_vault: &Pubkey,
+    payee_deposit_account: &Pubkey,
+    deposit_mint: &Pubkey,
+    deposit_token_program: &Pubkey,
+    max_amount_per_period: u64,
+    split_accounts: &[(Pubkey, Pubkey)],
+    funding_account: Option<&Pubkey>,
+) -> Instruction {
+    let data = SubscriptionInstruction::ChangePlan {
+        max_amount_per_period,
+    };
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(*owner_subscription_token_account, false),
+        AccountMeta::new(*subscription, false),
+        AccountMeta::new_readonly(*new_plan, false),
+        AccountMeta::new(*deposit_vault, false),
+        AccountMeta::new(*payee_deposit_account, false),
+        AccountMeta::new_readonly(*deposit_token_program, false),
+        AccountMeta::new_readonly(*deposit_mint, false),
+    ];
+    push_split_accounts(&mut accounts, split_accounts);
+    if let Some(funding_account) = funding_account {
+        accounts.push(AccountMeta::new(*funding_account, false));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `SchedulePriceChange` instruction
+pub fn schedule_price_change(
+    program_id: &Pubkey,
+    payee: &Pubkey,
+    plan: &Pubkey,
+    amount: u64,
+    effective_time: i64,
+) -> Instruction {
+    let data = SubscriptionInstruction::SchedulePriceChange {
+        amount,
+        effective_time,
+    };
+
+    let accounts = vec![
+        AccountMeta::new_readonly(*payee, true),
+        AccountMeta::new(*plan, false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates an `Extend` instruction. `split_accounts` is each recipient of the
+/// plan's split and their deposit token account, in the plan's order.
+/// `referral_accounts` is the referrer and their deposit token account,
+/// needed while they're owed commission.
+pub fn extend(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    payer_deposit_account: &Pubkey,
+    subscription: &Pubkey,
+    plan: &Pubkey,
+    payee_deposit_account: &Pubkey,
+    deposit_mint: &Pubkey,
+    deposit_token_program: &Pubkey,
+    periods: u32,
+    split_accounts: &[(Pubkey, Pubkey)],
+    referral_accounts: Option<(&Pubkey, &Pubkey)>,
+) -> Instruction {
+    let data = SubscriptionInstruction::Extend { periods };
+
+    let mut accounts = vec![
+        AccountMeta::new_readonly(*payer, true),
+        AccountMeta::new(*payer_deposit_account, false),
+        AccountMeta::new(*subscription, false),
+        AccountMeta::new_readonly(*plan, false),
+        AccountMeta::new(*payee_deposit_account, false),
+        AccountMeta::new_readonly(*deposit_token_program, false),
+        AccountMeta::new_readonly(*deposit_mint, false),
+    ];
+    push_split_accounts(&mut accounts, split_accounts);
+    if let Some((referrer, referrer_deposit_account)) = referral_accounts {
+        accounts.push(AccountMeta::new_readonly(*referrer, false));
+        accounts.push(AccountMeta::new(*referrer_deposit_account, false));
+    }
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Creates a `SyncMetadata` instruction
+pub fn sync_metadata(
+    program_id: &Pubkey,
+    payer: &Pubkey,
+    owner: &Pubkey,
+    owner_subscription_token_account: &Pubkey,
+    subscription: &Pubkey,
+    plan: &Pubkey,
+    subscription_mint: &Pubkey,
+    token_metadata: &Pubkey,
+) -> Instruction {
+    let data = SubscriptionInstruction::SyncMetadata {};
+
+    let accounts = vec![
+        AccountMeta::new(*payer, true),
+        AccountMeta::new_readonly(*owner, true),
+        AccountMeta::new_readonly(*owner_subscription_token_account, false),
+        AccountMeta::new_readonly(*subscription, false),
+        AccountMeta::new_readonly(*plan, false),
+        AccountMeta::new_readonly(*subscription_mint, false),
+        AccountMeta::new(*token_metadata, false),
+        AccountMeta::new_readonly(system_program::id(), false),
+        AccountMeta::new_readonly(sysvar::rent::id(), false),
+        AccountMeta::new_readonly(mpl_token_metadata::id(), false),
+    ];
+
+    Instruction {
+        program_id: *program_id,
+        accounts,
+        data: data.try_to_vec().unwrap(),
+    }
+}
+
+/// Appends each split recipient and their deposit token account
+fn push_split_accounts(accounts: &mut Vec<AccountMeta>, split_accounts: &[(Pubkey, Pubkey)]) {
+    for (recipient, recipient_deposit_account) in split_accounts {
+        accounts.push(AccountMeta::new_readonly(*recipient, false));
+        accounts.push(AccountMeta::new(*recipient_deposit_account, false));
+    }
+}
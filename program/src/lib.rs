@@ -1,6 +1,11 @@
+pub mod billing;
+pub mod calendar;
 pub mod entrypoint;
 pub mod error;
+pub mod fee;
 pub mod instruction;
+pub mod metadata;
 pub mod processor;
 pub mod state;
+pub mod token;
 pub mod utils;
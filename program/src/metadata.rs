@@ -0,0 +1,348 @@
+use {
+    crate::state::{BillingInterval, Plan, Subscription2},
+    borsh::BorshSerialize,
+    solana_program::{
+        instruction::{AccountMeta, Instruction},
+        pubkey::Pubkey,
+        system_program, sysvar,
+    },
+};
+
+/// The Metaplex token metadata program, which gives the subscription
+/// ownership token a name and URI that wallets display.
+pub mod mpl_token_metadata {
+    solana_program::declare_id!("metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s");
+}
+
+pub const MAX_NAME_LENGTH: usize = 32;
+pub const MAX_SYMBOL_LENGTH: usize = 10;
+pub const MAX_URI_LENGTH: usize = 200;
+
+/// Symbol of every subscription ownership token.
+pub const SYMBOL: &str = "SUB";
+
+// token metadata instructions, by their index in the program's enum
+const UPDATE_METADATA_ACCOUNT_V2: u8 = 15;
+const CREATE_METADATA_ACCOUNT_V3: u8 = 33;
+
+#[derive(BorshSerialize, Debug, Clone, PartialEq)]
+pub struct Creator {
+    pub address: Pubkey,
+    pub verified: bool,
+    pub share: u8,
+}
+
+#[derive(BorshSerialize, Debug, Clone, PartialEq)]
+pub struct Collection {
+    pub verified: bool,
+    pub key: Pubkey,
+}
+
+#[derive(BorshSerialize, Debug, Clone, PartialEq)]
+pub struct Uses {
+    pub use_method: u8,
+    pub remaining: u64,
+    pub total: u64,
+}
+
+/// The token metadata program's `DataV2`.
+#[derive(BorshSerialize, Debug, Clone, PartialEq)]
+pub struct DataV2 {
+    pub name: String,
+    pub symbol: String,
+    pub uri: String,
+    pub seller_fee_basis_points: u16,
+    pub creators: Option<Vec<Creator>>,
+    pub collection: Option<Collection>,
+    pub uses: Option<Uses>,
+}
+
+/// Returns the metadata account of `mint`.
+pub fn find_metadata_address(mint: &Pubkey) -> (Pubkey, u8) {
+    Pubkey::find_program_address(
+        &[
+            b"metadata",
+            mpl_token_metadata::id().as_ref(),
+            mint.as_ref(),
+        ],
+        &mpl_token_metadata::id(),
+    )
+}
+
+/// Returns a plan's fixed size name or uri as a string, without the padding.
+fn from_fixed_bytes(bytes: &[u8]) -> String {
+    let len = bytes
+        .iter()
+        .rposition(|byte| *byte != 0)
+        .map_or(0, |i| i + 1);
+    String::from_utf8_lossy(&bytes[..len]).into_owned()
+}
+
+/// Returns the status of a subscription shown in its ownership token's URI.
+pub fn subscription_status(subscription: &Subscription2) -> &'static str {
+    if !subscription.active && subscription.start_time != 0 {
+        "scheduled"
+    } else if !subscription.active {
+        "expired"
+    } else if subscription.paused {
+        "paused"
+    } else if subscription.past_due {
+        "past_due"
+    } else if subscription.trial {
+        "trial"
+    } else {
+        "active"
+    }
+}
+
+/// Returns the metadata of a subscription's ownership token: the plan's name,
+/// the payee as unverified creator, and the plan's URI with the billing period
+/// and the subscription's status appended as query parameters.
+pub fn subscription_metadata(subscription: &Subscription2, plan: &Plan) -> DataV2 {
+    let period = match plan.interval {
+        BillingInterval::Duration => format!("{}s", plan.duration),
+        BillingInterval::Month => String::from("month"),
+        BillingInterval::Year => String::from("year"),
+    };
+    let uri = from_fixed_bytes(&plan.uri);
+    let separator = if uri.contains('?') { '&' } else { '?' };
+
+    DataV2 {
+        name: from_fixed_bytes(&plan.name),
+        symbol: String::from(SYMBOL),
+        uri: format!(
+            "{}{}period={}&status={}",
+            uri,
+            separator,
+            period,
+            subscription_status(subscription)
+        ),
+        seller_fee_basis_points: 0,
+        creators: Some(vec![Creator {
+            address: plan.payee,
+            verified: false,
+            share: 100,
+        }]),
+        collection: None,
+        uses: None,
+    }
+}
+
+/// Creates a `CreateMetadataAccountV3` instruction for `mint`, mutable by
+/// `update_authority` and not a collection.
+pub fn create_metadata_account(
+    metadata: &Pubkey,
+    mint: &Pubkey,
+    mint_authority: &Pubkey,
+    payer: &Pubkey,
+    update_authority: &Pubkey,
+    data: DataV2,
+) -> Instruction {
+    let mut instruction_data = vec![CREATE_METADATA_ACCOUNT_V3];
+    // data, is_mutable, then collection_details
+    (data, true, None::<u8>)
+        .serialize(&mut instruction_data)
+        .unwrap();
+
+    Instruction {
+        program_id: mpl_token_metadata::id(),
+        accounts: vec![
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(*mint_authority, true),
+            AccountMeta::new(*payer, true),
+            AccountMeta::new_readonly(*update_authority, true),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: instruction_data,
+    }
+}
+
+/// Creates an `UpdateMetadataAccountV2` instruction replacing the data of
+/// `metadata`.
+pub fn update_metadata_account(
+    metadata: &Pubkey,
+    update_authority: &Pubkey,
+    data: DataV2,
+) -> Instruction {
+    let mut instruction_data = vec![UPDATE_METADATA_ACCOUNT_V2];
+    // data, then update_authority, primary_sale_happened and is_mutable left
+    // as they are
+    (Some(data), None::<Pubkey>, None::<bool>, None::<bool>)
+        .serialize(&mut instruction_data)
+        .unwrap();
+
+    Instruction {
+        program_id: mpl_token_metadata::id(),
+        accounts: vec![
+            AccountMeta::new(*metadata, false),
+            AccountMeta::new_readonly(*update_authority, true),
+        ],
+        data: instruction_data,
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use {super::*, crate::utils::to_fixed_bytes, borsh::BorshDeserialize};
+
+    fn test_plan(interval: BillingInterval, uri: &str) -> Plan {
+        let data = vec![0; 1024];
+        let mut plan = Plan::deserialize(&mut &data[..]).unwrap();
+        plan.payee = Pubkey::new_unique();
+        plan.duration = 3_600;
+        plan.interval = interval;
+        plan.name = to_fixed_bytes("Pro").unwrap();
+        plan.uri = to_fixed_bytes(uri).unwrap();
+        plan
+    }
+
+    fn subscription() -> Subscription2 {
+        let data = vec![0; 1024];
+        let mut subscription = Subscription2::deserialize(&mut &data[..]).unwrap();
+        subscription.active = true;
+        subscription
+    }
+
+    #[test]
+    fn test_find_addresses() {
+        let mint = Pubkey::new_unique();
+        let (metadata, bump) = find_metadata_address(&mint);
+        assert_eq!(
+            Pubkey::create_program_address(
+                &[
+                    b"metadata",
+                    mpl_token_metadata::id().as_ref(),
+                    mint.as_ref(),
+                    &[bump],
+                ],
+                &mpl_token_metadata::id(),
+            ),
+            Ok(metadata)
+        );
+        assert_ne!(find_metadata_address(&Pubkey::new_unique()).0, metadata);
+    }
+
+    #[test]
+    fn test_from_fixed_bytes() {
+        assert_eq!(from_fixed_bytes(&[0; 32]), "");
+        assert_eq!(
+            from_fixed_bytes(&to_fixed_bytes::<32>("Pro").unwrap()),
+            "Pro"
+        );
+        assert_eq!(from_fixed_bytes(&[b'n'; 32]), "n".repeat(32));
+        // only trailing padding is dropped
+        assert_eq!(from_fixed_bytes(b"a\0b\0\0"), "a\0b");
+    }
+
+    #[test]
+    fn test_subscription_status() {
+        let mut subscription = subscription();
+        assert_eq!(subscription_status(&subscription), "active");
+        subscription.trial = true;
+        assert_eq!(subscription_status(&subscription), "trial");
+        subscription.past_due = true;
+        assert_eq!(subscription_status(&subscription), "past_due");
+        subscription.paused = true;
+        assert_eq!(subscription_status(&subscription), "paused");
+        subscription.active = false;
+        assert_eq!(subscription_status(&subscription), "expired");
+        subscription.start_time = 1;
+        assert_eq!(subscription_status(&subscription), "scheduled");
+    }
+
+    #[test]
+    fn test_subscription_metadata() {
+        let plan = test_plan(BillingInterval::Duration, "https://example.com/pro.json");
+        let data = subscription_metadata(&subscription(), &plan);
+        assert_eq!(data.name, "Pro");
+        assert_eq!(data.symbol, SYMBOL);
+        assert_eq!(
+            data.uri,
+            "https://example.com/pro.json?period=3600s&status=active"
+        );
+        assert_eq!(
+            data.creators,
+            Some(vec![Creator {
+                address: plan.payee,
+                verified: false,
+                share: 100,
+            }])
+        );
+
+        let plan = test_plan(BillingInterval::Month, "https://example.com/pro?v=2");
+        assert_eq!(
+            subscription_metadata(&subscription(), &plan).uri,
+            "https://example.com/pro?v=2&period=month&status=active"
+        );
+        let plan = test_plan(BillingInterval::Year, "");
+        assert_eq!(
+            subscription_metadata(&subscription(), &plan).uri,
+            "?period=year&status=active"
+        );
+    }
+
+    #[test]
+    fn test_expired_subscription_metadata() {
+        let plan = test_plan(BillingInterval::Month, "https://example.com/pro.json");
+        let mut subscription = subscription();
+        let active = subscription_metadata(&subscription, &plan);
+        subscription.active = false;
+        let expired = subscription_metadata(&subscription, &plan);
+
+        // only the status changes
+        assert_eq!(active.name, "Pro");
+        assert_eq!(expired.name, active.name);
+        assert_eq!(
+            active.uri,
+            "https://example.com/pro.json?period=month&status=active"
+        );
+        assert_eq!(
+            expired.uri,
+            "https://example.com/pro.json?period=month&status=expired"
+        );
+        assert_eq!(expired.creators, active.creators);
+    }
+
+    #[test]
+    fn test_metadata_fits_limits() {
+        let mut plan = test_plan(BillingInterval::Duration, &"u".repeat(128));
+        plan.name = [b'n'; 32];
+        plan.duration = i64::MIN;
+        let mut subscription = subscription();
+        subscription.active = false;
+        subscription.start_time = 1;
+        let data = subscription_metadata(&subscription, &plan);
+        assert!(data.name.len() <= MAX_NAME_LENGTH);
+        assert!(data.symbol.len() <= MAX_SYMBOL_LENGTH);
+        assert!(data.uri.len() <= MAX_URI_LENGTH);
+    }
+
+    #[test]
+    fn test_instruction_data() {
+        let data = DataV2 {
+            name: String::from("a"),
+            symbol: String::from("b"),
+            uri: String::from("c"),
+            seller_fee_basis_points: 0,
+            creators: None,
+            collection: None,
+            uses: None,
+        };
+        let fields = [
+            1, 0, 0, 0, b'a', 1, 0, 0, 0, b'b', 1, 0, 0, 0, b'c', 0, 0, 0, 0, 0,
+        ];
+        let key = Pubkey::new_unique();
+
+        let instruction = create_metadata_account(&key, &key, &key, &key, &key, data.clone());
+        assert_eq!(instruction.data[0], CREATE_METADATA_ACCOUNT_V3);
+        assert_eq!(instruction.data[1..fields.len() + 1], fields);
+        assert_eq!(instruction.data[fields.len() + 1..], [1, 0]);
+
+        let instruction = update_metadata_account(&key, &key, data);
+        assert_eq!(instruction.data[..2], [UPDATE_METADATA_ACCOUNT_V2, 1]);
+        assert_eq!(instruction.data[2..fields.len() + 2], fields);
+        assert_eq!(instruction.data[fields.len() + 2..], [0, 0, 0]);
+    }
+}
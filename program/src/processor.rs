@@ -13,6 +13,17 @@ pub mod deposit;
 pub mod initialize2;
 pub mod renew2;
 pub mod close;
+pub mod withdraw;
+pub mod create_plan;
+pub mod update_plan;
+pub mod retire_plan;
+pub mod pause;
+pub mod resume;
+pub mod set_cancel_at_period_end;
+pub mod change_plan;
+pub mod schedule_price_change;
+pub mod extend;
+pub mod sync_metadata;
 
 use spl_token::*;
 
@@ -44,21 +55,48 @@ impl Processor {
                 msg!("amount: {}", amount);
                 deposit::process_deposit(accounts, amount)?;
             }
-            SubscriptionInstruction::Withdraw { amount } => {
+            SubscriptionInstruction::Withdraw {
+                amount,
+                count,
+                cancel,
+            } => {
                 msg!("Instruction: Withdraw");
                 msg!("amount: {}", amount);
+                msg!("cancel: {}", cancel);
+                withdraw::process_withdraw(program_id, accounts, amount, count, cancel)?;
             }
             SubscriptionInstruction::Renew { count } => {
                 msg!("Instruction: Renew");
                 renew::process_renew(program_id, accounts, count)?;
             }
-            SubscriptionInstruction::Initialize2 { payee, amount, duration, start_amount} => {
+            SubscriptionInstruction::Initialize2 {
+                start_amount,
+                index,
+                max_amount_per_period,
+                max_renewals,
+                end_time,
+                start_time,
+                delegated,
+            } => {
                 msg!("Instruction: Initialize2");
-                msg!("payee: {}", payee);
-                msg!("amount: {}", amount);
-                msg!("duration: {}", duration);
                 msg!("start_amount: {}", start_amount);
-                initialize2::process_initialize2(program_id, accounts, &payee, amount, duration, start_amount)?;
+                msg!("index: {}", index);
+                msg!("max_amount_per_period: {}", max_amount_per_period);
+                msg!("max_renewals: {}", max_renewals);
+                msg!("end_time: {}", end_time);
+                msg!("start_time: {}", start_time);
+                msg!("delegated: {}", delegated);
+                initialize2::process_initialize2(
+                    program_id,
+                    accounts,
+                    start_amount,
+                    index,
+                    max_amount_per_period,
+                    max_renewals,
+                    end_time,
+                    start_time,
+                    delegated,
+                )?;
             }
             SubscriptionInstruction::Renew2 {} => {
                 msg!("Instruction: Renew2");
@@ -68,6 +106,96 @@ impl Processor {
                 msg!("Instruction: Close");
                 close::process_close(program_id, accounts)?;
             }
+            SubscriptionInstruction::CreatePlan {
+                id,
+                amount,
+                duration,
+                interval,
+                fee,
+                policy,
+                splits,
+                name,
+                uri,
+            } => {
+                msg!("Instruction: CreatePlan");
+                msg!("id: {}", id);
+                msg!("amount: {}", amount);
+                msg!("duration: {}", duration);
+                msg!("interval: {:?}", interval);
+                msg!("fee: {:?}", fee);
+                msg!("policy: {:?}", policy);
+                msg!("splits: {:?}", splits);
+                create_plan::process_create_plan(
+                    program_id, accounts, id, amount, duration, interval, &fee, &policy, &splits,
+                    &name, &uri,
+                )?;
+            }
+            SubscriptionInstruction::UpdatePlan {
+                fee,
+                policy,
+                name,
+                uri,
+            } => {
+                msg!("Instruction: UpdatePlan");
+                msg!("fee: {:?}", fee);
+                msg!("policy: {:?}", policy);
+                update_plan::process_update_plan(
+                    program_id, accounts, &fee, &policy, &name, &uri,
+                )?;
+            }
+            SubscriptionInstruction::RetirePlan {} => {
+                msg!("Instruction: RetirePlan");
+                retire_plan::process_retire_plan(program_id, accounts)?;
+            }
+            SubscriptionInstruction::Pause {} => {
+                msg!("Instruction: Pause");
+                pause::process_pause(program_id, accounts)?;
+            }
+            SubscriptionInstruction::Resume {} => {
+                msg!("Instruction: Resume");
+                resume::process_resume(program_id, accounts)?;
+            }
+            SubscriptionInstruction::SetCancelAtPeriodEnd {
+                cancel_at_period_end,
+            } => {
+                msg!("Instruction: SetCancelAtPeriodEnd");
+                msg!("cancel_at_period_end: {}", cancel_at_period_end);
+                set_cancel_at_period_end::process_set_cancel_at_period_end(
+                    program_id,
+                    accounts,
+                    cancel_at_period_end,
+                )?;
+            }
+            SubscriptionInstruction::ChangePlan {
+                max_amount_per_period,
+            } => {
+                msg!("Instruction: ChangePlan");
+                msg!("max_amount_per_period: {}", max_amount_per_period);
+                change_plan::process_change_plan(program_id, accounts, max_amount_per_period)?;
+            }
+            SubscriptionInstruction::SchedulePriceChange {
+                amount,
+                effective_time,
+            } => {
+                msg!("Instruction: SchedulePriceChange");
+                msg!("amount: {}", amount);
+                msg!("effective_time: {}", effective_time);
+                schedule_price_change::process_schedule_price_change(
+                    program_id,
+                    accounts,
+                    amount,
+                    effective_time,
+                )?;
+            }
+            SubscriptionInstruction::Extend { periods } => {
+                msg!("Instruction: Extend");
+                msg!("periods: {}", periods);
+                extend::process_extend(program_id, accounts, periods)?;
+            }
+            SubscriptionInstruction::SyncMetadata {} => {
+                msg!("Instruction: SyncMetadata");
+                sync_metadata::process_sync_metadata(program_id, accounts)?;
+            }
         }
         
         Ok(())
@@ -0,0 +1,331 @@
+use {
+    crate::{
+        billing::{
+            advance_periods, credit_to_time, pending_price, plan_amount, prorate, retreat_periods,
+            split_amount, split_recipients,
+        },
+        calendar::day_of_month,
+        error::SubscriptionError,
+        fee::crank_fee,
+        state::{Plan, Subscription2},
+        token::{
+            check_token_program, delegated_balance, mint_decimals, transfer_checked, TransferFee,
+        },
+        utils::{
+            check_ata_initialized, check_plan_address, check_signer, check_split_accounts,
+            check_subscription_address, check_writable,
+        },
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        clock::Clock,
+        entrypoint::ProgramResult,
+        msg,
+        program::invoke_signed,
+        program_error::ProgramError,
+        program_pack::Pack,
+        pubkey::Pubkey,
+        sysvar::Sysvar,
+    },
+    spl_token::{error::TokenError, state::Account as TokenAccount},
+};
+
+pub fn process_change_plan(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    max_amount_per_period: u64,
+) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let owner_ai = next_account_info(accounts_iter)?;
+    let owner_subscription_token_ai = next_account_info(accounts_iter)?;
+    let subscription_ai = next_account_info(accounts_iter)?;
+    let new_plan_ai = next_account_info(accounts_iter)?;
+    let deposit_vault_ai = next_account_info(accounts_iter)?;
+    let payee_deposit_ai = next_account_info(accounts_iter)?;
+
+    let token_program_ai = next_account_info(accounts_iter)?;
+    let deposit_mint_ai = next_account_info(accounts_iter)?;
+
+    // the new plan's split recipients, then the subscriber's funding account
+    // if the subscription is delegated
+    let remaining_ais = accounts_iter.as_slice();
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(owner_ai)?;
+    check_writable(subscription_ai)?;
+    check_writable(deposit_vault_ai)?;
+    check_writable(payee_deposit_ai)?;
+
+    // PDAs
+    let mut subscription = Subscription2::try_from_slice(&subscription_ai.try_borrow_data()?)?;
+    check_subscription_address(
+        subscription_ai,
+        &subscription.origin_plan,
+        subscription.index,
+        Some(subscription.bump),
+        program_id,
+    )?;
+    let plan_key = subscription.origin_plan;
+    let subscription_seeds = &[
+        b"subscription_metadata",
+        plan_key.as_ref(),
+        &subscription.index.to_le_bytes(),
+        &[subscription.bump],
+    ];
+
+    let new_plan = Plan::try_from_slice(&new_plan_ai.try_borrow_data()?)?;
+    check_plan_address(
+        new_plan_ai,
+        &new_plan.payee,
+        new_plan.id,
+        Some(new_plan.bump),
+        program_id,
+    )?;
+    if *new_plan_ai.key == subscription.plan {
+        msg!("Already on plan: {}", new_plan_ai.key);
+        return Err(ProgramError::InvalidArgument);
+    }
+    if !new_plan.active {
+        msg!("Plan is retired: {}", new_plan_ai.key);
+        return Err(SubscriptionError::PlanRetired.into());
+    }
+
+    // payee
+    if new_plan.payee != subscription.payee {
+        msg!(
+            "Invalid payee:\tExpected: {}\tGot: {}",
+            subscription.payee,
+            new_plan.payee
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // deposit mint, the vault can only hold one
+    if new_plan.deposit_mint != subscription.deposit_mint {
+        return Err(TokenError::MintMismatch.into());
+    }
+    if *deposit_mint_ai.key != subscription.deposit_mint {
+        msg!("Invalid deposit mint: {}", deposit_mint_ai.key);
+        return Err(TokenError::MintMismatch.into());
+    }
+    check_token_program(token_program_ai, deposit_mint_ai)?;
+
+    // token accounts
+    if *deposit_vault_ai.key != subscription.deposit_vault {
+        msg!("Invalid deposit vault: {}", deposit_vault_ai.key);
+        return Err(ProgramError::InvalidArgument);
+    }
+    check_ata_initialized(
+        deposit_vault_ai,
+        subscription_ai.key,
+        &subscription.deposit_mint,
+        token_program_ai.key,
+    )?;
+    check_ata_initialized(
+        payee_deposit_ai,
+        &subscription.payee,
+        &subscription.deposit_mint,
+        token_program_ai.key,
+    )?;
+    let recipients = split_recipients(&new_plan.splits);
+    let recipient_ais = check_split_accounts(
+        recipients,
+        remaining_ais,
+        &subscription.deposit_mint,
+        token_program_ai.key,
+    )?;
+    for (recipient_ai, recipient_deposit_ai) in recipient_ais.iter() {
+        check_ata_initialized(
+            recipient_deposit_ai,
+            recipient_ai.key,
+            &subscription.deposit_mint,
+            token_program_ai.key,
+        )?;
+    }
+    let optional_ais = &mut remaining_ais[2 * recipients.len()..].iter();
+    let funding_ai = if subscription.funding_account != Pubkey::default() {
+        let funding_ai = next_account_info(optional_ais)?;
+        if *funding_ai.key != subscription.funding_account {
+            msg!(
+                "Invalid funding account:\tExpected: {}\tGot: {}",
+                subscription.funding_account,
+                funding_ai.key
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+        check_writable(funding_ai)?;
+        Some(funding_ai)
+    } else {
+        None
+    };
+
+    // ownership is proven by holding the subscription token
+    check_ata_initialized(
+        owner_subscription_token_ai,
+        owner_ai.key,
+        &subscription.mint,
+        &spl_token::id(),
+    )?;
+    let owner_subscription_token =
+        TokenAccount::unpack_from_slice(&owner_subscription_token_ai.try_borrow_data()?)?;
+    if owner_subscription_token.amount == 0 {
+        return Err(SubscriptionError::NotOwner.into());
+    }
+
+    // LOGIC
+
+    if !subscription.active {
+        return Err(SubscriptionError::AlreadyExpired.into());
+    }
+    if subscription.paused {
+        return Err(SubscriptionError::Paused.into());
+    }
+
+    // credit what's left of the current period, nothing was paid for a trial
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    // periods prepaid with Extend past the current one can't be credited
+    let period_start = retreat_periods(
+        subscription.interval,
+        subscription.duration,
+        subscription.anchor_day,
+        subscription.next_renew_time,
+        1,
+    )?;
+    if !subscription.trial && period_start > now {
+        msg!(
+            "Prepaid until {}, change plans in the last period.",
+            subscription.next_renew_time
+        );
+        return Err(SubscriptionError::Prepaid.into());
+    }
+    let credit = if subscription.trial {
+        0
+    } else {
+        prorate(
+            subscription.amount,
+            subscription.next_renew_time - now,
+            subscription.duration,
+        )?
+    };
+    msg!("credit: {}", credit);
+
+    // the new plan's period starts now, any credit past its price extends it
+    let new_amount = plan_amount(&new_plan, now);
+    if new_amount > max_amount_per_period {
+        msg!(
+            "Amount {} exceeds max_amount_per_period {}",
+            new_amount,
+            max_amount_per_period
+        );
+        return Err(SubscriptionError::AmountAboveMax.into());
+    }
+    let charge = new_amount.saturating_sub(credit);
+    let extension = credit_to_time(
+        credit.saturating_sub(new_amount),
+        new_amount,
+        new_plan.duration,
+    )?;
+
+    if charge > 0 {
+        // the payee is paid in full unless the new plan splits payments
+        let payouts: Vec<_> = if recipient_ais.is_empty() {
+            vec![(payee_deposit_ai, charge)]
+        } else {
+            recipient_ais
+                .into_iter()
+                .map(|(_, recipient_deposit_ai)| recipient_deposit_ai)
+                .zip(split_amount(recipients, charge)?)
+                .collect()
+        };
+
+        // grossed up by the deposit mint's transfer fee. A delegated
+        // subscription pays from the funding account as far as its approval
+        // allows, otherwise the vault pays and leaves one fee behind so the
+        // subscription can still be expired
+        let decimals = mint_decimals(deposit_mint_ai)?;
+        let transfer_fee = TransferFee::get(deposit_mint_ai, clock.epoch)?;
+        let required_amount = transfer_fee.gross_total(payouts.iter().map(|(_, share)| *share))?;
+        let (source_ai, available, required_amount) = match funding_ai {
+            Some(funding_ai) => (
+                funding_ai,
+                delegated_balance(funding_ai, token_program_ai.key, subscription_ai.key)?,
+                required_amount,
+            ),
+            None => {
+                let deposit_vault =
+                    TokenAccount::unpack_from_slice(&deposit_vault_ai.try_borrow_data()?)?;
+                let fee = crank_fee(&new_plan.fee, new_amount)?;
+                (
+                    deposit_vault_ai,
+                    deposit_vault.amount,
+                    required_amount
+                        .checked_add(fee)
+                        .ok_or(TokenError::Overflow)?,
+                )
+            }
+        };
+        if available < required_amount {
+            msg!("Insufficient funds: need {}", required_amount);
+            return Err(TokenError::InsufficientFunds.into());
+        }
+
+        msg!("Transferring {}...", charge);
+        for (recipient_deposit_ai, share) in payouts {
+            if share == 0 {
+                continue;
+            }
+            invoke_signed(
+                &transfer_checked(
+                    token_program_ai.key,
+                    source_ai.key,
+                    deposit_mint_ai.key,
+                    recipient_deposit_ai.key,
+                    subscription_ai.key,
+                    transfer_fee.gross_amount(share)?,
+                    decimals,
+                )?,
+                &[
+                    source_ai.clone(),
+                    deposit_mint_ai.clone(),
+                    recipient_deposit_ai.clone(),
+                    subscription_ai.clone(),
+                    token_program_ai.clone(),
+                ],
+                &[subscription_seeds],
+            )?;
+        }
+    }
+
+    msg!("Updating subscription metadata...");
+    subscription.plan = *new_plan_ai.key;
+    subscription.amount = new_amount;
+    subscription.max_amount_per_period = max_amount_per_period;
+    subscription.duration = new_plan.duration;
+    subscription.interval = new_plan.interval;
+    subscription.fee = new_plan.fee;
+    subscription.past_due = false;
+    subscription.trial = false;
+    let (pending_amount, pending_amount_time) = pending_price(&new_plan, now).unwrap_or((0, 0));
+    subscription.pending_amount = pending_amount;
+    subscription.pending_amount_time = pending_amount_time;
+    // calendar periods are anchored on today, or the extended renewal
+    let anchor_day = day_of_month(now);
+    subscription.next_renew_time =
+        advance_periods(new_plan.interval, new_plan.duration, anchor_day, now, 1)?
+            .checked_add(extension)
+            .ok_or(SubscriptionError::Overflow)?;
+    subscription.anchor_day = if extension == 0 {
+        anchor_day
+    } else {
+        day_of_month(subscription.next_renew_time)
+    };
+    msg!("next_renew_time: {}", subscription.next_renew_time);
+    subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
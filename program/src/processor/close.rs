@@ -1,22 +1,273 @@
 use {
     crate::{
-        state::{Counter, Subscription},
-        utils::{check_ata, check_pda, check_program_id, check_signer, check_writable},
+        error::SubscriptionError,
+        state::Subscription2,
+        token::{
+            check_token_program, close_account, create_associated_token_account,
+            harvest_withheld_tokens_to_mint, has_transfer_fee_config, mint_decimals,
+            transfer_checked,
+        },
+        utils::{
+            check_ata, check_ata_initialized, check_program_id, check_signer,
+            check_subscription_address, check_writable,
+        },
     },
-    borsh::{BorshDeserialize, BorshSerialize},
+    borsh::BorshDeserialize,
     solana_program::{
         account_info::{next_account_info, AccountInfo},
         entrypoint::ProgramResult,
+        msg,
         program::{invoke, invoke_signed},
+        program_error::ProgramError,
+        program_pack::Pack,
         pubkey::Pubkey,
-        system_instruction, system_program,
-        sysvar::{rent, Sysvar},
+        system_program,
+        sysvar::rent,
     },
+    spl_token::{error::TokenError, native_mint, state::Account as TokenAccount},
 };
 
-pub fn process_close(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-) -> ProgramResult {
+pub fn process_close(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let user_ai = next_account_info(accounts_iter)?;
+    let user_deposit_ai = next_account_info(accounts_iter)?;
+    let user_subscription_token_ai = next_account_info(accounts_iter)?;
+    let subscription_mint_ai = next_account_info(accounts_iter)?;
+    let subscription_ai = next_account_info(accounts_iter)?;
+    let deposit_vault_ai = next_account_info(accounts_iter)?;
+    let deposit_mint_ai = next_account_info(accounts_iter)?;
+
+    let system_program_ai = next_account_info(accounts_iter)?;
+    let sysvar_rent_ai = next_account_info(accounts_iter)?;
+    let token_program_ai = next_account_info(accounts_iter)?;
+    let associated_token_program_ai = next_account_info(accounts_iter)?;
+    let deposit_token_program_ai = next_account_info(accounts_iter)?;
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(user_ai)?;
+    check_writable(user_ai)?;
+    check_writable(user_deposit_ai)?;
+    check_writable(user_subscription_token_ai)?;
+    check_writable(subscription_mint_ai)?;
+    check_writable(subscription_ai)?;
+    check_writable(deposit_vault_ai)?;
+
+    // PDAs
+    let subscription = Subscription2::try_from_slice(&subscription_ai.try_borrow_data()?)?;
+    check_subscription_address(
+        subscription_ai,
+        &subscription.origin_plan,
+        subscription.index,
+        Some(subscription.bump),
+        program_id,
+    )?;
+    let plan_key = subscription.origin_plan;
+    let subscription_seeds = &[
+        b"subscription_metadata",
+        plan_key.as_ref(),
+        &subscription.index.to_le_bytes(),
+        &[subscription.bump],
+    ];
+
+    if *subscription_mint_ai.key != subscription.mint {
+        msg!("Invalid subscription mint: {}", subscription_mint_ai.key);
+        return Err(TokenError::MintMismatch.into());
+    }
+
+    // deposit mint
+    if *deposit_mint_ai.key != subscription.deposit_mint {
+        return Err(TokenError::MintMismatch.into());
+    }
+    check_token_program(deposit_token_program_ai, deposit_mint_ai)?;
+
+    // token accounts
+    if *deposit_vault_ai.key != subscription.deposit_vault {
+        msg!("Invalid deposit vault: {}", deposit_vault_ai.key);
+        return Err(ProgramError::InvalidArgument);
+    }
+    check_ata_initialized(
+        deposit_vault_ai,
+        subscription_ai.key,
+        &subscription.deposit_mint,
+        deposit_token_program_ai.key,
+    )?;
+    check_ata(
+        user_deposit_ai,
+        user_ai.key,
+        &subscription.deposit_mint,
+        deposit_token_program_ai.key,
+    )?;
+
+    // ownership is proven by holding the subscription token
+    check_ata_initialized(
+        user_subscription_token_ai,
+        user_ai.key,
+        &subscription.mint,
+        &spl_token::id(),
+    )?;
+    let user_subscription_token =
+        TokenAccount::unpack_from_slice(&user_subscription_token_ai.try_borrow_data()?)?;
+    if user_subscription_token.amount == 0 {
+        return Err(SubscriptionError::NotOwner.into());
+    }
+
+    // programs
+    check_program_id(system_program_ai, &system_program::id())?;
+    check_program_id(sysvar_rent_ai, &rent::id())?;
+    check_program_id(token_program_ai, &spl_token::id())?;
+    check_program_id(
+        associated_token_program_ai,
+        &spl_associated_token_account::id(),
+    )?;
+
+    // LOGIC
+
+    // native SOL is unwrapped by closing the vault straight to the user,
+    // anything else is swept to the user's token account
+    let native = *deposit_mint_ai.key == native_mint::id();
+    if !native {
+        // create user token account if uninitialized
+        if user_deposit_ai.data_len() == 0 {
+            msg!("Creating user deposit token account...");
+            invoke(
+                &create_associated_token_account(
+                    user_ai.key,
+                    user_ai.key,
+                    &subscription.deposit_mint,
+                    deposit_token_program_ai.key,
+                ),
+                &[
+                    user_ai.clone(),
+                    user_deposit_ai.clone(),
+                    user_ai.clone(),
+                    deposit_mint_ai.clone(),
+                    system_program_ai.clone(),
+                    deposit_token_program_ai.clone(),
+                    sysvar_rent_ai.clone(),
+                    associated_token_program_ai.clone(),
+                ],
+            )?;
+        } else {
+            check_ata_initialized(
+                user_deposit_ai,
+                user_ai.key,
+                &subscription.deposit_mint,
+                deposit_token_program_ai.key,
+            )?;
+        }
+
+        // sweep deposit vault
+        let deposit_vault = TokenAccount::unpack_from_slice(&deposit_vault_ai.try_borrow_data()?)?;
+        if deposit_vault.amount > 0 {
+            msg!(
+                "Transferring {} from deposit vault to user...",
+                deposit_vault.amount
+            );
+            invoke_signed(
+                &transfer_checked(
+                    deposit_token_program_ai.key,
+                    deposit_vault_ai.key,
+                    deposit_mint_ai.key,
+                    user_deposit_ai.key,
+                    subscription_ai.key,
+                    deposit_vault.amount,
+                    mint_decimals(deposit_mint_ai)?,
+                )?,
+                &[
+                    deposit_vault_ai.clone(),
+                    deposit_mint_ai.clone(),
+                    user_deposit_ai.clone(),
+                    subscription_ai.clone(),
+                    deposit_token_program_ai.clone(),
+                ],
+                &[subscription_seeds],
+            )?;
+        }
+    }
+
+    // spl-token-2022 won't close an account still holding withheld fees
+    if has_transfer_fee_config(deposit_mint_ai)? {
+        check_writable(deposit_mint_ai)?;
+        msg!("Harvesting withheld fees from deposit vault...");
+        invoke(
+            &harvest_withheld_tokens_to_mint(deposit_mint_ai.key, &[deposit_vault_ai.key]),
+            &[
+                deposit_mint_ai.clone(),
+                deposit_vault_ai.clone(),
+                deposit_token_program_ai.clone(),
+            ],
+        )?;
+    }
+
+    msg!("Closing deposit vault...");
+    invoke_signed(
+        &close_account(
+            deposit_token_program_ai.key,
+            deposit_vault_ai.key,
+            user_ai.key,
+            subscription_ai.key,
+        )?,
+        &[
+            deposit_vault_ai.clone(),
+            user_ai.clone(),
+            subscription_ai.clone(),
+            deposit_token_program_ai.clone(),
+        ],
+        &[subscription_seeds],
+    )?;
+
+    // burn the ownership token so the index can mint again when reused. Its
+    // token metadata stays behind with the mint, SyncMetadata refreshes it
+    // for the next holder
+    msg!("Burning subscription ownership token...");
+    invoke(
+        &spl_token::instruction::burn(
+            &spl_token::id(),
+            user_subscription_token_ai.key,
+            subscription_mint_ai.key,
+            user_ai.key,
+            &[],
+            user_subscription_token.amount,
+        )?,
+        &[
+            user_subscription_token_ai.clone(),
+            subscription_mint_ai.clone(),
+            user_ai.clone(),
+            token_program_ai.clone(),
+        ],
+    )?;
+
+    msg!("Closing subscription ownership token account...");
+    invoke(
+        &spl_token::instruction::close_account(
+            &spl_token::id(),
+            user_subscription_token_ai.key,
+            user_ai.key,
+            user_ai.key,
+            &[],
+        )?,
+        &[
+            user_subscription_token_ai.clone(),
+            user_ai.clone(),
+            token_program_ai.clone(),
+        ],
+    )?;
+
+    msg!("Withdrawing rent from subscription account...");
+    let user_starting_lamports = user_ai.lamports();
+    **user_ai.lamports.borrow_mut() = user_starting_lamports
+        .checked_add(subscription_ai.lamports())
+        .ok_or(TokenError::Overflow)?;
+    **subscription_ai.lamports.borrow_mut() = 0;
+
+    // zero out account data
+    let mut subscription_data = subscription_ai.try_borrow_mut_data()?;
+    for i in 0..subscription_data.len() {
+        subscription_data[i] = 0;
+    }
+
     Ok(())
 }
@@ -0,0 +1,122 @@
+use {
+    crate::{
+        fee::check_fee_policy,
+        state::{
+            BillingInterval, FeePolicy, Plan, PlanPolicy, SplitRecipient, MAX_SPLIT_RECIPIENTS,
+        },
+        token::check_deposit_mint,
+        utils::{
+            check_plan_address, check_plan_duration, check_plan_policy, check_program_id,
+            check_signer, check_splits, check_writable, to_fixed_bytes,
+        },
+    },
+    borsh::BorshSerialize,
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        entrypoint::ProgramResult,
+        msg,
+        program::invoke_signed,
+        program_error::ProgramError,
+        pubkey::Pubkey,
+        system_instruction, system_program,
+        sysvar::{rent, Sysvar},
+    },
+};
+
+pub fn process_create_plan(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    id: u64,
+    amount: u64,
+    duration: i64,
+    interval: BillingInterval,
+    fee: &FeePolicy,
+    policy: &PlanPolicy,
+    splits: &[SplitRecipient; MAX_SPLIT_RECIPIENTS],
+    name: &str,
+    uri: &str,
+) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let payee_ai = next_account_info(accounts_iter)?;
+    let plan_ai = next_account_info(accounts_iter)?;
+    let deposit_mint_ai = next_account_info(accounts_iter)?;
+
+    let system_program_ai = next_account_info(accounts_iter)?;
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(payee_ai)?;
+    check_writable(payee_ai)?;
+    check_writable(plan_ai)?;
+
+    // PDAs
+    check_plan_address(plan_ai, payee_ai.key, id, None, program_id)?;
+    let (_, plan_bump) = Pubkey::find_program_address(
+        &[b"plan", payee_ai.key.as_ref(), &id.to_le_bytes()],
+        program_id,
+    );
+    let plan_seeds = &[
+        b"plan",
+        payee_ai.key.as_ref(),
+        &id.to_le_bytes(),
+        &[plan_bump],
+    ];
+    if plan_ai.data_len() != 0 {
+        msg!("Plan already exists: {}", plan_ai.key);
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    // deposit mint, either token program's
+    check_deposit_mint(deposit_mint_ai)?;
+
+    // programs
+    check_program_id(system_program_ai, &system_program::id())?;
+
+    // LOGIC
+
+    if amount == 0 {
+        msg!("Plan amount must be positive.");
+        return Err(ProgramError::InvalidArgument);
+    }
+    check_plan_duration(interval, duration)?;
+    check_plan_policy(policy, duration)?;
+    check_fee_policy(fee, amount)?;
+    check_splits(splits)?;
+
+    msg!("Creating plan account...");
+    let plan = Plan {
+        bump: plan_bump,
+        active: true,
+        payee: *payee_ai.key,
+        id,
+        deposit_mint: *deposit_mint_ai.key,
+        amount,
+        duration,
+        interval,
+        fee: *fee,
+        policy: *policy,
+        splits: *splits,
+        pending_amount: 0,
+        pending_amount_time: 0,
+        count: 0,
+        name: to_fixed_bytes(name)?,
+        uri: to_fixed_bytes(uri)?,
+    };
+    let plan_data = plan.try_to_vec()?;
+    invoke_signed(
+        &system_instruction::create_account(
+            payee_ai.key,
+            plan_ai.key,
+            rent::Rent::get()?.minimum_balance(plan_data.len()),
+            plan_data.len() as u64,
+            program_id,
+        ),
+        &[payee_ai.clone(), plan_ai.clone(), system_program_ai.clone()],
+        &[plan_seeds],
+    )?;
+    plan_ai.try_borrow_mut_data()?.copy_from_slice(&plan_data);
+
+    Ok(())
+}
@@ -1,62 +1,101 @@
-use solana_program::{
-    account_info::next_account_info, account_info::AccountInfo, entrypoint::ProgramResult, msg,
-    program::invoke, program_error::ProgramError, program_pack::Pack,
+use {
+    crate::{
+        token::{check_token_program, mint_decimals, transfer_checked, TransferFee},
+        utils::{check_program_id, check_signer, check_writable},
+    },
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        clock::Clock,
+        entrypoint::ProgramResult,
+        msg,
+        program::invoke,
+        program_pack::Pack,
+        system_instruction, system_program,
+        sysvar::Sysvar,
+    },
+    spl_token::{error::TokenError, native_mint, state::Account as TokenAccount},
 };
 
-use spl_token::*;
-
-use crate::utils::{check_program_id, check_signer, check_writable};
-
 pub fn process_deposit(accounts: &[AccountInfo], amount: u64) -> ProgramResult {
-    // get accounts
+    // GET ACCOUNTS
     let accounts_iter = &mut accounts.iter();
 
-    let payer = next_account_info(accounts_iter)?;
-    let payer_token = next_account_info(accounts_iter)?;
-    let vault = next_account_info(accounts_iter)?;
+    let payer_ai = next_account_info(accounts_iter)?;
+    let payer_token_ai = next_account_info(accounts_iter)?;
+    let deposit_vault_ai = next_account_info(accounts_iter)?;
 
     let token_program_ai = next_account_info(accounts_iter)?;
+    let deposit_mint_ai = next_account_info(accounts_iter)?;
+    let system_program_ai = next_account_info(accounts_iter)?;
 
-    // check program id with spl_token
-    check_program_id(token_program_ai, &spl_token::id())?;
-
-    check_signer(payer)?;
-    check_writable(payer)?;
-    check_writable(payer_token)?;
-    check_writable(vault)?;
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(payer_ai)?;
+    check_writable(payer_ai)?;
+    check_writable(payer_token_ai)?;
+    check_writable(deposit_vault_ai)?;
 
-    // this function does some stuff by making the account a "token account"
-    let payer_token_account =
-        spl_token::state::Account::unpack_from_slice(&payer_token.try_borrow_data()?)?;
-    let vault_token =
-        spl_token::state::Account::unpack_from_slice(&payer_token.try_borrow_data()?)?;
+    // deposit mint
+    check_token_program(token_program_ai, deposit_mint_ai)?;
 
-    // validate token account's owner
-    if payer_token_account.owner != *payer.key {
-        return Err(spl_token::error::TokenError::OwnerMismatch.into());
+    // token accounts
+    let payer_token = TokenAccount::unpack_from_slice(&payer_token_ai.try_borrow_data()?)?;
+    let deposit_vault = TokenAccount::unpack_from_slice(&deposit_vault_ai.try_borrow_data()?)?;
+    if payer_token.owner != *payer_ai.key {
+        return Err(TokenError::OwnerMismatch.into());
     }
-
-    // check if the token account and vault are the same type
-    if vault_token.mint != payer_token_account.mint {
+    if payer_token.mint != *deposit_mint_ai.key || deposit_vault.mint != *deposit_mint_ai.key {
         msg!("Incorrect token account mint.");
-        return Err(spl_token::error::TokenError::MintMismatch.into());
+        return Err(TokenError::MintMismatch.into());
+    }
+
+    // programs
+    check_program_id(system_program_ai, &system_program::id())?;
+
+    // LOGIC
+
+    // grossed up by the deposit mint's transfer fee, so the vault receives
+    // `amount` in full
+    let decimals = mint_decimals(deposit_mint_ai)?;
+    let transfer_fee = TransferFee::get(deposit_mint_ai, Clock::get()?.epoch)?;
+    let gross_amount = transfer_fee.gross_amount(amount)?;
+
+    // native SOL is wrapped as needed
+    let native = *deposit_mint_ai.key == native_mint::id();
+    if native && payer_token.amount < gross_amount {
+        let shortfall = gross_amount - payer_token.amount;
+        msg!("Wrapping {} lamports...", shortfall);
+        invoke(
+            &system_instruction::transfer(payer_ai.key, payer_token_ai.key, shortfall),
+            &[
+                payer_ai.clone(),
+                payer_token_ai.clone(),
+                system_program_ai.clone(),
+            ],
+        )?;
+        invoke(
+            &spl_token::instruction::sync_native(&spl_token::id(), payer_token_ai.key)?,
+            &[payer_token_ai.clone(), token_program_ai.clone()],
+        )?;
     }
 
-    // invoke: transfer of tokens "amount" to the vault
+    msg!("Transferring {} to deposit vault...", gross_amount);
     invoke(
-        &spl_token::instruction::transfer(
+        &transfer_checked(
             token_program_ai.key,
-            &payer_token.key,
-            &vault.key,
-            &payer.key,
-            &[],
-            amount as u64,
+            payer_token_ai.key,
+            deposit_mint_ai.key,
+            deposit_vault_ai.key,
+            payer_ai.key,
+            gross_amount,
+            decimals,
         )?,
         &[
+            payer_token_ai.clone(),
+            deposit_mint_ai.clone(),
+            deposit_vault_ai.clone(),
+            payer_ai.clone(),
             token_program_ai.clone(),
-            payer_token.clone(),
-            vault.clone(),
-            payer.clone(),
         ],
     )?;
 
@@ -0,0 +1,280 @@
+use {
+    crate::{
+        billing::{
+            advance_periods, pending_price, plan_amount, prepay_amount, prepay_referral_commission,
+            split_amount, split_recipients,
+        },
+        error::SubscriptionError,
+        state::{Plan, Subscription2},
+        token::{check_token_program, mint_decimals, transfer_checked, TransferFee},
+        utils::{
+            check_ata_initialized, check_plan_address, check_signer, check_split_accounts,
+            check_subscription_address, check_writable,
+        },
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        clock::Clock,
+        entrypoint::ProgramResult,
+        msg,
+        program::invoke,
+        program_error::ProgramError,
+        program_pack::Pack,
+        pubkey::Pubkey,
+        sysvar::Sysvar,
+    },
+    spl_token::{error::TokenError, state::Account as TokenAccount},
+};
+
+pub fn process_extend(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    periods: u32,
+) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let payer_ai = next_account_info(accounts_iter)?;
+    let payer_deposit_ai = next_account_info(accounts_iter)?;
+    let subscription_ai = next_account_info(accounts_iter)?;
+    let plan_ai = next_account_info(accounts_iter)?;
+    let payee_deposit_ai = next_account_info(accounts_iter)?;
+
+    let token_program_ai = next_account_info(accounts_iter)?;
+    let deposit_mint_ai = next_account_info(accounts_iter)?;
+
+    // the plan's split recipients, then the referrer while they're owed
+    // commission
+    let remaining_ais = accounts_iter.as_slice();
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(payer_ai)?;
+    check_writable(payer_deposit_ai)?;
+    check_writable(subscription_ai)?;
+    check_writable(payee_deposit_ai)?;
+
+    // PDAs
+    let mut subscription = Subscription2::try_from_slice(&subscription_ai.try_borrow_data()?)?;
+    check_subscription_address(
+        subscription_ai,
+        &subscription.origin_plan,
+        subscription.index,
+        Some(subscription.bump),
+        program_id,
+    )?;
+
+    let plan = Plan::try_from_slice(&plan_ai.try_borrow_data()?)?;
+    check_plan_address(plan_ai, &plan.payee, plan.id, Some(plan.bump), program_id)?;
+    if *plan_ai.key != subscription.plan {
+        msg!(
+            "Invalid plan:\tExpected: {}\tGot: {}",
+            subscription.plan,
+            plan_ai.key
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // deposit mint
+    if *deposit_mint_ai.key != subscription.deposit_mint {
+        msg!("Invalid deposit mint: {}", deposit_mint_ai.key);
+        return Err(TokenError::MintMismatch.into());
+    }
+    check_token_program(token_program_ai, deposit_mint_ai)?;
+
+    // token accounts
+    check_ata_initialized(
+        payer_deposit_ai,
+        payer_ai.key,
+        &subscription.deposit_mint,
+        token_program_ai.key,
+    )?;
+    check_ata_initialized(
+        payee_deposit_ai,
+        &subscription.payee,
+        &subscription.deposit_mint,
+        token_program_ai.key,
+    )?;
+    let recipients = split_recipients(&plan.splits);
+    let recipient_ais = check_split_accounts(
+        recipients,
+        remaining_ais,
+        &subscription.deposit_mint,
+        token_program_ai.key,
+    )?;
+    for (recipient_ai, recipient_deposit_ai) in recipient_ais.iter() {
+        check_ata_initialized(
+            recipient_deposit_ai,
+            recipient_ai.key,
+            &subscription.deposit_mint,
+            token_program_ai.key,
+        )?;
+    }
+    let optional_ais = &mut remaining_ais[2 * recipients.len()..].iter();
+    let referral_owed = subscription.referral_basis_points != 0
+        && (subscription.referral_renewals == 0
+            || subscription.renewal_count < subscription.referral_renewals as u64);
+    let referrer_deposit_ai = if referral_owed {
+        let referrer_ai = next_account_info(optional_ais)?;
+        let referrer_deposit_ai = next_account_info(optional_ais)?;
+        if *referrer_ai.key != subscription.referrer {
+            msg!(
+                "Invalid referrer:\tExpected: {}\tGot: {}",
+                subscription.referrer,
+                referrer_ai.key
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+        check_writable(referrer_deposit_ai)?;
+        check_ata_initialized(
+            referrer_deposit_ai,
+            referrer_ai.key,
+            &subscription.deposit_mint,
+            token_program_ai.key,
+        )?;
+        Some(referrer_deposit_ai)
+    } else {
+        None
+    };
+
+    // LOGIC
+
+    if periods == 0 {
+        msg!("Must extend by at least one period.");
+        return Err(ProgramError::InvalidArgument);
+    }
+    if !subscription.active {
+        return Err(SubscriptionError::AlreadyExpired.into());
+    }
+    if subscription.paused {
+        msg!("Subscription is paused.");
+        return Err(SubscriptionError::Paused.into());
+    }
+
+    // prepaid periods count towards a fixed term, and can't start after it
+    let renewal_count = subscription
+        .renewal_count
+        .checked_add(periods as u64)
+        .ok_or(SubscriptionError::Overflow)?;
+    if subscription.max_renewals != 0 && renewal_count > subscription.max_renewals {
+        msg!(
+            "Only {} renewals left.",
+            subscription.max_renewals - subscription.renewal_count
+        );
+        return Err(SubscriptionError::TermExceeded.into());
+    }
+    let last_period_start = advance_periods(
+        subscription.interval,
+        subscription.duration,
+        subscription.anchor_day,
+        subscription.next_renew_time,
+        periods - 1,
+    )?;
+    if subscription.end_time != 0 && last_period_start >= subscription.end_time {
+        msg!(
+            "Last period would start after end_time {}.",
+            subscription.end_time
+        );
+        return Err(SubscriptionError::TermExceeded.into());
+    }
+
+    // prepaid at today's price, which the subscriber must have agreed to
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    let amount = plan_amount(&plan, now);
+    if amount > subscription.max_amount_per_period {
+        msg!(
+            "Amount {} exceeds subscriber maximum {}.",
+            amount,
+            subscription.max_amount_per_period
+        );
+        return Err(SubscriptionError::AmountAboveMax.into());
+    }
+    let total_amount = prepay_amount(&plan.policy, amount, periods)?;
+
+    // the referrer's commission on the prepaid periods comes out of the
+    // payee's share, as it would on renewal
+    let commission = match referrer_deposit_ai {
+        Some(_) => prepay_referral_commission(
+            subscription.referral_basis_points,
+            subscription.referral_renewals,
+            subscription.renewal_count,
+            total_amount,
+            periods,
+        )?,
+        None => 0,
+    };
+    let payee_amount = total_amount - commission;
+
+    // the payee is paid in full unless the plan splits payments
+    let mut payouts: Vec<_> = if recipient_ais.is_empty() {
+        vec![(payee_deposit_ai, payee_amount)]
+    } else {
+        recipient_ais
+            .into_iter()
+            .map(|(_, recipient_deposit_ai)| recipient_deposit_ai)
+            .zip(split_amount(recipients, payee_amount)?)
+            .collect()
+    };
+    if let Some(referrer_deposit_ai) = referrer_deposit_ai {
+        payouts.push((referrer_deposit_ai, commission));
+    }
+
+    // grossed up by the deposit mint's transfer fee, so each recipient
+    // receives their share in full
+    let decimals = mint_decimals(deposit_mint_ai)?;
+    let transfer_fee = TransferFee::get(deposit_mint_ai, clock.epoch)?;
+    let required_amount = transfer_fee.gross_total(payouts.iter().map(|(_, share)| *share))?;
+    let payer_deposit = TokenAccount::unpack_from_slice(&payer_deposit_ai.try_borrow_data()?)?;
+    if payer_deposit.amount < required_amount {
+        msg!("Insufficient funds: need {}", required_amount);
+        return Err(TokenError::InsufficientFunds.into());
+    }
+
+    msg!("Transferring {} for {} periods...", total_amount, periods);
+    for (recipient_deposit_ai, share) in payouts {
+        if share == 0 {
+            continue;
+        }
+        invoke(
+            &transfer_checked(
+                token_program_ai.key,
+                payer_deposit_ai.key,
+                deposit_mint_ai.key,
+                recipient_deposit_ai.key,
+                payer_ai.key,
+                transfer_fee.gross_amount(share)?,
+                decimals,
+            )?,
+            &[
+                payer_deposit_ai.clone(),
+                deposit_mint_ai.clone(),
+                recipient_deposit_ai.clone(),
+                payer_ai.clone(),
+                token_program_ai.clone(),
+            ],
+        )?;
+    }
+
+    msg!("Updating subscription metadata...");
+    subscription.renewal_count = renewal_count;
+    subscription.past_due = false;
+    // follow the plan's price as renewals do, so a scheduled change is shown
+    // to the subscriber prepaying across it
+    let (pending_amount, pending_amount_time) = pending_price(&plan, now).unwrap_or((0, 0));
+    subscription.amount = amount;
+    subscription.pending_amount = pending_amount;
+    subscription.pending_amount_time = pending_amount_time;
+    subscription.next_renew_time = advance_periods(
+        subscription.interval,
+        subscription.duration,
+        subscription.anchor_day,
+        last_period_start,
+        1,
+    )?;
+    msg!("next_renew_time: {}", subscription.next_renew_time);
+    subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
@@ -1,6 +1,7 @@
 use {
     crate::{
         state::{Counter, Subscription},
+        token::check_spl_token_mint,
         utils::{check_ata, check_pda, check_program_id, check_signer, check_writable},
     },
     borsh::{BorshDeserialize, BorshSerialize},
@@ -91,8 +92,16 @@ pub fn process_initialize(
         &[subscription_bump],
     ];
 
+    // deposit mint
+    check_spl_token_mint(deposit_mint_ai)?;
+
     // token accounts
-    check_ata(deposit_vault_ai, subscription_ai.key, deposit_mint_ai.key)?;
+    check_ata(
+        deposit_vault_ai,
+        subscription_ai.key,
+        deposit_mint_ai.key,
+        &spl_token::id(),
+    )?;
 
     // programs
     check_program_id(system_program_ai, &system_program::id())?;
@@ -1,65 +1,710 @@
 use {
     crate::{
-        state::{Counter2, Subscription2},
-        utils::{check_ata, check_pda, check_program_id, check_signer, check_writable},
+        billing::{advance_periods, pending_price, plan_amount, split_amount, split_recipients},
+        calendar::day_of_month,
+        error::SubscriptionError,
+        fee::crank_fee,
+        state::{Plan, Subscription2, TrialRecord},
+        token::{
+            check_token_program, close_account, create_associated_token_account, mint_decimals,
+            transfer_checked, TransferFee,
+        },
+        utils::{
+            check_ata, check_ata_initialized, check_pda, check_plan_address, check_program_id,
+            check_signer, check_split_accounts, check_writable,
+        },
     },
     borsh::{BorshDeserialize, BorshSerialize},
     solana_program::{
         account_info::{next_account_info, AccountInfo},
+        clock::Clock,
         entrypoint::ProgramResult,
+        msg,
         program::{invoke, invoke_signed},
+        program_error::ProgramError,
+        program_pack::Pack,
         pubkey::Pubkey,
         system_instruction, system_program,
         sysvar::{rent, Sysvar},
     },
+    spl_token::{error::TokenError, native_mint, state::Account as TokenAccount, state::Mint},
 };
 
 pub fn process_initialize2(
     program_id: &Pubkey,
     accounts: &[AccountInfo],
-    payee: &Pubkey,
-    amount: u64,
-    duration: i64,
     start_amount: u64,
+    index: u64,
+    max_amount_per_period: u64,
+    max_renewals: u64,
+    end_time: i64,
+    start_time: i64,
+    delegated: bool,
 ) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let user_ai = next_account_info(accounts_iter)?;
+    let user_deposit_ai = next_account_info(accounts_iter)?;
+    let user_subscription_token_ai = next_account_info(accounts_iter)?;
+    let payee_ai = next_account_info(accounts_iter)?;
+    let payee_deposit_ai = next_account_info(accounts_iter)?;
+    let subscription_ai = next_account_info(accounts_iter)?;
+    let plan_ai = next_account_info(accounts_iter)?;
+    let trial_record_ai = next_account_info(accounts_iter)?;
+    let subscription_mint_ai = next_account_info(accounts_iter)?;
+    let deposit_vault_ai = next_account_info(accounts_iter)?;
+    let deposit_mint_ai = next_account_info(accounts_iter)?;
+
+    let system_program_ai = next_account_info(accounts_iter)?;
+    let sysvar_rent_ai = next_account_info(accounts_iter)?;
+    let token_program_ai = next_account_info(accounts_iter)?;
+    let associated_token_program_ai = next_account_info(accounts_iter)?;
+    let deposit_token_program_ai = next_account_info(accounts_iter)?;
+
+    // the plan's split recipients, then the optional referrer
+    let remaining_ais = accounts_iter.as_slice();
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(user_ai)?;
+    check_writable(user_ai)?;
+    check_writable(user_deposit_ai)?;
+    check_writable(user_subscription_token_ai)?;
+    check_writable(payee_deposit_ai)?;
+    check_writable(subscription_ai)?;
+    check_writable(plan_ai)?;
+    check_writable(trial_record_ai)?;
+    check_writable(subscription_mint_ai)?;
+    check_writable(deposit_vault_ai)?;
+
+    // plan
+    let mut plan = Plan::try_from_slice(&plan_ai.try_borrow_data()?)?;
+    check_plan_address(plan_ai, &plan.payee, plan.id, Some(plan.bump), program_id)?;
+    if !plan.active {
+        msg!("Plan is retired: {}", plan_ai.key);
+        return Err(SubscriptionError::PlanRetired.into());
+    }
+    let payee = &plan.payee;
+
+    // payee
+    if *payee_ai.key != *payee {
+        msg!("Invalid payee:\tExpected: {}\tGot: {}", payee, payee_ai.key);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // deposit mint, under either token program
+    if *deposit_mint_ai.key != plan.deposit_mint {
+        return Err(TokenError::MintMismatch.into());
+    }
+    check_token_program(deposit_token_program_ai, deposit_mint_ai)?;
+
+    // index is either the next new subscription or a previously closed one
+    let count = plan.count;
+    if index > count {
+        msg!("Invalid index:\tcount: {}\tGot: {}", count, index);
+        return Err(SubscriptionError::InvalidIndex.into());
+    }
+
+    // subscription PDA
+    let subscription_seeds = &[
+        b"subscription_metadata",
+        plan_ai.key.as_ref(),
+        &index.to_le_bytes(),
+    ];
+    check_pda(subscription_ai, subscription_seeds, program_id)?;
+    let (_, subscription_bump) = Pubkey::find_program_address(subscription_seeds, program_id);
+    let subscription_seeds = &[
+        b"subscription_metadata",
+        plan_ai.key.as_ref(),
+        &index.to_le_bytes(),
+        &[subscription_bump],
+    ];
+    if subscription_ai.data_len() != 0 {
+        msg!("Subscription already in use: {}", subscription_ai.key);
+        return Err(ProgramError::AccountAlreadyInitialized);
+    }
+
+    // trial record PDA, only created once the user has had the plan's trial
+    let trial_record_seeds = &[b"trial", plan_ai.key.as_ref(), user_ai.key.as_ref()];
+    check_pda(trial_record_ai, trial_record_seeds, program_id)?;
+    let (_, trial_record_bump) = Pubkey::find_program_address(trial_record_seeds, program_id);
+    let trial_record_seeds = &[
+        b"trial",
+        plan_ai.key.as_ref(),
+        user_ai.key.as_ref(),
+        &[trial_record_bump],
+    ];
+
+    // subscription ownership mint PDA
+    let subscription_mint_seeds = &[b"subscription_mint", subscription_ai.key.as_ref()];
+    check_pda(subscription_mint_ai, subscription_mint_seeds, program_id)?;
+    let (_, subscription_mint_bump) =
+        Pubkey::find_program_address(subscription_mint_seeds, program_id);
+    let subscription_mint_seeds = &[
+        b"subscription_mint",
+        subscription_ai.key.as_ref(),
+        &[subscription_mint_bump],
+    ];
+
+    // a reused index already has a mint, which must have been emptied on close
+    let reused_mint = subscription_mint_ai.data_len() != 0;
+    if reused_mint {
+        if *subscription_mint_ai.owner != spl_token::id() {
+            msg!(
+                "Subscription mint not owned by token program: {}",
+                subscription_mint_ai.key
+            );
+            return Err(ProgramError::IllegalOwner);
+        }
+        let subscription_mint = Mint::unpack(&subscription_mint_ai.try_borrow_data()?)?;
+        if subscription_mint.supply != 0 {
+            msg!("Subscription mint still has an outstanding token.");
+            return Err(ProgramError::AccountAlreadyInitialized);
+        }
+    }
+
+    // token accounts, a native SOL account is created if needed
+    let native = *deposit_mint_ai.key == native_mint::id();
+    if native {
+        check_ata(
+            user_deposit_ai,
+            user_ai.key,
+            deposit_mint_ai.key,
+            deposit_token_program_ai.key,
+        )?;
+    } else {
+        check_ata_initialized(
+            user_deposit_ai,
+            user_ai.key,
+            deposit_mint_ai.key,
+            deposit_token_program_ai.key,
+        )?;
+    }
+    check_ata(
+        user_subscription_token_ai,
+        user_ai.key,
+        subscription_mint_ai.key,
+        &spl_token::id(),
+    )?;
+    check_ata(
+        payee_deposit_ai,
+        payee,
+        deposit_mint_ai.key,
+        deposit_token_program_ai.key,
+    )?;
+    check_ata(
+        deposit_vault_ai,
+        subscription_ai.key,
+        deposit_mint_ai.key,
+        deposit_token_program_ai.key,
+    )?;
+    let recipients = split_recipients(&plan.splits);
+    let recipient_ais = check_split_accounts(
+        recipients,
+        remaining_ais,
+        deposit_mint_ai.key,
+        deposit_token_program_ai.key,
+    )?;
+    let referrer_ai = remaining_ais.get(2 * recipients.len());
+
+    // programs
+    check_program_id(system_program_ai, &system_program::id())?;
+    check_program_id(sysvar_rent_ai, &rent::id())?;
+    check_program_id(token_program_ai, &spl_token::id())?;
+    check_program_id(
+        associated_token_program_ai,
+        &spl_associated_token_account::id(),
+    )?;
+
+    // LOGIC
+
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    let amount = plan_amount(&plan, now);
+    let (pending_amount, pending_amount_time) = pending_price(&plan, now).unwrap_or((0, 0));
+    if amount > max_amount_per_period {
+        msg!(
+            "Amount {} exceeds max_amount_per_period {}",
+            amount,
+            max_amount_per_period
+        );
+        return Err(SubscriptionError::AmountAboveMax.into());
+    }
+
+    // the referrer is paid a share of renewals under the plan's terms
+    let referrer = match referrer_ai {
+        Some(referrer_ai) => {
+            if plan.policy.referral_basis_points == 0 {
+                msg!("Plan pays no referral commission.");
+                return Err(ProgramError::InvalidArgument);
+            }
+            if referrer_ai.key == user_ai.key {
+                msg!("Subscribers can't refer themselves.");
+                return Err(ProgramError::InvalidArgument);
+            }
+            msg!("Referred by {}", referrer_ai.key);
+            *referrer_ai.key
+        }
+        None => Pubkey::default(),
+    };
+
+    // the payee is paid in full unless the plan splits payments
+    let payouts = if recipient_ais.is_empty() {
+        vec![(payee_ai, payee_deposit_ai, amount)]
+    } else {
+        recipient_ais
+            .into_iter()
+            .zip(split_amount(recipients, amount)?)
+            .map(|((recipient_ai, recipient_deposit_ai), share)| {
+                (recipient_ai, recipient_deposit_ai, share)
+            })
+            .collect()
+    };
+
+    // payments are grossed up by the deposit mint's transfer fee, so each
+    // recipient receives their share in full
+    let decimals = mint_decimals(deposit_mint_ai)?;
+    let transfer_fee = TransferFee::get(deposit_mint_ai, clock.epoch)?;
+    let first_payment = transfer_fee.gross_total(payouts.iter().map(|(_, _, share)| *share))?;
+    let start_deposit = start_amount - transfer_fee.fee(start_amount)?;
+
+    // a start in the future is charged by the first renewal at or after it
+    let scheduled = start_time > now;
+    let start_time = if scheduled { start_time } else { now };
+    if end_time != 0 && end_time <= start_time {
+        msg!("end_time must be after the start: {}", end_time);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // starting deposit must cover the fee paid to whoever expires the
+    // subscription, unless renewals are pulled from the user's account
+    let fee_amount = crank_fee(&plan.fee, amount)?;
+    if !delegated && start_deposit < fee_amount {
+        msg!(
+            "start_amount must be at least the renewal fee: {}",
+            fee_amount
+        );
+        return Err(SubscriptionError::InsufficientStartAmount.into());
+    }
+
+    // the trial replaces the first payment, once per user and plan
+    let trial = plan.policy.trial_periods > 0 && trial_record_ai.data_len() == 0;
+
+    // a scheduled start is paid from the deposit vault, so it must hold the
+    // first payment and leave a fee behind like any renewal
+    if scheduled && !trial && !delegated {
+        let required_amount = first_payment
+            .checked_add(fee_amount)
+            .and_then(|total| total.checked_add(fee_amount))
+            .ok_or(TokenError::Overflow)?;
+        if start_deposit < required_amount {
+            msg!(
+                "start_amount must cover the first payment and fees: {}",
+                required_amount
+            );
+            return Err(SubscriptionError::InsufficientStartAmount.into());
+        }
+    }
+
+    // calendar periods renew on the day of month the subscription starts
+    let first_periods = if trial { plan.policy.trial_periods } else { 1 };
+    let anchor_day = day_of_month(start_time);
+    let next_renew_time = if scheduled {
+        start_time
+    } else {
+        advance_periods(plan.interval, plan.duration, anchor_day, now, first_periods)?
+    };
+
+    // native SOL is wrapped as needed, the account is closed again
+    // afterwards if it was created here and isn't funding renewals
+    let wrap_account = native && user_deposit_ai.data_len() == 0;
+    if wrap_account {
+        msg!("Creating user native SOL token account...");
+        invoke(
+            &create_associated_token_account(
+                user_ai.key,
+                user_ai.key,
+                deposit_mint_ai.key,
+                deposit_token_program_ai.key,
+            ),
+            &[
+                user_ai.clone(),
+                user_deposit_ai.clone(),
+                user_ai.clone(),
+                deposit_mint_ai.clone(),
+                system_program_ai.clone(),
+                deposit_token_program_ai.clone(),
+                sysvar_rent_ai.clone(),
+                associated_token_program_ai.clone(),
+            ],
+        )?;
+    }
+    let user_deposit = TokenAccount::unpack_from_slice(&user_deposit_ai.try_borrow_data()?)?;
+    let total_amount = if trial || scheduled {
+        start_amount
+    } else {
+        first_payment
+            .checked_add(start_amount)
+            .ok_or(TokenError::Overflow)?
+    };
+    if native && user_deposit.amount < total_amount {
+        let shortfall = total_amount - user_deposit.amount;
+        msg!("Wrapping {} lamports...", shortfall);
+        invoke(
+            &system_instruction::transfer(user_ai.key, user_deposit_ai.key, shortfall),
+            &[
+                user_ai.clone(),
+                user_deposit_ai.clone(),
+                system_program_ai.clone(),
+            ],
+        )?;
+        invoke(
+            &spl_token::instruction::sync_native(&spl_token::id(), user_deposit_ai.key)?,
+            &[user_deposit_ai.clone(), deposit_token_program_ai.clone()],
+        )?;
+    } else if user_deposit.amount < total_amount {
+        msg!("Insufficient funds: need {}", total_amount);
+        return Err(TokenError::InsufficientFunds.into());
+    }
+
+    // initialize subscription metadata account
+    msg!("Creating subscription metadata account...");
+    let subscription_size = 1 + 1 + 1 // bump, mint_bump, active
+        + 32 + 32 + 32 + 32 + 32 + 32 // plan, origin_plan, mint, deposit_vault, deposit_mint, payee
+        + 8 + 8 + 1 + 1 // amount, duration, interval, anchor_day
+        + 8 + 8 + (8 + 2) // index, next_renew_time, fee
+        + 1 + 8 // paused, paused_at
+        + 1 // cancel_at_period_end
+        + 1 + 1 // past_due, trial
+        + 8 + 8 // pending_amount, pending_amount_time
+        + 8 // max_amount_per_period
+        + 8 + 8 + 8 // renewal_count, max_renewals, end_time
+        + 8 // start_time
+        + 32 + 2 + 4 // referrer, referral_basis_points, referral_renewals
+        + 32; // funding_account = 377
+    invoke_signed(
+        &system_instruction::create_account(
+            user_ai.key,
+            subscription_ai.key,
+            rent::Rent::get()?.minimum_balance(subscription_size),
+            subscription_size as u64,
+            program_id,
+        ),
+        &[
+            user_ai.clone(),
+            subscription_ai.clone(),
+            system_program_ai.clone(),
+        ],
+        &[subscription_seeds],
+    )?;
+
+    // initialize deposit vault
+    if deposit_vault_ai.data_len() == 0 {
+        msg!("Creating deposit vault...");
+        invoke(
+            &create_associated_token_account(
+                user_ai.key,
+                subscription_ai.key,
+                deposit_mint_ai.key,
+                deposit_token_program_ai.key,
+            ),
+            &[
+                user_ai.clone(),
+                deposit_vault_ai.clone(),
+                subscription_ai.clone(),
+                deposit_mint_ai.clone(),
+                system_program_ai.clone(),
+                deposit_token_program_ai.clone(),
+                sysvar_rent_ai.clone(),
+                associated_token_program_ai.clone(),
+            ],
+        )?;
+    } else {
+        check_ata_initialized(
+            deposit_vault_ai,
+            subscription_ai.key,
+            deposit_mint_ai.key,
+            deposit_token_program_ai.key,
+        )?;
+    }
+
+    // initialize recipient deposit token accounts
+    for (recipient_ai, recipient_deposit_ai, _) in payouts.iter() {
+        if recipient_deposit_ai.data_len() == 0 {
+            msg!("Creating {} deposit token account...", recipient_ai.key);
+            invoke(
+                &create_associated_token_account(
+                    user_ai.key,
+                    recipient_ai.key,
+                    deposit_mint_ai.key,
+                    deposit_token_program_ai.key,
+                ),
+                &[
+                    user_ai.clone(),
+                    (*recipient_deposit_ai).clone(),
+                    (*recipient_ai).clone(),
+                    deposit_mint_ai.clone(),
+                    system_program_ai.clone(),
+                    deposit_token_program_ai.clone(),
+                    sysvar_rent_ai.clone(),
+                    associated_token_program_ai.clone(),
+                ],
+            )?;
+        } else {
+            check_ata_initialized(
+                recipient_deposit_ai,
+                recipient_ai.key,
+                deposit_mint_ai.key,
+                deposit_token_program_ai.key,
+            )?;
+        }
+    }
+
+    if trial {
+        msg!(
+            "Starting free trial of {} periods...",
+            plan.policy.trial_periods
+        );
+        let trial_record = TrialRecord {
+            bump: trial_record_bump,
+        };
+        let trial_record_data = trial_record.try_to_vec()?;
+        invoke_signed(
+            &system_instruction::create_account(
+                user_ai.key,
+                trial_record_ai.key,
+                rent::Rent::get()?.minimum_balance(trial_record_data.len()),
+                trial_record_data.len() as u64,
+                program_id,
+            ),
+            &[
+                user_ai.clone(),
+                trial_record_ai.clone(),
+                system_program_ai.clone(),
+            ],
+            &[trial_record_seeds],
+        )?;
+        trial_record_ai
+            .try_borrow_mut_data()?
+            .copy_from_slice(&trial_record_data);
+    } else if scheduled {
+        msg!("Scheduling start at {}...", start_time);
+    } else {
+        // first payment
+        msg!("Transferring first payment...");
+        for (_, recipient_deposit_ai, share) in payouts {
+            if share == 0 {
+                continue;
+            }
+            invoke(
+                &transfer_checked(
+                    deposit_token_program_ai.key,
+                    user_deposit_ai.key,
+                    deposit_mint_ai.key,
+                    recipient_deposit_ai.key,
+                    user_ai.key,
+                    transfer_fee.gross_amount(share)?,
+                    decimals,
+                )?,
+                &[
+                    user_deposit_ai.clone(),
+                    deposit_mint_ai.clone(),
+                    recipient_deposit_ai.clone(),
+                    user_ai.clone(),
+                    deposit_token_program_ai.clone(),
+                ],
+            )?;
+        }
+    }
+
+    // starting deposit
+    msg!("Transferring start_amount to deposit vault...");
+    invoke(
+        &transfer_checked(
+            deposit_token_program_ai.key,
+            user_deposit_ai.key,
+            deposit_mint_ai.key,
+            deposit_vault_ai.key,
+            user_ai.key,
+            start_amount,
+            decimals,
+        )?,
+        &[
+            user_deposit_ai.clone(),
+            deposit_mint_ai.clone(),
+            deposit_vault_ai.clone(),
+            user_ai.clone(),
+            deposit_token_program_ai.clone(),
+        ],
+    )?;
+
+    if wrap_account && !delegated {
+        msg!("Closing user native SOL token account...");
+        invoke(
+            &close_account(
+                deposit_token_program_ai.key,
+                user_deposit_ai.key,
+                user_ai.key,
+                user_ai.key,
+            )?,
+            &[
+                user_deposit_ai.clone(),
+                user_ai.clone(),
+                user_ai.clone(),
+                deposit_token_program_ai.clone(),
+            ],
+        )?;
+    }
+
+    // initialize subscription ownership mint
+    if !reused_mint {
+        msg!("Creating subscription ownership mint...");
+        invoke_signed(
+            &system_instruction::create_account(
+                user_ai.key,
+                subscription_mint_ai.key,
+                rent::Rent::get()?.minimum_balance(Mint::get_packed_len()),
+                Mint::get_packed_len() as u64,
+                &spl_token::id(),
+            ),
+            &[
+                user_ai.clone(),
+                subscription_mint_ai.clone(),
+                system_program_ai.clone(),
+            ],
+            &[subscription_mint_seeds],
+        )?;
+
+        msg!("Initializing subscription ownership mint...");
+        invoke(
+            &spl_token::instruction::initialize_mint(
+                &spl_token::id(),
+                subscription_mint_ai.key,
+                subscription_ai.key,
+                Some(subscription_ai.key),
+                0,
+            )?,
+            &[
+                subscription_mint_ai.clone(),
+                sysvar_rent_ai.clone(),
+                token_program_ai.clone(),
+            ],
+        )?;
+    }
+
+    // initialize user subscription ownership token account
+    if user_subscription_token_ai.data_len() == 0 {
+        msg!("Creating user subscription ownership token account...");
+        invoke(
+            &spl_associated_token_account::create_associated_token_account(
+                user_ai.key,
+                user_ai.key,
+                subscription_mint_ai.key,
+            ),
+            &[
+                user_ai.clone(),
+                user_subscription_token_ai.clone(),
+                user_ai.clone(),
+                subscription_mint_ai.clone(),
+                system_program_ai.clone(),
+                token_program_ai.clone(),
+                sysvar_rent_ai.clone(),
+                associated_token_program_ai.clone(),
+            ],
+        )?;
+    } else {
+        check_ata_initialized(
+            user_subscription_token_ai,
+            user_ai.key,
+            subscription_mint_ai.key,
+            &spl_token::id(),
+        )?;
+    }
+
+    // mint the one ownership token
+    msg!("Minting subscription ownership token...");
+    invoke_signed(
+        &spl_token::instruction::mint_to(
+            &spl_token::id(),
+            subscription_mint_ai.key,
+            user_subscription_token_ai.key,
+            subscription_ai.key,
+            &[],
+            1,
+        )?,
+        &[
+            subscription_mint_ai.clone(),
+            user_subscription_token_ai.clone(),
+            subscription_ai.clone(),
+            token_program_ai.clone(),
+        ],
+        &[subscription_seeds],
+    )?;
+
+    // one ownership token per subscription
+    // mint authority stays with the subscription PDA instead of being revoked
+    // so a closed index can mint again; supply is capped at one since tokens
+    // are only ever minted here, while supply is zero, and burned on close
+    let subscription_mint = Mint::unpack(&subscription_mint_ai.try_borrow_data()?)?;
+    if subscription_mint.supply != 1 {
+        msg!(
+            "Subscription mint supply should be 1, got {}",
+            subscription_mint.supply
+        );
+        return Err(ProgramError::InvalidAccountData);
+    }
 
-    // Get accounts
-
-    // Validate accounts
-
-    // user - should be signer
-    // user deposit token account - check pda, owned by user, mint is deposit mint
-    // user subscription ownership token account - check pda
-    //      should be owned by user, should have mint of sub ownership token mint
-    // payee - check same as input - because you have to include this, you technically could
-    //      could remove the input parameter, might be cleaner without
-    // payee deposit token account - check pda, owner is payee, mint is deposit mint
-    // subscription metadata - check pda
-    // subscription counter - check pda
-    // subscription ownership token mint - check pda
-    // deposit vault - check pda, should be owned by subscription, should have deposit mint
-    // deposit mint - check initialized
-    // system program - check program
-    // sysvar rent - check program
-    // token program - check program
-    // associated token program - check program
-
-    // Execute instruction
-
-    // initialize metadata account (system program create account)
     // initialize metadata
-    // initialize deposit vault (associated token instr create account)
-    // ensure user token account has enough funds
-    // ensure start_amount > typical fee (refer to current renew instr for calculation)
-    // initialize payee deposit token account if needed (associated token program create account)
-    // send funds to payee (token program transfer)
-    // send start_amount to deposit vault (token program transfer)
-    // initialize sub ownership mint (token program initialize mint)
-    // initialize user sub ownership token account (associated token instr create account)
-    // mint first token to user (token program mint to)
-    // freeze mint (token program freeze)
-    // initialize counter if needed (system program create account)
+    let subscription = Subscription2 {
+        bump: subscription_bump,
+        mint_bump: subscription_mint_bump,
+        active: !scheduled,
+        plan: *plan_ai.key,
+        origin_plan: *plan_ai.key,
+        mint: *subscription_mint_ai.key,
+        deposit_vault: *deposit_vault_ai.key,
+        deposit_mint: *deposit_mint_ai.key,
+        payee: *payee,
+        amount,
+        duration: plan.duration,
+        interval: plan.interval,
+        anchor_day,
+        index,
+        next_renew_time,
+        fee: plan.fee,
+        paused: false,
+        paused_at: 0,
+        cancel_at_period_end: false,
+        past_due: false,
+        trial,
+        pending_amount,
+        pending_amount_time,
+        max_amount_per_period,
+        renewal_count: 0,
+        max_renewals,
+        end_time,
+        start_time: if scheduled { start_time } else { 0 },
+        referrer,
+        referral_basis_points: if referrer_ai.is_some() {
+            plan.policy.referral_basis_points
+        } else {
+            0
+        },
+        referral_renewals: plan.policy.referral_renewals,
+        funding_account: if delegated {
+            *user_deposit_ai.key
+        } else {
+            Pubkey::default()
+        },
+    };
+    subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
+
     // increment counter
+    if index == count {
+        plan.count = count + 1;
+        plan.serialize(&mut *plan_ai.try_borrow_mut_data()?)?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,94 @@
+use {
+    crate::{
+        error::SubscriptionError,
+        state::{Plan, Subscription2},
+        utils::{
+            check_ata_initialized, check_plan_address, check_signer, check_subscription_address,
+            check_writable,
+        },
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        clock::Clock,
+        entrypoint::ProgramResult,
+        msg,
+        program_error::ProgramError,
+        program_pack::Pack,
+        pubkey::Pubkey,
+        sysvar::Sysvar,
+    },
+    spl_token::state::Account as TokenAccount,
+};
+
+pub fn process_pause(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let owner_ai = next_account_info(accounts_iter)?;
+    let owner_subscription_token_ai = next_account_info(accounts_iter)?;
+    let subscription_ai = next_account_info(accounts_iter)?;
+    let plan_ai = next_account_info(accounts_iter)?;
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(owner_ai)?;
+    check_writable(subscription_ai)?;
+
+    // PDAs
+    let mut subscription = Subscription2::try_from_slice(&subscription_ai.try_borrow_data()?)?;
+    check_subscription_address(
+        subscription_ai,
+        &subscription.origin_plan,
+        subscription.index,
+        Some(subscription.bump),
+        program_id,
+    )?;
+
+    if *plan_ai.key != subscription.plan {
+        msg!("Invalid plan: {}", plan_ai.key);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let plan = Plan::try_from_slice(&plan_ai.try_borrow_data()?)?;
+    check_plan_address(plan_ai, &plan.payee, plan.id, Some(plan.bump), program_id)?;
+
+    // ownership is proven by holding the subscription token
+    check_ata_initialized(
+        owner_subscription_token_ai,
+        owner_ai.key,
+        &subscription.mint,
+        &spl_token::id(),
+    )?;
+    let owner_subscription_token =
+        TokenAccount::unpack_from_slice(&owner_subscription_token_ai.try_borrow_data()?)?;
+    if owner_subscription_token.amount == 0 {
+        return Err(SubscriptionError::NotOwner.into());
+    }
+
+    // LOGIC
+
+    if !plan.policy.allow_pause {
+        return Err(SubscriptionError::PauseNotAllowed.into());
+    }
+    if subscription.paused {
+        return Err(SubscriptionError::Paused.into());
+    }
+    if !subscription.active {
+        return Err(SubscriptionError::AlreadyExpired.into());
+    }
+
+    // a due renewal is settled first, otherwise resuming would carry over
+    // a period that was never paid for
+    let now = Clock::get()?.unix_timestamp;
+    if subscription.past_due || subscription.next_renew_time <= now {
+        msg!("Renew before pausing.");
+        return Err(SubscriptionError::RenewalDue.into());
+    }
+
+    msg!("Pausing subscription...");
+    subscription.paused = true;
+    subscription.paused_at = now;
+    subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
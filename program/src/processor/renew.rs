@@ -1,7 +1,9 @@
 use {
     crate::{
         error::SubscriptionError,
+        fee::renewal_fee,
         state::Subscription,
+        token::check_spl_token_mint,
         utils::{
             check_ata, check_ata_initialized, check_pda, check_program_id, check_signer,
             check_writable,
@@ -23,10 +25,6 @@ use {
     spl_token::{error::TokenError, instruction, state::Account as TokenAccount, state::Mint},
 };
 
-// fee paid to renewers are currently fixed to 0.01 * amount
-const FEE: u64 = 1;
-const FEE_DECIMALS: u8 = 2;
-
 pub fn process_renew(program_id: &Pubkey, accounts: &[AccountInfo], count: u64) -> ProgramResult {
     // GET ACCOUNTS
     let accounts_iter = &mut accounts.iter();
@@ -96,16 +94,28 @@ pub fn process_renew(program_id: &Pubkey, accounts: &[AccountInfo], count: u64)
     if *deposit_mint_ai.key != subscription.deposit_mint {
         return Err(TokenError::MintMismatch.into());
     }
+    check_spl_token_mint(deposit_mint_ai)?;
 
     check_ata_initialized(
         deposit_vault_ai,
         subscription_ai.key,
         &subscription.deposit_mint,
+        &spl_token::id(),
     )?;
 
-    check_ata(payee_vault_ai, payee, &subscription.deposit_mint)?;
+    check_ata(
+        payee_vault_ai,
+        payee,
+        &subscription.deposit_mint,
+        &spl_token::id(),
+    )?;
 
-    check_ata(caller_vault_ai, caller_ai.key, &subscription.deposit_mint)?;
+    check_ata(
+        caller_vault_ai,
+        caller_ai.key,
+        &subscription.deposit_mint,
+        &spl_token::id(),
+    )?;
 
     let new_mint_seeds = &[
         b"subscription_mint",
@@ -121,10 +131,20 @@ pub fn process_renew(program_id: &Pubkey, accounts: &[AccountInfo], count: u64)
         &[new_mint_bump],
     ];
 
-    check_ata(payer_new_vault_ai, payer_ai.key, new_mint_ai.key)?;
+    check_ata(
+        payer_new_vault_ai,
+        payer_ai.key,
+        new_mint_ai.key,
+        &spl_token::id(),
+    )?;
 
     if let Some(current_mint) = subscription.mint {
-        check_ata_initialized(payer_old_vault_ai, payer_ai.key, &current_mint)?;
+        check_ata_initialized(
+            payer_old_vault_ai,
+            payer_ai.key,
+            &current_mint,
+            &spl_token::id(),
+        )?;
     }
 
     // programs
@@ -148,8 +168,7 @@ pub fn process_renew(program_id: &Pubkey, accounts: &[AccountInfo], count: u64)
     }
 
     // calculate payments
-    let base: u32 = 10;
-    let caller_amount = (amount as f64 * FEE as f64 / base.pow(FEE_DECIMALS.into()) as f64) as u64;
+    let caller_amount = renewal_fee(amount)?;
     let payee_amount = amount - caller_amount;
 
     // checks balance of deposit vault, if not enough, deactivate, compensate caller, return
@@ -186,7 +205,12 @@ pub fn process_renew(program_id: &Pubkey, accounts: &[AccountInfo], count: u64)
                     ],
                 )?;
             } else {
-                check_ata_initialized(caller_vault_ai, caller_ai.key, &subscription.deposit_mint)?;
+                check_ata_initialized(
+                    caller_vault_ai,
+                    caller_ai.key,
+                    &subscription.deposit_mint,
+                    &spl_token::id(),
+                )?;
             }
 
             // pay out variable amount
@@ -276,7 +300,12 @@ pub fn process_renew(program_id: &Pubkey, accounts: &[AccountInfo], count: u64)
             ],
         )?;
     } else {
-        check_ata_initialized(caller_vault_ai, caller_ai.key, &subscription.deposit_mint)?;
+        check_ata_initialized(
+            caller_vault_ai,
+            caller_ai.key,
+            &subscription.deposit_mint,
+            &spl_token::id(),
+        )?;
     }
     if payee_vault_ai.data_len() == 0 {
         invoke(
@@ -297,7 +326,12 @@ pub fn process_renew(program_id: &Pubkey, accounts: &[AccountInfo], count: u64)
             ],
         )?;
     } else {
-        check_ata_initialized(payee_vault_ai, payee, &subscription.deposit_mint)?;
+        check_ata_initialized(
+            payee_vault_ai,
+            payee,
+            &subscription.deposit_mint,
+            &spl_token::id(),
+        )?;
     }
 
     // transfer to payee, transfer to caller, create mint, mint token
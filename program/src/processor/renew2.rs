@@ -1,55 +1,765 @@
 use {
     crate::{
-        state::{Counter2, Subscription2},
-        utils::{check_ata, check_pda, check_program_id, check_signer, check_writable},
+        billing::{
+            advance_periods, pending_price, periods_due, plan_amount, referral_commission,
+            split_amount, split_recipients,
+        },
+        calendar::day_of_month,
+        error::SubscriptionError,
+        fee::crank_fee,
+        state::{CatchUpPolicy, Plan, Subscription2},
+        token::{
+            check_token_program, close_account, create_associated_token_account, delegated_balance,
+            mint_decimals, transfer_checked, TransferFee,
+        },
+        utils::{
+            check_ata, check_ata_initialized, check_pda, check_plan_address, check_program_id,
+            check_signer, check_split_accounts, check_subscription_address, check_writable,
+        },
     },
     borsh::{BorshDeserialize, BorshSerialize},
     solana_program::{
         account_info::{next_account_info, AccountInfo},
+        clock::Clock,
         entrypoint::ProgramResult,
+        msg,
         program::{invoke, invoke_signed},
+        program_error::ProgramError,
+        program_pack::Pack,
         pubkey::Pubkey,
         system_instruction, system_program,
         sysvar::{rent, Sysvar},
     },
+    spl_token::{error::TokenError, native_mint, state::Account as TokenAccount},
 };
 
-pub fn process_renew2(
-    program_id: &Pubkey,
-    accounts: &[AccountInfo],
-) -> ProgramResult {
-
-    // Get accounts
-
-    // Validate accounts
-
-    // caller - check signer
-    // caller deposit token account - check pda, owner is caller, mint is deposit mint
-    // payee - check same as subscription payee
-    // payee deposit token account - check pda, owner is payee, mint is deposit mint
-    // subscription metadata - check pda
-    // deposit vault - check pda, check same as subscription.deposit_vault, owner is
-    //      subscription, mint is deposit mint
-    // deposit mint - check initialized, check same as subscription.deposit_mint
-    // system program - check program
-    // sysvar rent - check program
-    // token program - check program
-    // associated token program - check program
-
-    // Execute instruction
-
-    // if time is not up yet, revert
-    // calculate fee/transfer amount - should account for leaving minimum fee in deposit vault
-    // if deposit vault as insufficient funds
-    //      expire
-    //          send fee funds to caller (initialize token account if needed)
-    //          mark subscription metadata active status to false
-    // if deposit vault has sufficient funds
-    //      renew
-    //          send fee funds to caller (initialize token account if needed)
-    //          send funds to payee (initialize token account if needed)
-    //          mark subscription metadata active status to true
-    //          set new next renew time
+pub fn process_renew2(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let caller_ai = next_account_info(accounts_iter)?;
+    let caller_deposit_ai = next_account_info(accounts_iter)?;
+    let payee_ai = next_account_info(accounts_iter)?;
+    let payee_deposit_ai = next_account_info(accounts_iter)?;
+    let subscription_ai = next_account_info(accounts_iter)?;
+    let plan_ai = next_account_info(accounts_iter)?;
+    let deposit_vault_ai = next_account_info(accounts_iter)?;
+    let deposit_mint_ai = next_account_info(accounts_iter)?;
+
+    let system_program_ai = next_account_info(accounts_iter)?;
+    let sysvar_rent_ai = next_account_info(accounts_iter)?;
+    let token_program_ai = next_account_info(accounts_iter)?;
+    let associated_token_program_ai = next_account_info(accounts_iter)?;
+
+    // the plan's split recipients, the subscriber's funding account if
+    // delegated, the referrer while they're owed commission, then the holder
+    // of the subscription token, only needed for a refund when the
+    // subscription ends
+    let remaining_ais = accounts_iter.as_slice();
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(caller_ai)?;
+    check_writable(caller_ai)?;
+    check_writable(caller_deposit_ai)?;
+    check_writable(payee_deposit_ai)?;
+    check_writable(subscription_ai)?;
+    check_writable(deposit_vault_ai)?;
+
+    // PDAs
+    let mut subscription = Subscription2::try_from_slice(&subscription_ai.try_borrow_data()?)?;
+    let payee = subscription.payee;
+    check_subscription_address(
+        subscription_ai,
+        &subscription.origin_plan,
+        subscription.index,
+        Some(subscription.bump),
+        program_id,
+    )?;
+    let plan_key = subscription.origin_plan;
+    let subscription_seeds = &[
+        b"subscription_metadata",
+        plan_key.as_ref(),
+        &subscription.index.to_le_bytes(),
+        &[subscription.bump],
+    ];
+
+    if *plan_ai.key != subscription.plan {
+        msg!("Invalid plan: {}", plan_ai.key);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let plan = Plan::try_from_slice(&plan_ai.try_borrow_data()?)?;
+    check_plan_address(plan_ai, &plan.payee, plan.id, Some(plan.bump), program_id)?;
+
+    // payee
+    if *payee_ai.key != payee {
+        msg!("Invalid payee:\tExpected: {}\tGot: {}", payee, payee_ai.key);
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // deposit mint
+    if *deposit_mint_ai.key != subscription.deposit_mint {
+        return Err(TokenError::MintMismatch.into());
+    }
+    check_token_program(token_program_ai, deposit_mint_ai)?;
+
+    // token accounts
+    if *deposit_vault_ai.key != subscription.deposit_vault {
+        msg!("Invalid deposit vault: {}", deposit_vault_ai.key);
+        return Err(ProgramError::InvalidArgument);
+    }
+    check_ata_initialized(
+        deposit_vault_ai,
+        subscription_ai.key,
+        &subscription.deposit_mint,
+        token_program_ai.key,
+    )?;
+    check_ata(
+        caller_deposit_ai,
+        caller_ai.key,
+        &subscription.deposit_mint,
+        token_program_ai.key,
+    )?;
+    check_ata(
+        payee_deposit_ai,
+        &payee,
+        &subscription.deposit_mint,
+        token_program_ai.key,
+    )?;
+    let recipients = split_recipients(&plan.splits);
+    let recipient_ais = check_split_accounts(
+        recipients,
+        remaining_ais,
+        &subscription.deposit_mint,
+        token_program_ai.key,
+    )?;
+    let optional_ais = &mut remaining_ais[2 * recipients.len()..].iter();
+    let funding_ai = if subscription.funding_account != Pubkey::default() {
+        let funding_ai = next_account_info(optional_ais)?;
+        if *funding_ai.key != subscription.funding_account {
+            msg!(
+                "Invalid funding account:\tExpected: {}\tGot: {}",
+                subscription.funding_account,
+                funding_ai.key
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+        check_writable(funding_ai)?;
+        Some(funding_ai)
+    } else {
+        None
+    };
+    let referral_owed = subscription.referral_basis_points != 0
+        && (subscription.referral_renewals == 0
+            || subscription.renewal_count < subscription.referral_renewals as u64);
+    let referral_ais = if referral_owed {
+        let referrer_ai = next_account_info(optional_ais)?;
+        let referrer_deposit_ai = next_account_info(optional_ais)?;
+        if *referrer_ai.key != subscription.referrer {
+            msg!(
+                "Invalid referrer:\tExpected: {}\tGot: {}",
+                subscription.referrer,
+                referrer_ai.key
+            );
+            return Err(ProgramError::InvalidArgument);
+        }
+        check_writable(referrer_deposit_ai)?;
+        check_ata(
+            referrer_deposit_ai,
+            referrer_ai.key,
+            &subscription.deposit_mint,
+            token_program_ai.key,
+        )?;
+        Some((referrer_ai, referrer_deposit_ai))
+    } else {
+        None
+    };
+    let owner_ai = optional_ais.next();
+    let owner_subscription_token_ai = optional_ais.next();
+    let owner_deposit_ai = optional_ais.next();
+    let refund_ais = match (owner_ai, owner_subscription_token_ai, owner_deposit_ai) {
+        (Some(owner_ai), Some(owner_subscription_token_ai), Some(owner_deposit_ai)) => {
+            // the refund goes to whoever holds the subscription token now
+            check_ata_initialized(
+                owner_subscription_token_ai,
+                owner_ai.key,
+                &subscription.mint,
+                &spl_token::id(),
+            )?;
+            let owner_subscription_token =
+                TokenAccount::unpack_from_slice(&owner_subscription_token_ai.try_borrow_data()?)?;
+            if owner_subscription_token.amount == 0 {
+                return Err(SubscriptionError::NotOwner.into());
+            }
+            check_writable(owner_deposit_ai)?;
+            if *deposit_mint_ai.key == native_mint::id() {
+                // native SOL is unwrapped through a temporary refund account
+                // closed to the owner
+                check_writable(owner_ai)?;
+                check_pda(
+                    owner_deposit_ai,
+                    &[b"refund", subscription_ai.key.as_ref()],
+                    program_id,
+                )?;
+            } else {
+                check_ata(
+                    owner_deposit_ai,
+                    owner_ai.key,
+                    &subscription.deposit_mint,
+                    token_program_ai.key,
+                )?;
+            }
+            Some((owner_ai, owner_deposit_ai))
+        }
+        _ => None,
+    };
+
+    // programs
+    check_program_id(system_program_ai, &system_program::id())?;
+    check_program_id(sysvar_rent_ai, &rent::id())?;
+    check_program_id(
+        associated_token_program_ai,
+        &spl_associated_token_account::id(),
+    )?;
+
+    // LOGIC
+
+    if subscription.paused {
+        msg!("Subscription is paused.");
+        return Err(SubscriptionError::Paused.into());
+    }
+
+    // check time, if not time, throw error
+    let clock = Clock::get()?;
+    let now = clock.unix_timestamp;
+    msg!("now: {}", now);
+    msg!("next_renew_time: {}", subscription.next_renew_time);
+    if now < subscription.next_renew_time {
+        return Err(SubscriptionError::EarlyRenew.into());
+    }
+
+    // a scheduled start with a free trial starts the trial instead of charging
+    let starting = subscription.start_time != 0;
+    if starting && subscription.trial {
+        msg!(
+            "Starting free trial of {} periods...",
+            plan.policy.trial_periods
+        );
+        subscription.active = true;
+        subscription.start_time = 0;
+        subscription.next_renew_time = advance_periods(
+            subscription.interval,
+            subscription.duration,
+            subscription.anchor_day,
+            subscription.next_renew_time,
+            plan.policy.trial_periods,
+        )?;
+        subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
+        return Ok(());
+    }
+    let grace_end = subscription
+        .next_renew_time
+        .checked_add(plan.policy.grace_period)
+        .ok_or(SubscriptionError::Overflow)?;
+    let in_grace_period = now < grace_end;
+
+    // cancelled or fixed term over, end it and refund the owner
+    let term_over = (subscription.max_renewals != 0
+        && subscription.renewal_count >= subscription.max_renewals)
+        || (subscription.end_time != 0 && now >= subscription.end_time);
+    let ending = subscription.cancel_at_period_end || term_over;
+    let refund_ais = if ending {
+        Some(refund_ais.ok_or(ProgramError::NotEnoughAccountKeys)?)
+    } else {
+        None
+    };
+
+    // follow the plan's price, a scheduled change is recorded until it's
+    // effective and charged from then on
+    let amount = plan_amount(&plan, now);
+    let (pending_amount, pending_amount_time) = pending_price(&plan, now).unwrap_or((0, 0));
+    subscription.amount = amount;
+    subscription.pending_amount = pending_amount;
+    subscription.pending_amount_time = pending_amount_time;
+
+    // never charge more than the subscriber agreed to, the fee is based on
+    // what they agreed to as well
+    let within_max = amount <= subscription.max_amount_per_period;
+    if !within_max {
+        msg!(
+            "Amount {} exceeds subscriber maximum {}.",
+            amount,
+            subscription.max_amount_per_period
+        );
+    }
+
+    // missed periods are charged, skipped or dropped as the plan says, only
+    // counting periods within the term; a start or reactivation starts over
+    let catch_up = if subscription.active {
+        plan.policy.catch_up
+    } else {
+        CatchUpPolicy::Reanchor
+    };
+    let due_until = if subscription.end_time != 0 {
+        std::cmp::min(now, subscription.end_time - 1)
+    } else {
+        now
+    };
+    let periods_due = match catch_up {
+        CatchUpPolicy::Reanchor => 1,
+        _ => periods_due(
+            subscription.interval,
+            subscription.duration,
+            subscription.anchor_day,
+            subscription.next_renew_time,
+            due_until,
+        )?,
+    };
+    let periods = match catch_up {
+        CatchUpPolicy::ChargeMissed if subscription.max_renewals != 0 => {
+            let remaining = subscription
+                .max_renewals
+                .saturating_sub(subscription.renewal_count);
+            std::cmp::min(periods_due as u64, remaining) as u32
+        }
+        CatchUpPolicy::ChargeMissed => periods_due,
+        CatchUpPolicy::SkipMissed | CatchUpPolicy::Reanchor => 1,
+    };
+    let total_amount = amount
+        .checked_mul(periods as u64)
+        .ok_or(TokenError::Overflow)?;
+
+    // the referrer's commission comes out of the payee's share, the first
+    // charge of a scheduled start isn't a renewal
+    let commission = match referral_ais {
+        Some(_) if !starting => referral_commission(
+            subscription.referral_basis_points,
+            subscription.referral_renewals,
+            subscription.renewal_count,
+            amount,
+            periods,
+        )?,
+        _ => 0,
+    };
+    let payee_amount = total_amount - commission;
+
+    // the payee is paid in full unless the plan splits payments
+    let mut payouts = if recipient_ais.is_empty() {
+        vec![(payee_ai, payee_deposit_ai, payee_amount)]
+    } else {
+        recipient_ais
+            .into_iter()
+            .zip(split_amount(recipients, payee_amount)?)
+            .map(|((recipient_ai, recipient_deposit_ai), share)| {
+                (recipient_ai, recipient_deposit_ai, share)
+            })
+            .collect()
+    };
+    if let Some((referrer_ai, referrer_deposit_ai)) = referral_ais {
+        if commission > 0 {
+            payouts.push((referrer_ai, referrer_deposit_ai, commission));
+        }
+    }
+
+    // payments are grossed up by the deposit mint's transfer fee, so each
+    // recipient receives their share in full
+    let decimals = mint_decimals(deposit_mint_ai)?;
+    let transfer_fee = TransferFee::get(deposit_mint_ai, clock.epoch)?;
+    let gross_amount = transfer_fee.gross_total(payouts.iter().map(|(_, _, share)| *share))?;
+
+    // calculate payments
+    let fee = crank_fee(
+        &subscription.fee,
+        std::cmp::min(amount, subscription.max_amount_per_period),
+    )?;
+    let required_amount = gross_amount.checked_add(fee).ok_or(TokenError::Overflow)?;
+
+    // native SOL accounts are topped up by sending lamports, which count once
+    // synced
+    if *deposit_mint_ai.key == native_mint::id() {
+        let open_funding_ai = funding_ai.filter(|funding_ai| {
+            funding_ai.owner == token_program_ai.key && funding_ai.data_len() >= TokenAccount::LEN
+        });
+        for native_ai in std::iter::once(deposit_vault_ai).chain(open_funding_ai) {
+            invoke(
+                &spl_token::instruction::sync_native(&spl_token::id(), native_ai.key)?,
+                &[native_ai.clone(), token_program_ai.clone()],
+            )?;
+        }
+    }
+
+    // a delegated renewal pulls from the subscriber's account as far as the
+    // delegation allows, a renewal from the vault must leave one fee behind
+    // so the subscription can always compensate whoever expires it without
+    // touching rent
+    let deposit_vault = TokenAccount::unpack_from_slice(&deposit_vault_ai.try_borrow_data()?)?;
+    let (source_ai, available, required_amount) = match funding_ai {
+        Some(funding_ai) => (
+            funding_ai,
+            delegated_balance(funding_ai, token_program_ai.key, subscription_ai.key)?,
+            required_amount,
+        ),
+        None => (
+            deposit_vault_ai,
+            deposit_vault.amount,
+            required_amount
+                .checked_add(fee)
+                .ok_or(TokenError::Overflow)?,
+        ),
+    };
+    let funded = available >= required_amount;
+    let renewing = !ending && within_max && funded;
+    if !renewing && starting {
+        msg!("Unable to start, needs {} available.", required_amount);
+        return Err(TokenError::InsufficientFunds.into());
+    }
+    if !renewing && !subscription.active {
+        msg!("Already deactivated, unable to renew.");
+        return Err(SubscriptionError::AlreadyExpired.into());
+    }
+
+    // stay active through the grace period so the subscriber can top up,
+    // the caller is only paid once it can be expired
+    if !funded && within_max && !ending && in_grace_period {
+        msg!("Insufficient funds: past due until {}.", grace_end);
+        subscription.past_due = true;
+        subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
+        return Ok(());
+    }
+
+    // create caller token account if uninitialized
+    if caller_deposit_ai.data_len() == 0 {
+        msg!("Caller does not have associated token account to accept payment, initializing...");
+        invoke(
+            &create_associated_token_account(
+                caller_ai.key,
+                caller_ai.key,
+                &subscription.deposit_mint,
+                token_program_ai.key,
+            ),
+            &[
+                caller_ai.clone(),
+                caller_deposit_ai.clone(),
+                caller_ai.clone(),
+                deposit_mint_ai.clone(),
+                system_program_ai.clone(),
+                token_program_ai.clone(),
+                sysvar_rent_ai.clone(),
+                associated_token_program_ai.clone(),
+            ],
+        )?;
+    } else {
+        check_ata_initialized(
+            caller_deposit_ai,
+            caller_ai.key,
+            &subscription.deposit_mint,
+            token_program_ai.key,
+        )?;
+    }
+
+    // transfer to caller, a renewal pays the fee from where it pulls the
+    // payment, anything else from what's left in the vault
+    let (fee_source_ai, caller_amount) = if renewing {
+        (source_ai, fee)
+    } else {
+        (deposit_vault_ai, std::cmp::min(fee, deposit_vault.amount))
+    };
+    if caller_amount > 0 {
+        msg!("Transferring fee to caller...");
+        invoke_signed(
+            &transfer_checked(
+                token_program_ai.key,
+                fee_source_ai.key,
+                deposit_mint_ai.key,
+                caller_deposit_ai.key,
+                subscription_ai.key,
+                caller_amount,
+                decimals,
+            )?,
+            &[
+                fee_source_ai.clone(),
+                deposit_mint_ai.clone(),
+                caller_deposit_ai.clone(),
+                subscription_ai.clone(),
+                token_program_ai.clone(),
+            ],
+            &[subscription_seeds],
+        )?;
+    }
+
+    if let Some((owner_ai, owner_deposit_ai)) = refund_ais {
+        if subscription.cancel_at_period_end {
+            msg!("Cancelled at period end: deactivating subscription.");
+        } else {
+            msg!("Term over: deactivating subscription.");
+        }
+
+        // refund the rest of the vault to owner
+        let refund_amount = deposit_vault.amount - caller_amount;
+        if *deposit_mint_ai.key == native_mint::id() {
+            // the caller fronts up to a token account's rent in lamports and
+            // is repaid as much in wrapped SOL, anything more goes through a
+            // temporary account of the subscription closed to the owner
+            let account_rent = rent::Rent::get()?.minimum_balance(TokenAccount::LEN);
+            let swap_amount = std::cmp::min(refund_amount, account_rent);
+            if swap_amount > 0 {
+                invoke_signed(
+                    &transfer_checked(
+                        token_program_ai.key,
+                        deposit_vault_ai.key,
+                        deposit_mint_ai.key,
+                        caller_deposit_ai.key,
+                        subscription_ai.key,
+                        swap_amount,
+                        decimals,
+                    )?,
+                    &[
+                        deposit_vault_ai.clone(),
+                        deposit_mint_ai.clone(),
+                        caller_deposit_ai.clone(),
+                        subscription_ai.clone(),
+                        token_program_ai.clone(),
+                    ],
+                    &[subscription_seeds],
+                )?;
+            }
+
+            if refund_amount > account_rent {
+                msg!("Refunding {} to owner as native SOL...", refund_amount);
+                let refund_seeds = &[b"refund", subscription_ai.key.as_ref()];
+                let (_, refund_bump) = Pubkey::find_program_address(refund_seeds, program_id);
+                let refund_seeds = &[b"refund", subscription_ai.key.as_ref(), &[refund_bump]];
+                invoke_signed(
+                    &system_instruction::create_account(
+                        caller_ai.key,
+                        owner_deposit_ai.key,
+                        account_rent,
+                        TokenAccount::LEN as u64,
+                        token_program_ai.key,
+                    ),
+                    &[
+                        caller_ai.clone(),
+                        owner_deposit_ai.clone(),
+                        system_program_ai.clone(),
+                    ],
+                    &[refund_seeds],
+                )?;
+                invoke(
+                    &spl_token::instruction::initialize_account(
+                        &spl_token::id(),
+                        owner_deposit_ai.key,
+                        deposit_mint_ai.key,
+                        subscription_ai.key,
+                    )?,
+                    &[
+                        owner_deposit_ai.clone(),
+                        deposit_mint_ai.clone(),
+                        subscription_ai.clone(),
+                        sysvar_rent_ai.clone(),
+                        token_program_ai.clone(),
+                    ],
+                )?;
+                invoke_signed(
+                    &transfer_checked(
+                        token_program_ai.key,
+                        deposit_vault_ai.key,
+                        deposit_mint_ai.key,
+                        owner_deposit_ai.key,
+                        subscription_ai.key,
+                        refund_amount - account_rent,
+                        decimals,
+                    )?,
+                    &[
+                        deposit_vault_ai.clone(),
+                        deposit_mint_ai.clone(),
+                        owner_deposit_ai.clone(),
+                        subscription_ai.clone(),
+                        token_program_ai.clone(),
+                    ],
+                    &[subscription_seeds],
+                )?;
+                invoke_signed(
+                    &close_account(
+                        token_program_ai.key,
+                        owner_deposit_ai.key,
+                        owner_ai.key,
+                        subscription_ai.key,
+                    )?,
+                    &[
+                        owner_deposit_ai.clone(),
+                        owner_ai.clone(),
+                        subscription_ai.clone(),
+                        token_program_ai.clone(),
+                    ],
+                    &[subscription_seeds],
+                )?;
+            } else if refund_amount > 0 {
+                msg!("Refunding {} to owner as native SOL...", refund_amount);
+                invoke(
+                    &system_instruction::transfer(caller_ai.key, owner_ai.key, refund_amount),
+                    &[
+                        caller_ai.clone(),
+                        owner_ai.clone(),
+                        system_program_ai.clone(),
+                    ],
+                )?;
+            }
+        } else {
+            // create owner token account if uninitialized
+            if owner_deposit_ai.data_len() == 0 {
+                invoke(
+                    &create_associated_token_account(
+                        caller_ai.key,
+                        owner_ai.key,
+                        &subscription.deposit_mint,
+                        token_program_ai.key,
+                    ),
+                    &[
+                        caller_ai.clone(),
+                        owner_deposit_ai.clone(),
+                        owner_ai.clone(),
+                        deposit_mint_ai.clone(),
+                        system_program_ai.clone(),
+                        token_program_ai.clone(),
+                        sysvar_rent_ai.clone(),
+                        associated_token_program_ai.clone(),
+                    ],
+                )?;
+            } else {
+                check_ata_initialized(
+                    owner_deposit_ai,
+                    owner_ai.key,
+                    &subscription.deposit_mint,
+                    token_program_ai.key,
+                )?;
+            }
+
+            if refund_amount > 0 {
+                msg!("Refunding {} to owner...", refund_amount);
+                invoke_signed(
+                    &transfer_checked(
+                        token_program_ai.key,
+                        deposit_vault_ai.key,
+                        deposit_mint_ai.key,
+                        owner_deposit_ai.key,
+                        subscription_ai.key,
+                        refund_amount,
+                        decimals,
+                    )?,
+                    &[
+                        deposit_vault_ai.clone(),
+                        deposit_mint_ai.clone(),
+                        owner_deposit_ai.clone(),
+                        subscription_ai.clone(),
+                        token_program_ai.clone(),
+                    ],
+                    &[subscription_seeds],
+                )?;
+            }
+        }
+
+        subscription.active = false;
+        subscription.past_due = false;
+        subscription.cancel_at_period_end = false;
+        subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
+        return Ok(());
+    }
+
+    if !renewing {
+        msg!("Unable to renew: deactivating subscription.");
+        subscription.active = false;
+        subscription.past_due = false;
+        subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
+        return Ok(());
+    }
+
+    msg!("Sufficient funds: renewing subscription.");
+    msg!("Charging {} periods...", periods);
+
+    for (recipient_ai, recipient_deposit_ai, share) in payouts {
+        // create recipient token account if uninitialized
+        if recipient_deposit_ai.data_len() == 0 {
+            invoke(
+                &create_associated_token_account(
+                    caller_ai.key,
+                    recipient_ai.key,
+                    &subscription.deposit_mint,
+                    token_program_ai.key,
+                ),
+                &[
+                    caller_ai.clone(),
+                    recipient_deposit_ai.clone(),
+                    recipient_ai.clone(),
+                    deposit_mint_ai.clone(),
+                    system_program_ai.clone(),
+                    token_program_ai.clone(),
+                    sysvar_rent_ai.clone(),
+                    associated_token_program_ai.clone(),
+                ],
+            )?;
+        } else {
+            check_ata_initialized(
+                recipient_deposit_ai,
+                recipient_ai.key,
+                &subscription.deposit_mint,
+                token_program_ai.key,
+            )?;
+        }
+
+        if share == 0 {
+            continue;
+        }
+        msg!("Transferring {} to {}...", share, recipient_ai.key);
+        invoke_signed(
+            &transfer_checked(
+                token_program_ai.key,
+                source_ai.key,
+                deposit_mint_ai.key,
+                recipient_deposit_ai.key,
+                subscription_ai.key,
+                transfer_fee.gross_amount(share)?,
+                decimals,
+            )?,
+            &[
+                source_ai.clone(),
+                deposit_mint_ai.clone(),
+                recipient_deposit_ai.clone(),
+                subscription_ai.clone(),
+                token_program_ai.clone(),
+            ],
+            &[subscription_seeds],
+        )?;
+    }
+
+    msg!("Updating subscription metadata...");
+    subscription.active = true;
+    subscription.past_due = false;
+    subscription.trial = false;
+    // the first charge of a scheduled start isn't a renewal
+    if starting {
+        subscription.start_time = 0;
+    } else {
+        subscription.renewal_count += periods as u64;
+    }
+    // charged and skipped periods keep the billing anchor, re-anchoring keeps
+    // it only for a late renewal within the grace period
+    let (period_start, periods_passed) = match catch_up {
+        CatchUpPolicy::ChargeMissed => (subscription.next_renew_time, periods),
+        CatchUpPolicy::SkipMissed => (subscription.next_renew_time, periods_due),
+        CatchUpPolicy::Reanchor if in_grace_period => (subscription.next_renew_time, 1),
+        CatchUpPolicy::Reanchor => {
+            subscription.anchor_day = day_of_month(now);
+            (now, 1)
+        }
+    };
+    subscription.next_renew_time = advance_periods(
+        subscription.interval,
+        subscription.duration,
+        subscription.anchor_day,
+        period_start,
+        periods_passed,
+    )?;
+    subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
 
     Ok(())
 }
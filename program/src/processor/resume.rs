@@ -0,0 +1,79 @@
+use {
+    crate::{
+        calendar::day_of_month,
+        error::SubscriptionError,
+        state::Subscription2,
+        utils::{check_ata_initialized, check_signer, check_subscription_address, check_writable},
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        clock::Clock,
+        entrypoint::ProgramResult,
+        msg,
+        program_pack::Pack,
+        pubkey::Pubkey,
+        sysvar::Sysvar,
+    },
+    spl_token::state::Account as TokenAccount,
+};
+
+pub fn process_resume(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let owner_ai = next_account_info(accounts_iter)?;
+    let owner_subscription_token_ai = next_account_info(accounts_iter)?;
+    let subscription_ai = next_account_info(accounts_iter)?;
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(owner_ai)?;
+    check_writable(subscription_ai)?;
+
+    // PDAs
+    let mut subscription = Subscription2::try_from_slice(&subscription_ai.try_borrow_data()?)?;
+    check_subscription_address(
+        subscription_ai,
+        &subscription.origin_plan,
+        subscription.index,
+        Some(subscription.bump),
+        program_id,
+    )?;
+
+    // ownership is proven by holding the subscription token
+    check_ata_initialized(
+        owner_subscription_token_ai,
+        owner_ai.key,
+        &subscription.mint,
+        &spl_token::id(),
+    )?;
+    let owner_subscription_token =
+        TokenAccount::unpack_from_slice(&owner_subscription_token_ai.try_borrow_data()?)?;
+    if owner_subscription_token.amount == 0 {
+        return Err(SubscriptionError::NotOwner.into());
+    }
+
+    // LOGIC
+
+    if !subscription.paused {
+        return Err(SubscriptionError::NotPaused.into());
+    }
+
+    // carry over whatever was left of the paid period when it was paused
+    let now = Clock::get()?.unix_timestamp;
+    let remaining = std::cmp::max(subscription.next_renew_time - subscription.paused_at, 0);
+
+    msg!("Resuming subscription...");
+    subscription.paused = false;
+    subscription.paused_at = 0;
+    subscription.next_renew_time = now
+        .checked_add(remaining)
+        .ok_or(SubscriptionError::Overflow)?;
+    // calendar periods are anchored on the shifted renewal from now on
+    subscription.anchor_day = day_of_month(subscription.next_renew_time);
+    msg!("next_renew_time: {}", subscription.next_renew_time);
+    subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
@@ -0,0 +1,48 @@
+use {
+    crate::{
+        state::Plan,
+        utils::{check_plan_address, check_signer, check_writable},
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        entrypoint::ProgramResult,
+        msg,
+        program_error::ProgramError,
+        pubkey::Pubkey,
+    },
+};
+
+pub fn process_retire_plan(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let payee_ai = next_account_info(accounts_iter)?;
+    let plan_ai = next_account_info(accounts_iter)?;
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(payee_ai)?;
+    check_writable(plan_ai)?;
+
+    // PDAs
+    let mut plan = Plan::try_from_slice(&plan_ai.try_borrow_data()?)?;
+    check_plan_address(plan_ai, &plan.payee, plan.id, Some(plan.bump), program_id)?;
+    if plan.payee != *payee_ai.key {
+        msg!(
+            "Invalid payee:\tExpected: {}\tGot: {}",
+            plan.payee,
+            payee_ai.key
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // LOGIC
+
+    // only stops new subscriptions, existing ones keep renewing
+    msg!("Retiring plan...");
+    plan.active = false;
+    plan.serialize(&mut *plan_ai.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
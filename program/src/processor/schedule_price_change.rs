@@ -0,0 +1,85 @@
+use {
+    crate::{
+        billing::plan_amount,
+        error::SubscriptionError,
+        fee::check_fee_policy,
+        state::Plan,
+        utils::{check_plan_address, check_signer, check_writable},
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        clock::Clock,
+        entrypoint::ProgramResult,
+        msg,
+        program_error::ProgramError,
+        pubkey::Pubkey,
+        sysvar::Sysvar,
+    },
+};
+
+pub fn process_schedule_price_change(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    effective_time: i64,
+) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let payee_ai = next_account_info(accounts_iter)?;
+    let plan_ai = next_account_info(accounts_iter)?;
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(payee_ai)?;
+    check_writable(plan_ai)?;
+
+    // PDAs
+    let mut plan = Plan::try_from_slice(&plan_ai.try_borrow_data()?)?;
+    check_plan_address(plan_ai, &plan.payee, plan.id, Some(plan.bump), program_id)?;
+    if plan.payee != *payee_ai.key {
+        msg!(
+            "Invalid payee:\tExpected: {}\tGot: {}",
+            plan.payee,
+            payee_ai.key
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // LOGIC
+
+    if amount == 0 {
+        msg!("Plan amount must be positive.");
+        return Err(ProgramError::InvalidArgument);
+    }
+    check_fee_policy(&plan.fee, amount)?;
+
+    // subscribers get at least one full period of notice
+    let now = Clock::get()?.unix_timestamp;
+    let earliest_time = now
+        .checked_add(plan.duration)
+        .ok_or(SubscriptionError::Overflow)?;
+    if effective_time < earliest_time {
+        msg!(
+            "Price change too soon:\tEarliest: {}\tGot: {}",
+            earliest_time,
+            effective_time
+        );
+        return Err(SubscriptionError::InsufficientNotice.into());
+    }
+
+    // settle a previous change that is already in effect, a pending one is
+    // replaced
+    msg!(
+        "Scheduling price change to {} at {}...",
+        amount,
+        effective_time
+    );
+    plan.amount = plan_amount(&plan, now);
+    plan.pending_amount = amount;
+    plan.pending_amount_time = effective_time;
+    plan.serialize(&mut *plan_ai.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
@@ -0,0 +1,73 @@
+use {
+    crate::{
+        error::SubscriptionError,
+        state::Subscription2,
+        utils::{check_ata_initialized, check_signer, check_subscription_address, check_writable},
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        entrypoint::ProgramResult,
+        msg,
+        program_pack::Pack,
+        pubkey::Pubkey,
+    },
+    spl_token::state::Account as TokenAccount,
+};
+
+pub fn process_set_cancel_at_period_end(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    cancel_at_period_end: bool,
+) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let owner_ai = next_account_info(accounts_iter)?;
+    let owner_subscription_token_ai = next_account_info(accounts_iter)?;
+    let subscription_ai = next_account_info(accounts_iter)?;
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(owner_ai)?;
+    check_writable(subscription_ai)?;
+
+    // PDAs
+    let mut subscription = Subscription2::try_from_slice(&subscription_ai.try_borrow_data()?)?;
+    check_subscription_address(
+        subscription_ai,
+        &subscription.origin_plan,
+        subscription.index,
+        Some(subscription.bump),
+        program_id,
+    )?;
+
+    // ownership is proven by holding the subscription token
+    check_ata_initialized(
+        owner_subscription_token_ai,
+        owner_ai.key,
+        &subscription.mint,
+        &spl_token::id(),
+    )?;
+    let owner_subscription_token =
+        TokenAccount::unpack_from_slice(&owner_subscription_token_ai.try_borrow_data()?)?;
+    if owner_subscription_token.amount == 0 {
+        return Err(SubscriptionError::NotOwner.into());
+    }
+
+    // LOGIC
+
+    if !subscription.active {
+        return Err(SubscriptionError::AlreadyExpired.into());
+    }
+
+    if cancel_at_period_end {
+        msg!("Cancelling subscription at period end...");
+    } else {
+        msg!("Keeping subscription past period end...");
+    }
+    subscription.cancel_at_period_end = cancel_at_period_end;
+    subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
@@ -0,0 +1,152 @@
+use {
+    crate::{
+        error::SubscriptionError,
+        metadata::{
+            create_metadata_account, find_metadata_address, mpl_token_metadata,
+            subscription_metadata, update_metadata_account,
+        },
+        state::{Plan, Subscription2},
+        utils::{
+            check_ata_initialized, check_plan_address, check_program_id, check_signer,
+            check_subscription_address, check_writable,
+        },
+    },
+    borsh::BorshDeserialize,
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        entrypoint::ProgramResult,
+        msg,
+        program::invoke_signed,
+        program_error::ProgramError,
+        program_pack::Pack,
+        pubkey::Pubkey,
+        system_program,
+        sysvar::rent,
+    },
+    spl_token::{error::TokenError, state::Account as TokenAccount},
+};
+
+pub fn process_sync_metadata(program_id: &Pubkey, accounts: &[AccountInfo]) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let payer_ai = next_account_info(accounts_iter)?;
+    let owner_ai = next_account_info(accounts_iter)?;
+    let owner_subscription_token_ai = next_account_info(accounts_iter)?;
+    let subscription_ai = next_account_info(accounts_iter)?;
+    let plan_ai = next_account_info(accounts_iter)?;
+    let subscription_mint_ai = next_account_info(accounts_iter)?;
+    let token_metadata_ai = next_account_info(accounts_iter)?;
+
+    let system_program_ai = next_account_info(accounts_iter)?;
+    let sysvar_rent_ai = next_account_info(accounts_iter)?;
+    let token_metadata_program_ai = next_account_info(accounts_iter)?;
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(payer_ai)?;
+    check_writable(payer_ai)?;
+    check_signer(owner_ai)?;
+    check_writable(token_metadata_ai)?;
+
+    // PDAs
+    let subscription = Subscription2::try_from_slice(&subscription_ai.try_borrow_data()?)?;
+    check_subscription_address(
+        subscription_ai,
+        &subscription.origin_plan,
+        subscription.index,
+        Some(subscription.bump),
+        program_id,
+    )?;
+    let plan_key = subscription.origin_plan;
+    let subscription_seeds = &[
+        b"subscription_metadata",
+        plan_key.as_ref(),
+        &subscription.index.to_le_bytes(),
+        &[subscription.bump],
+    ];
+
+    if *plan_ai.key != subscription.plan {
+        msg!("Invalid plan: {}", plan_ai.key);
+        return Err(ProgramError::InvalidArgument);
+    }
+    let plan = Plan::try_from_slice(&plan_ai.try_borrow_data()?)?;
+    check_plan_address(plan_ai, &plan.payee, plan.id, Some(plan.bump), program_id)?;
+
+    if *subscription_mint_ai.key != subscription.mint {
+        msg!("Invalid subscription mint: {}", subscription_mint_ai.key);
+        return Err(TokenError::MintMismatch.into());
+    }
+    let (token_metadata, _) = find_metadata_address(subscription_mint_ai.key);
+    if *token_metadata_ai.key != token_metadata {
+        msg!(
+            "Invalid token metadata:\tExpected: {}\tGot: {}",
+            token_metadata,
+            token_metadata_ai.key
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // ownership is proven by holding the subscription token
+    check_ata_initialized(
+        owner_subscription_token_ai,
+        owner_ai.key,
+        &subscription.mint,
+        &spl_token::id(),
+    )?;
+    let owner_subscription_token =
+        TokenAccount::unpack_from_slice(&owner_subscription_token_ai.try_borrow_data()?)?;
+    if owner_subscription_token.amount == 0 {
+        return Err(SubscriptionError::NotOwner.into());
+    }
+
+    // programs
+    check_program_id(system_program_ai, &system_program::id())?;
+    check_program_id(sysvar_rent_ai, &rent::id())?;
+    check_program_id(token_metadata_program_ai, &mpl_token_metadata::id())?;
+
+    // LOGIC
+
+    // the subscription is both mint and update authority. There's no master
+    // edition, it would take the mint authority over for good and the index
+    // could never mint an ownership token again
+    let data = subscription_metadata(&subscription, &plan);
+    msg!("uri: {}", data.uri);
+    if token_metadata_ai.data_len() == 0 {
+        msg!("Creating subscription ownership token metadata...");
+        invoke_signed(
+            &create_metadata_account(
+                token_metadata_ai.key,
+                subscription_mint_ai.key,
+                subscription_ai.key,
+                payer_ai.key,
+                subscription_ai.key,
+                data,
+            ),
+            &[
+                token_metadata_ai.clone(),
+                subscription_mint_ai.clone(),
+                subscription_ai.clone(),
+                payer_ai.clone(),
+                subscription_ai.clone(),
+                system_program_ai.clone(),
+                sysvar_rent_ai.clone(),
+                token_metadata_program_ai.clone(),
+            ],
+            &[subscription_seeds],
+        )?;
+    } else {
+        msg!("Updating subscription ownership token metadata...");
+        invoke_signed(
+            &update_metadata_account(token_metadata_ai.key, subscription_ai.key, data),
+            &[
+                token_metadata_ai.clone(),
+                subscription_ai.clone(),
+                token_metadata_program_ai.clone(),
+            ],
+            &[subscription_seeds],
+        )?;
+    }
+
+    Ok(())
+}
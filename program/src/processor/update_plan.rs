@@ -0,0 +1,66 @@
+use {
+    crate::{
+        fee::check_fee_policy,
+        state::{FeePolicy, Plan, PlanPolicy},
+        utils::{
+            check_plan_address, check_plan_policy, check_signer, check_writable, to_fixed_bytes,
+        },
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        entrypoint::ProgramResult,
+        msg,
+        program_error::ProgramError,
+        pubkey::Pubkey,
+    },
+};
+
+pub fn process_update_plan(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    fee: &FeePolicy,
+    policy: &PlanPolicy,
+    name: &str,
+    uri: &str,
+) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let payee_ai = next_account_info(accounts_iter)?;
+    let plan_ai = next_account_info(accounts_iter)?;
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(payee_ai)?;
+    check_writable(plan_ai)?;
+
+    // PDAs
+    let mut plan = Plan::try_from_slice(&plan_ai.try_borrow_data()?)?;
+    check_plan_address(plan_ai, &plan.payee, plan.id, Some(plan.bump), program_id)?;
+    if plan.payee != *payee_ai.key {
+        msg!(
+            "Invalid payee:\tExpected: {}\tGot: {}",
+            plan.payee,
+            payee_ai.key
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+
+    // LOGIC
+
+    // existing subscriptions keep the fee they were created with, policy
+    // changes apply to them from now on
+    check_plan_policy(policy, plan.duration)?;
+    check_fee_policy(fee, plan.amount)?;
+    if plan.pending_amount_time != 0 {
+        check_fee_policy(fee, plan.pending_amount)?;
+    }
+    plan.fee = *fee;
+    plan.policy = *policy;
+    plan.name = to_fixed_bytes(name)?;
+    plan.uri = to_fixed_bytes(uri)?;
+    plan.serialize(&mut *plan_ai.try_borrow_mut_data()?)?;
+
+    Ok(())
+}
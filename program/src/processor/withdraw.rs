@@ -0,0 +1,153 @@
+use {
+    crate::{
+        error::SubscriptionError,
+        state::Subscription,
+        utils::{check_ata_initialized, check_pda, check_program_id, check_signer, check_writable},
+    },
+    borsh::{BorshDeserialize, BorshSerialize},
+    solana_program::{
+        account_info::{next_account_info, AccountInfo},
+        entrypoint::ProgramResult,
+        msg,
+        program::invoke_signed,
+        program_pack::Pack,
+        pubkey::Pubkey,
+    },
+    spl_token::{error::TokenError, state::Account as TokenAccount},
+};
+
+pub fn process_withdraw(
+    program_id: &Pubkey,
+    accounts: &[AccountInfo],
+    amount: u64,
+    count: u64,
+    cancel: bool,
+) -> ProgramResult {
+    // GET ACCOUNTS
+    let accounts_iter = &mut accounts.iter();
+
+    let owner_ai = next_account_info(accounts_iter)?;
+    let owner_deposit_ai = next_account_info(accounts_iter)?;
+    let owner_subscription_token_ai = next_account_info(accounts_iter)?;
+    let deposit_vault_ai = next_account_info(accounts_iter)?;
+    let subscription_ai = next_account_info(accounts_iter)?;
+
+    let token_program_ai = next_account_info(accounts_iter)?;
+
+    // VALIDATE ACCOUNTS
+    // signer/writable
+    check_signer(owner_ai)?;
+    check_writable(owner_deposit_ai)?;
+    check_writable(deposit_vault_ai)?;
+    check_writable(subscription_ai)?;
+
+    // PDAs
+    // subscriptions that were never renewed are stored with trailing zeroes
+    // where the mint would be, so don't require the whole buffer to be consumed
+    let mut subscription = Subscription::deserialize(&mut &subscription_ai.try_borrow_data()?[..])?;
+
+    let payee = &subscription.payee;
+    let subscription_seeds = &[
+        b"subscription_metadata",
+        payee.as_ref(),
+        &subscription.amount.to_le_bytes(),
+        &subscription.duration.to_le_bytes(),
+        &count.to_le_bytes(),
+    ];
+    check_pda(subscription_ai, subscription_seeds, program_id)?;
+    let (_, subscription_bump) = Pubkey::find_program_address(subscription_seeds, program_id);
+    let subscription_seeds = &[
+        b"subscription_metadata",
+        payee.as_ref(),
+        &subscription.amount.to_le_bytes(),
+        &subscription.duration.to_le_bytes(),
+        &count.to_le_bytes(),
+        &[subscription_bump],
+    ];
+
+    // token accounts, v1 vaults only ever hold spl-token deposits since
+    // Initialize rejects spl-token-2022 mints
+    check_ata_initialized(
+        deposit_vault_ai,
+        subscription_ai.key,
+        &subscription.deposit_mint,
+        &spl_token::id(),
+    )?;
+    check_ata_initialized(
+        owner_deposit_ai,
+        owner_ai.key,
+        &subscription.deposit_mint,
+        &spl_token::id(),
+    )?;
+
+    // ownership is proven by holding a token from the current mint
+    let current_mint = match subscription.mint {
+        Some(mint) => mint,
+        None => {
+            msg!("Subscription has no ownership token yet.");
+            return Err(SubscriptionError::NotOwner.into());
+        }
+    };
+    check_ata_initialized(
+        owner_subscription_token_ai,
+        owner_ai.key,
+        &current_mint,
+        &spl_token::id(),
+    )?;
+    let owner_subscription_token =
+        TokenAccount::unpack_from_slice(&owner_subscription_token_ai.try_borrow_data()?)?;
+    if owner_subscription_token.amount == 0 {
+        return Err(SubscriptionError::NotOwner.into());
+    }
+
+    // programs
+    check_program_id(token_program_ai, &spl_token::id())?;
+
+    // LOGIC
+
+    // the crank fee is paid out of `amount` on renewal, so keeping `amount`
+    // in the vault is enough to cover both the next renewal and its fee
+    let deposit_vault = TokenAccount::unpack_from_slice(&deposit_vault_ai.try_borrow_data()?)?;
+    let remaining = deposit_vault
+        .amount
+        .checked_sub(amount)
+        .ok_or(TokenError::InsufficientFunds)?;
+    msg!("vault balance: {}", deposit_vault.amount);
+    if subscription.active && !cancel && remaining < subscription.amount {
+        msg!(
+            "Withdrawal would leave {} in vault, next renewal needs {}.",
+            remaining,
+            subscription.amount
+        );
+        return Err(SubscriptionError::InsufficientReserve.into());
+    }
+
+    msg!("Transferring funds to owner...");
+    invoke_signed(
+        &spl_token::instruction::transfer(
+            &spl_token::id(),
+            deposit_vault_ai.key,
+            owner_deposit_ai.key,
+            subscription_ai.key,
+            &[],
+            amount,
+        )?,
+        &[
+            deposit_vault_ai.clone(),
+            owner_deposit_ai.clone(),
+            subscription_ai.clone(),
+            token_program_ai.clone(),
+        ],
+        &[subscription_seeds],
+    )?;
+
+    // cancelling deactivates the subscription so cranks don't expire it a
+    // second time and close its accounts
+    if cancel && subscription.active {
+        msg!("Cancelling subscription...");
+        subscription.active = false;
+        subscription.serialize(&mut *subscription_ai.try_borrow_mut_data()?)?;
+    }
+
+    Ok(())
+}
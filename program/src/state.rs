@@ -22,11 +22,105 @@ pub struct Counter {
     pub count: u64,
 }
 
+/// Fee paid to renewers: a fixed amount plus a percentage of the renewal amount.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub struct FeePolicy {
+    pub fixed: u64,
+    pub basis_points: u16,
+}
+
+/// Payee controlled rules for subscriptions created from a plan.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub struct PlanPolicy {
+    pub allow_pause: bool,
+    pub grace_period: i64, // seconds a short vault stays active past due
+    pub trial_periods: u32, // free periods for a subscriber's first subscription
+    pub catch_up: CatchUpPolicy,
+    pub prepay_discounts: [PrepayDiscount; PREPAY_DISCOUNT_TIERS],
+    pub referral_basis_points: u16, // referrer's share of renewals, 0 if no referrals
+    pub referral_renewals: u32,     // renewals the referrer is paid for, 0 if forever
+}
+
+pub const PREPAY_DISCOUNT_TIERS: usize = 3;
+
+/// Discount for paying at least `min_periods` periods up front, unused if 0.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct PrepayDiscount {
+    pub min_periods: u32,
+    pub basis_points: u16,
+}
+
+/// What a renewal does about periods missed while no one renewed.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub enum CatchUpPolicy {
+    ChargeMissed, // charge every period that came due, keeping the anchor
+    SkipMissed,   // charge one period, keeping the anchor
+    Reanchor,     // charge one period starting now, unless within the grace period
+}
+
+/// How a plan's billing periods are measured. Calendar intervals renew on
+/// the subscription's anchor day, with `duration` as the nominal period.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone, Copy, PartialEq)]
+pub enum BillingInterval {
+    Duration, // every `duration` seconds
+    Month,
+    Year,
+}
+
+pub const MAX_SPLIT_RECIPIENTS: usize = 3;
+
+/// A share of every payment under a plan, paid to `recipient`'s deposit token
+/// account. Unused if `basis_points` is 0.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Default, Clone, Copy, PartialEq)]
+pub struct SplitRecipient {
+    pub recipient: Pubkey,
+    pub basis_points: u16,
+}
+
+pub const PLAN_NAME_LEN: usize = 32;
+pub const PLAN_URI_LEN: usize = 128;
+
+/// A payee's offering that subscriptions are created from.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct Plan {
+    pub bump: u8,
+    pub active: bool, // false once retired, no new subscriptions
+
+    pub payee: Pubkey,
+    pub id: u64,
+
+    pub deposit_mint: Pubkey,
+    pub amount: u64,
+    pub duration: i64,
+    pub interval: BillingInterval,
+    pub fee: FeePolicy,
+    pub policy: PlanPolicy,
+    pub splits: [SplitRecipient; MAX_SPLIT_RECIPIENTS], // all unused pays the payee
+
+    // scheduled price change, 0 if none
+    pub pending_amount: u64,
+    pub pending_amount_time: i64,
+
+    pub count: u64, // number of subscription indexes ever used
+
+    pub name: [u8; PLAN_NAME_LEN], // utf-8, zero padded
+    pub uri: [u8; PLAN_URI_LEN],   // utf-8, zero padded
+}
+
+/// Marks that a subscriber has used a plan's free trial.
+#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
+pub struct TrialRecord {
+    pub bump: u8,
+}
+
 #[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
 pub struct Subscription2 {
     pub bump: u8,
+    pub mint_bump: u8,
     pub active: bool,
 
+    pub plan: Pubkey,        // current plan, can change with ChangePlan
+    pub origin_plan: Pubkey, // plan the address and index were derived from
     pub mint: Pubkey,
     pub deposit_vault: Pubkey,
     pub deposit_mint: Pubkey,
@@ -34,12 +128,40 @@ pub struct Subscription2 {
     pub payee: Pubkey,
     pub amount: u64,
     pub duration: i64,
+    pub interval: BillingInterval,
+    pub anchor_day: u8, // day of month calendar periods renew on
     pub index: u64,
     pub next_renew_time: i64,
-}
+    pub fee: FeePolicy,
 
-#[derive(BorshSerialize, BorshDeserialize, Debug, Clone)]
-pub struct Counter2 {
-    pub bump: u8,
-    pub count: u64,
+    pub paused: bool,
+    pub paused_at: i64, // when the current pause started, 0 if not paused
+
+    pub cancel_at_period_end: bool, // refunds go to the token holder
+
+    pub past_due: bool, // renewal failed, waiting out the plan's grace period
+    pub trial: bool,    // in a free trial, nothing paid yet
+
+    // plan price change charged from the first renewal after it's effective,
+    // 0 if none
+    pub pending_amount: u64,
+    pub pending_amount_time: i64,
+
+    pub max_amount_per_period: u64, // most the subscriber agreed to be charged
+
+    // fixed term, max_renewals and end_time are 0 if unlimited
+    pub renewal_count: u64,
+    pub max_renewals: u64,
+    pub end_time: i64,
+
+    pub start_time: i64, // scheduled start, 0 once started
+
+    // referrer paid a share of renewals, default if none
+    pub referrer: Pubkey,
+    pub referral_basis_points: u16,
+    pub referral_renewals: u32, // renewals the referrer is paid for, 0 if forever
+
+    // subscriber token account renewals pull from as the PDA's delegate,
+    // default if paid from the deposit vault
+    pub funding_account: Pubkey,
 }
\ No newline at end of file
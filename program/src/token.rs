@@ -0,0 +1,536 @@
+use {
+    crate::fee::BASIS_POINTS_DENOMINATOR,
+    solana_program::{
+        account_info::AccountInfo,
+        entrypoint::ProgramResult,
+        instruction::{AccountMeta, Instruction},
+        msg,
+        program_error::ProgramError,
+        program_option::COption,
+        program_pack::Pack,
+        pubkey::Pubkey,
+        system_program, sysvar,
+    },
+    spl_token::{
+        error::TokenError,
+        state::{Account as TokenAccount, AccountState, Mint},
+    },
+};
+
+/// The spl-token-2022 program, which can own deposit mints as well as
+/// spl-token. Its base account layouts and instructions match spl-token.
+pub mod spl_token_2022 {
+    solana_program::declare_id!("TokenzQdBNbLqP5VEhdkAS6EPFLC1PaBqxxKTqbA7k4");
+}
+
+// spl-token-2022 extensions follow the base account, padded to a token
+// account's length, and an account type byte, as type-length-value entries
+const ACCOUNT_TYPE_OFFSET: usize = TokenAccount::LEN;
+const ACCOUNT_TYPE_MINT: u8 = 1;
+const EXTENSION_TRANSFER_FEE_CONFIG: u16 = 1;
+const EXTENSION_DEFAULT_ACCOUNT_STATE: u16 = 6;
+const EXTENSION_NON_TRANSFERABLE: u16 = 9;
+const EXTENSION_PERMANENT_DELEGATE: u16 = 12;
+const EXTENSION_TRANSFER_HOOK: u16 = 14;
+
+// TransferFeeConfig: two authorities and the withheld amount, then the older
+// and newer fees, each an epoch, maximum fee and basis points
+const OLDER_TRANSFER_FEE_OFFSET: usize = 32 + 32 + 8;
+const TRANSFER_FEE_LEN: usize = 8 + 8 + 2;
+
+/// Returns whether `program_id` is a token program deposit mints can use.
+pub fn is_token_program(program_id: &Pubkey) -> bool {
+    *program_id == spl_token::id() || *program_id == spl_token_2022::id()
+}
+
+/// Checks `token_program` is the token program that owns `mint`.
+pub fn check_token_program(token_program: &AccountInfo, mint: &AccountInfo) -> ProgramResult {
+    if !is_token_program(mint.owner) {
+        msg!("Mint not owned by a token program: {}", mint.key);
+        return Err(ProgramError::IllegalOwner);
+    }
+    if token_program.key != mint.owner {
+        msg!(
+            "Invalid token program:\tExpected: {}\tGot: {}",
+            mint.owner,
+            token_program.key
+        );
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+/// Checks `mint` is a spl-token mint. v1 subscriptions predate spl-token-2022
+/// support and move deposits with spl-token's unchecked transfers.
+pub fn check_spl_token_mint(mint: &AccountInfo) -> ProgramResult {
+    if *mint.owner != spl_token::id() {
+        msg!("Only spl-token deposit mints are supported: {}", mint.key);
+        return Err(ProgramError::IncorrectProgramId);
+    }
+    Ok(())
+}
+
+/// Returns the extensions of a mint's account data as `(type, value)` pairs,
+/// none for a spl-token mint or a spl-token-2022 mint without any.
+fn mint_extensions(data: &[u8]) -> Result<Vec<(u16, &[u8])>, ProgramError> {
+    if data.len() <= Mint::LEN {
+        return Ok(vec![]);
+    }
+    if data.len() <= ACCOUNT_TYPE_OFFSET || data[ACCOUNT_TYPE_OFFSET] != ACCOUNT_TYPE_MINT {
+        return Err(ProgramError::InvalidAccountData);
+    }
+
+    let mut extensions = vec![];
+    let mut offset = ACCOUNT_TYPE_OFFSET + 1;
+    while offset + 4 <= data.len() {
+        let extension_type = u16::from_le_bytes([data[offset], data[offset + 1]]);
+        let len = u16::from_le_bytes([data[offset + 2], data[offset + 3]]) as usize;
+        // the rest of the account is unused
+        if extension_type == 0 {
+            break;
+        }
+        let value = data
+            .get(offset + 4..offset + 4 + len)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        extensions.push((extension_type, value));
+        offset += 4 + len;
+    }
+    Ok(extensions)
+}
+
+/// Checks `mint` can be a plan's deposit mint: owned by a token program and
+/// without spl-token-2022 extensions that would stop payments, like
+/// non-transferable tokens, transfer hooks needing extra accounts, or token
+/// accounts frozen by default, or let a permanent delegate empty vaults.
+pub fn check_deposit_mint(mint: &AccountInfo) -> ProgramResult {
+    if !is_token_program(mint.owner) {
+        msg!("Mint not owned by a token program: {}", mint.key);
+        return Err(ProgramError::IllegalOwner);
+    }
+    let data = mint.try_borrow_data()?;
+    if data.len() < Mint::LEN || !Mint::unpack_from_slice(&data)?.is_initialized {
+        msg!("Invalid deposit mint: {}", mint.key);
+        return Err(ProgramError::InvalidAccountData);
+    }
+    for (extension_type, value) in mint_extensions(&data)? {
+        let supported = match extension_type {
+            EXTENSION_NON_TRANSFERABLE | EXTENSION_PERMANENT_DELEGATE => false,
+            // authority, then the hook program
+            EXTENSION_TRANSFER_HOOK => value
+                .get(32..64)
+                .map_or(false, |program_id| program_id.iter().all(|byte| *byte == 0)),
+            // frozen is 2
+            EXTENSION_DEFAULT_ACCOUNT_STATE => value.first() != Some(&2),
+            _ => true,
+        };
+        if !supported {
+            msg!("Unsupported mint extension: {}", extension_type);
+            return Err(TokenError::InvalidMint.into());
+        }
+    }
+    Ok(())
+}
+
+/// Returns whether `mint` has a transfer fee config, whose withheld fees must
+/// be harvested before token accounts can be closed.
+pub fn has_transfer_fee_config(mint: &AccountInfo) -> Result<bool, ProgramError> {
+    Ok(mint_extensions(&mint.try_borrow_data()?)?
+        .iter()
+        .any(|(extension_type, _)| *extension_type == EXTENSION_TRANSFER_FEE_CONFIG))
+}
+
+/// Returns how much `delegate` can pull from `account`, zero unless it's an
+/// open, unfrozen token account of `token_program` delegated to `delegate`.
+/// A closed or reassigned account is simply unfunded.
+pub fn delegated_balance(
+    account: &AccountInfo,
+    token_program: &Pubkey,
+    delegate: &Pubkey,
+) -> Result<u64, ProgramError> {
+    if account.owner != token_program || account.data_len() < TokenAccount::LEN {
+        return Ok(0);
+    }
+    let token_account = TokenAccount::unpack_from_slice(&account.try_borrow_data()?)?;
+    if token_account.state != AccountState::Initialized
+        || token_account.delegate != COption::Some(*delegate)
+    {
+        return Ok(0);
+    }
+    Ok(std::cmp::min(
+        token_account.amount,
+        token_account.delegated_amount,
+    ))
+}
+
+/// Returns the decimals of `mint`, needed for checked transfers.
+pub fn mint_decimals(mint: &AccountInfo) -> Result<u8, ProgramError> {
+    Ok(Mint::unpack_from_slice(&mint.try_borrow_data()?)?.decimals)
+}
+
+/// A mint's fee withheld from every transfer, zero for mints without one.
+#[derive(Debug, Default, Clone, Copy, PartialEq)]
+pub struct TransferFee {
+    pub basis_points: u16,
+    pub maximum_fee: u64,
+}
+
+impl TransferFee {
+    /// Returns the transfer fee of `mint` in effect at `epoch`.
+    pub fn get(mint: &AccountInfo, epoch: u64) -> Result<Self, ProgramError> {
+        Self::from_mint_data(&mint.try_borrow_data()?, epoch)
+    }
+
+    /// Returns the transfer fee in effect at `epoch` in a mint's account data.
+    pub fn from_mint_data(data: &[u8], epoch: u64) -> Result<Self, ProgramError> {
+        let config = match mint_extensions(data)?
+            .into_iter()
+            .find(|(extension_type, _)| *extension_type == EXTENSION_TRANSFER_FEE_CONFIG)
+        {
+            Some((_, config)) => config,
+            None => return Ok(Self::default()),
+        };
+
+        // the newer fee takes over from its epoch
+        let read_u64 = |offset: usize| -> Result<u64, ProgramError> {
+            let bytes = config
+                .get(offset..offset + 8)
+                .ok_or(ProgramError::InvalidAccountData)?;
+            let mut buffer = [0; 8];
+            buffer.copy_from_slice(bytes);
+            Ok(u64::from_le_bytes(buffer))
+        };
+        let newer_offset = OLDER_TRANSFER_FEE_OFFSET + TRANSFER_FEE_LEN;
+        let offset = if epoch >= read_u64(newer_offset)? {
+            newer_offset
+        } else {
+            OLDER_TRANSFER_FEE_OFFSET
+        };
+        let basis_points = config
+            .get(offset + 16..offset + 18)
+            .ok_or(ProgramError::InvalidAccountData)?;
+        Ok(Self {
+            basis_points: u16::from_le_bytes([basis_points[0], basis_points[1]]),
+            maximum_fee: read_u64(offset + 8)?,
+        })
+    }
+
+    /// Calculates the fee withheld from a transfer of `amount`, rounded up
+    /// and capped at the maximum fee.
+    pub fn fee(&self, amount: u64) -> Result<u64, ProgramError> {
+        if self.basis_points == 0 || amount == 0 {
+            return Ok(0);
+        }
+        let fee = (amount as u128)
+            .checked_mul(self.basis_points as u128)
+            .and_then(|fee| fee.checked_add(BASIS_POINTS_DENOMINATOR as u128 - 1))
+            .ok_or(TokenError::Overflow)?
+            / BASIS_POINTS_DENOMINATOR as u128;
+        Ok(std::cmp::min(fee as u64, self.maximum_fee))
+    }
+
+    /// Calculates how much to transfer for the recipient to receive
+    /// `amount` after the fee.
+    pub fn gross_amount(&self, amount: u64) -> Result<u64, ProgramError> {
+        if self.basis_points == 0 || amount == 0 {
+            return Ok(amount);
+        }
+        let capped = amount
+            .checked_add(self.maximum_fee)
+            .ok_or(TokenError::Overflow)?;
+        if self.basis_points as u64 >= BASIS_POINTS_DENOMINATOR {
+            return Ok(capped);
+        }
+        let denominator = (BASIS_POINTS_DENOMINATOR - self.basis_points as u64) as u128;
+        let gross =
+            ((amount as u128) * BASIS_POINTS_DENOMINATOR as u128 + denominator - 1) / denominator;
+        if gross >= capped as u128 {
+            return Ok(capped);
+        }
+        Ok(gross as u64)
+    }
+
+    /// Calculates how much to transfer in total for each recipient to
+    /// receive their amount after the fee.
+    pub fn gross_total<I: IntoIterator<Item = u64>>(
+        &self,
+        amounts: I,
+    ) -> Result<u64, ProgramError> {
+        amounts.into_iter().try_fold(0u64, |total, amount| {
+            total
+                .checked_add(self.gross_amount(amount)?)
+                .ok_or_else(|| TokenError::Overflow.into())
+        })
+    }
+}
+
+/// Returns the associated token account of `wallet` for `mint` under
+/// `token_program`.
+pub fn get_associated_token_address(
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Pubkey {
+    Pubkey::find_program_address(
+        &[wallet.as_ref(), token_program.as_ref(), mint.as_ref()],
+        &spl_associated_token_account::id(),
+    )
+    .0
+}
+
+/// Creates an associated token account creation instruction for `mint` under
+/// `token_program`.
+pub fn create_associated_token_account(
+    funder: &Pubkey,
+    wallet: &Pubkey,
+    mint: &Pubkey,
+    token_program: &Pubkey,
+) -> Instruction {
+    Instruction {
+        program_id: spl_associated_token_account::id(),
+        accounts: vec![
+            AccountMeta::new(*funder, true),
+            AccountMeta::new(
+                get_associated_token_address(wallet, mint, token_program),
+                false,
+            ),
+            AccountMeta::new_readonly(*wallet, false),
+            AccountMeta::new_readonly(*mint, false),
+            AccountMeta::new_readonly(system_program::id(), false),
+            AccountMeta::new_readonly(*token_program, false),
+            AccountMeta::new_readonly(sysvar::rent::id(), false),
+        ],
+        data: vec![],
+    }
+}
+
+/// Creates a `TransferChecked` instruction under `token_program`, which
+/// spl-token-2022 requires for mints with a transfer fee.
+pub fn transfer_checked(
+    token_program: &Pubkey,
+    source: &Pubkey,
+    mint: &Pubkey,
+    destination: &Pubkey,
+    authority: &Pubkey,
+    amount: u64,
+    decimals: u8,
+) -> Result<Instruction, ProgramError> {
+    let mut instruction = spl_token::instruction::transfer_checked(
+        &spl_token::id(),
+        source,
+        mint,
+        destination,
+        authority,
+        &[],
+        amount,
+        decimals,
+    )?;
+    instruction.program_id = *token_program;
+    Ok(instruction)
+}
+
+/// Creates a `CloseAccount` instruction under `token_program`.
+pub fn close_account(
+    token_program: &Pubkey,
+    account: &Pubkey,
+    destination: &Pubkey,
+    owner: &Pubkey,
+) -> Result<Instruction, ProgramError> {
+    let mut instruction =
+        spl_token::instruction::close_account(&spl_token::id(), account, destination, owner, &[])?;
+    instruction.program_id = *token_program;
+    Ok(instruction)
+}
+
+/// Creates a spl-token-2022 instruction moving the transfer fees withheld in
+/// `sources` to `mint`, which lets the token accounts be closed.
+pub fn harvest_withheld_tokens_to_mint(mint: &Pubkey, sources: &[&Pubkey]) -> Instruction {
+    let mut accounts = vec![AccountMeta::new(*mint, false)];
+    accounts.extend(
+        sources
+            .iter()
+            .map(|source| AccountMeta::new(**source, false)),
+    );
+    Instruction {
+        program_id: spl_token_2022::id(),
+        accounts,
+        // TransferFeeExtension, HarvestWithheldTokensToMint
+        data: vec![26, 4],
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+
+    /// Mint account data with a transfer fee config switching from `older`
+    /// to `newer` at `newer_epoch`.
+    fn mint_data(older: TransferFee, newer: TransferFee, newer_epoch: u64) -> Vec<u8> {
+        let mut data = vec![0; ACCOUNT_TYPE_OFFSET];
+        data.push(ACCOUNT_TYPE_MINT);
+        data.extend_from_slice(&EXTENSION_TRANSFER_FEE_CONFIG.to_le_bytes());
+        data.extend_from_slice(&108u16.to_le_bytes());
+        data.extend_from_slice(&[0; 32 + 32 + 8]);
+        for (epoch, fee) in [(0, older), (newer_epoch, newer)] {
+            data.extend_from_slice(&epoch.to_le_bytes());
+            data.extend_from_slice(&fee.maximum_fee.to_le_bytes());
+            data.extend_from_slice(&fee.basis_points.to_le_bytes());
+        }
+        data
+    }
+
+    /// Initialized spl-token-2022 mint account data with `extensions`.
+    fn extended_mint_data(extensions: &[(u16, Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![0; ACCOUNT_TYPE_OFFSET];
+        Mint {
+            is_initialized: true,
+            ..Mint::default()
+        }
+        .pack_into_slice(&mut data[..Mint::LEN]);
+        data.push(ACCOUNT_TYPE_MINT);
+        for (extension_type, value) in extensions {
+            data.extend_from_slice(&extension_type.to_le_bytes());
+            data.extend_from_slice(&(value.len() as u16).to_le_bytes());
+            data.extend_from_slice(value);
+        }
+        data
+    }
+
+    fn check_mint_data(owner: &Pubkey, mut data: Vec<u8>) -> ProgramResult {
+        let key = Pubkey::new_unique();
+        let mut lamports = 0;
+        let mint = AccountInfo::new(
+            &key,
+            false,
+            false,
+            &mut lamports,
+            &mut data,
+            owner,
+            false,
+            0,
+        );
+        check_deposit_mint(&mint)
+    }
+
+    #[test]
+    fn test_check_deposit_mint() {
+        let mut data = vec![0; Mint::LEN];
+        Mint {
+            is_initialized: true,
+            ..Mint::default()
+        }
+        .pack_into_slice(&mut data);
+        assert_eq!(check_mint_data(&spl_token::id(), data.clone()), Ok(()));
+        assert_eq!(
+            check_mint_data(&Pubkey::new_unique(), data),
+            Err(ProgramError::IllegalOwner)
+        );
+        assert_eq!(
+            check_mint_data(&spl_token::id(), vec![0; Mint::LEN]),
+            Err(ProgramError::InvalidAccountData)
+        );
+
+        let token_2022 = spl_token_2022::id();
+        let supported = [
+            (EXTENSION_TRANSFER_FEE_CONFIG, vec![0; 108]),
+            (EXTENSION_TRANSFER_HOOK, vec![0; 64]),
+            (EXTENSION_DEFAULT_ACCOUNT_STATE, vec![1]),
+        ];
+        assert_eq!(
+            check_mint_data(&token_2022, extended_mint_data(&supported)),
+            Ok(())
+        );
+
+        let mut hook = vec![0; 64];
+        hook[32] = 1;
+        for extension in [
+            (EXTENSION_NON_TRANSFERABLE, vec![]),
+            (EXTENSION_PERMANENT_DELEGATE, vec![1; 32]),
+            (EXTENSION_PERMANENT_DELEGATE, vec![0; 32]),
+            (EXTENSION_TRANSFER_HOOK, hook),
+            (EXTENSION_DEFAULT_ACCOUNT_STATE, vec![2]),
+        ] {
+            assert_eq!(
+                check_mint_data(&token_2022, extended_mint_data(&[extension])),
+                Err(TokenError::InvalidMint.into())
+            );
+        }
+    }
+
+    #[test]
+    fn test_transfer_fee_from_mint_data() {
+        let older = TransferFee {
+            basis_points: 50,
+            maximum_fee: 1_000,
+        };
+        let newer = TransferFee {
+            basis_points: 100,
+            maximum_fee: 5,
+        };
+        let data = mint_data(older, newer, 10);
+        assert_eq!(TransferFee::from_mint_data(&data, 9), Ok(older));
+        assert_eq!(TransferFee::from_mint_data(&data, 10), Ok(newer));
+
+        // no extensions
+        assert_eq!(
+            TransferFee::from_mint_data(&[0; Mint::LEN], 0),
+            Ok(TransferFee::default())
+        );
+        assert_eq!(
+            TransferFee::from_mint_data(&data[..ACCOUNT_TYPE_OFFSET + 1], 0),
+            Ok(TransferFee::default())
+        );
+        assert_eq!(
+            TransferFee::from_mint_data(&data[..data.len() - 1], 0),
+            Err(ProgramError::InvalidAccountData)
+        );
+    }
+
+    #[test]
+    fn test_transfer_fee() {
+        let fee = TransferFee {
+            basis_points: 100,
+            maximum_fee: 5,
+        };
+        assert_eq!(fee.fee(0), Ok(0));
+        assert_eq!(fee.fee(1), Ok(1));
+        assert_eq!(fee.fee(100), Ok(1));
+        assert_eq!(fee.fee(101), Ok(2));
+        assert_eq!(fee.fee(10_000), Ok(5));
+        assert_eq!(TransferFee::default().fee(10_000), Ok(0));
+    }
+
+    #[test]
+    fn test_gross_amount_nets_amount() {
+        for basis_points in [1, 25, 100, 333, 5_000, 9_999] {
+            for maximum_fee in [0, 1, 7, 1_000, u64::MAX / 2] {
+                let fee = TransferFee {
+                    basis_points,
+                    maximum_fee,
+                };
+                for amount in 0..2_000 {
+                    let gross = fee.gross_amount(amount).unwrap();
+                    assert_eq!(gross - fee.fee(gross).unwrap(), amount);
+                }
+            }
+        }
+        assert_eq!(TransferFee::default().gross_amount(200), Ok(200));
+        assert_eq!(
+            TransferFee {
+                basis_points: 100,
+                maximum_fee: 5,
+            }
+            .gross_total(vec![99, 0, 1_000]),
+            Ok(100 + 0 + 1_005)
+        );
+        let fee = TransferFee {
+            basis_points: 10_000,
+            maximum_fee: 3,
+        };
+        assert_eq!(fee.gross_amount(200), Ok(203));
+        let fee = TransferFee {
+            basis_points: 100,
+            maximum_fee: 1,
+        };
+        assert_eq!(fee.gross_amount(u64::MAX), Err(TokenError::Overflow.into()));
+    }
+}
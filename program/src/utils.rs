@@ -1,9 +1,15 @@
+use crate::{
+    billing::{shortest_period, split_recipients},
+    error::SubscriptionError,
+    fee::BASIS_POINTS_DENOMINATOR,
+    state::{BillingInterval, PlanPolicy, SplitRecipient},
+    token::get_associated_token_address,
+};
 use num_derive::FromPrimitive;
 use solana_program::{
     account_info::AccountInfo, entrypoint::ProgramResult, msg, program_error::ProgramError,
     program_pack::Pack, pubkey::Pubkey,
 };
-use spl_associated_token_account::*;
 use spl_token::{error::TokenError, state::Account as TokenAccount};
 use std::str::FromStr;
 use thiserror::Error;
@@ -37,32 +43,42 @@ pub fn check_subscription_counter_address(
     }
 }
 
-pub fn check_subscription_address(
+pub fn check_plan_address(
     account: &AccountInfo,
     payee: &Pubkey,
-    amount: u64,
-    duration: i64,
-    count: u64,
+    id: u64,
+    bump: Option<u8>,
+    program_id: &Pubkey,
+) -> ProgramResult {
+    if let Some(bump) = bump {
+        let plan_seeds = &[b"plan", payee.as_ref(), &id.to_le_bytes(), &[bump]];
+        check_pda_with_bump(account, plan_seeds, program_id)
+    } else {
+        let plan_seeds = &[b"plan", payee.as_ref(), &id.to_le_bytes()];
+        check_pda(account, plan_seeds, program_id)
+    }
+}
+
+pub fn check_subscription_address(
+    account: &AccountInfo,
+    plan_key: &Pubkey,
+    index: u64,
     bump: Option<u8>,
     program_id: &Pubkey,
 ) -> ProgramResult {
     if let Some(bump) = bump {
         let subscription_seeds = &[
             b"subscription_metadata",
-            payee.as_ref(),
-            &amount.to_le_bytes(),
-            &duration.to_le_bytes(),
-            &count.to_le_bytes(),
+            plan_key.as_ref(),
+            &index.to_le_bytes(),
             &[bump],
         ];
         check_pda_with_bump(account, subscription_seeds, program_id)
     } else {
         let subscription_seeds = &[
             b"subscription_metadata",
-            payee.as_ref(),
-            &amount.to_le_bytes(),
-            &duration.to_le_bytes(),
-            &count.to_le_bytes(),
+            plan_key.as_ref(),
+            &index.to_le_bytes(),
         ];
         check_pda(account, subscription_seeds, program_id)
     }
@@ -140,9 +156,10 @@ pub fn check_ata(
     account: &AccountInfo,
     user_address: &Pubkey,
     mint_address: &Pubkey,
+    token_program: &Pubkey,
 ) -> ProgramResult {
     // check pda
-    let ata = get_associated_token_address(user_address, mint_address);
+    let ata = get_associated_token_address(user_address, mint_address, token_program);
     if *account.key != ata {
         msg!(
             "Invalid ATA address:\tExpected: {}\tGot: {}",
@@ -159,12 +176,13 @@ pub fn check_ata_initialized(
     account: &AccountInfo,
     user_address: &Pubkey,
     mint_address: &Pubkey,
+    token_program: &Pubkey,
 ) -> ProgramResult {
     // check pda
-    check_ata(account, user_address, mint_address)?;
+    check_ata(account, user_address, mint_address, token_program)?;
 
     // check account owned by token program
-    if *account.owner != spl_token::id() {
+    if *account.owner != *token_program {
         msg!("ATA not owned by token program: {}", account.key);
         return Err(ProgramError::IllegalOwner.into());
     }
@@ -190,6 +208,46 @@ pub fn check_ata_initialized(
     Ok(())
 }
 
+/// Checks `accounts` starts with each split recipient followed by its deposit
+/// token account, in the plan's order, and returns them as pairs.
+pub fn check_split_accounts<'a, 'b>(
+    recipients: &[SplitRecipient],
+    accounts: &'b [AccountInfo<'a>],
+    mint_address: &Pubkey,
+    token_program: &Pubkey,
+) -> Result<Vec<(&'b AccountInfo<'a>, &'b AccountInfo<'a>)>, ProgramError> {
+    if accounts.len() < 2 * recipients.len() {
+        msg!(
+            "Expected {} split recipient accounts.",
+            2 * recipients.len()
+        );
+        return Err(ProgramError::NotEnoughAccountKeys);
+    }
+    recipients
+        .iter()
+        .zip(accounts.chunks_exact(2))
+        .map(|(split, pair)| {
+            let (recipient, recipient_deposit) = (&pair[0], &pair[1]);
+            if *recipient.key != split.recipient {
+                msg!(
+                    "Invalid split recipient:\tExpected: {}\tGot: {}",
+                    split.recipient,
+                    recipient.key
+                );
+                return Err(ProgramError::InvalidArgument);
+            }
+            check_writable(recipient_deposit)?;
+            check_ata(
+                recipient_deposit,
+                recipient.key,
+                mint_address,
+                token_program,
+            )?;
+            Ok((recipient, recipient_deposit))
+        })
+        .collect()
+}
+
 pub fn check_program_id(account: &AccountInfo, program_id: &Pubkey) -> ProgramResult {
     if *account.key != *program_id {
         msg!(
@@ -203,6 +261,103 @@ pub fn check_program_id(account: &AccountInfo, program_id: &Pubkey) -> ProgramRe
     }
 }
 
+// DATA
+
+/// Copies `value` into a zero padded fixed size buffer.
+pub fn to_fixed_bytes<const N: usize>(value: &str) -> Result<[u8; N], ProgramError> {
+    let bytes = value.as_bytes();
+    if bytes.len() > N {
+        msg!("String too long:\tMax: {}\tGot: {}", N, bytes.len());
+        return Err(ProgramError::InvalidArgument);
+    }
+    let mut fixed = [0; N];
+    fixed[..bytes.len()].copy_from_slice(bytes);
+    Ok(fixed)
+}
+
+/// Checks a plan billed by `interval` every `duration` seconds. Calendar
+/// plans prorate and give notice by `duration`, so it can't be shorter than
+/// any of their periods.
+pub fn check_plan_duration(interval: BillingInterval, duration: i64) -> ProgramResult {
+    let min_duration = shortest_period(interval);
+    if duration < min_duration {
+        msg!(
+            "Invalid duration:\tMinimum: {}\tGot: {}",
+            min_duration,
+            duration
+        );
+        return Err(ProgramError::InvalidArgument);
+    }
+    Ok(())
+}
+
+/// Checks a plan policy makes sense for a plan billed every `duration`.
+pub fn check_plan_policy(policy: &PlanPolicy, duration: i64) -> ProgramResult {
+    // the grace window has to close before the following period starts
+    if policy.grace_period < 0 || policy.grace_period >= duration {
+        msg!(
+            "Invalid grace period:\tDuration: {}\tGot: {}",
+            duration,
+            policy.grace_period
+        );
+        return Err(SubscriptionError::InvalidPolicy.into());
+    }
+
+    // a discount needs a tier to apply to and can't make periods free
+    for discount in policy.prepay_discounts.iter() {
+        let unused = discount.min_periods == 0;
+        if (unused && discount.basis_points != 0)
+            || discount.basis_points as u64 >= BASIS_POINTS_DENOMINATOR
+        {
+            msg!("Invalid prepay discount: {:?}", discount);
+            return Err(SubscriptionError::InvalidPolicy.into());
+        }
+    }
+
+    // the payee has to keep something from referred renewals
+    if policy.referral_basis_points as u64 >= BASIS_POINTS_DENOMINATOR {
+        msg!(
+            "Invalid referral commission: {}",
+            policy.referral_basis_points
+        );
+        return Err(SubscriptionError::InvalidPolicy.into());
+    }
+    Ok(())
+}
+
+/// Checks a plan's revenue split: recipients in use come first and their
+/// shares add up to the whole payment, or none are used at all.
+pub fn check_splits(splits: &[SplitRecipient]) -> ProgramResult {
+    let recipients = split_recipients(splits);
+    let unused = &splits[recipients.len()..];
+    if unused
+        .iter()
+        .any(|split| *split != SplitRecipient::default())
+    {
+        msg!("Unused split recipients must come last and be empty.");
+        return Err(SubscriptionError::InvalidSplit.into());
+    }
+    if recipients.is_empty() {
+        return Ok(());
+    }
+    if recipients
+        .iter()
+        .any(|split| split.recipient == Pubkey::default())
+    {
+        msg!("Split recipient missing.");
+        return Err(SubscriptionError::InvalidSplit.into());
+    }
+    let total = recipients
+        .iter()
+        .map(|split| split.basis_points as u64)
+        .sum::<u64>();
+    if total != BASIS_POINTS_DENOMINATOR {
+        msg!("Split shares add up to {} basis points.", total);
+        return Err(SubscriptionError::InvalidSplit.into());
+    }
+    Ok(())
+}
+
 #[derive(Error, Debug, Copy, Clone, FromPrimitive, PartialEq)]
 pub enum UtilsError {
     #[error("Invalid program address.")]
@@ -0,0 +1,4624 @@
+#![cfg(feature = "test-bpf")]
+
+use {
+    assert_matches::*,
+    borsh::{BorshDeserialize, BorshSerialize},
+    buoyant::{instruction, metadata, processor::Processor, state, token, utils},
+    solana_program::{
+        clock::Clock, program_option::COption, program_pack::Pack, pubkey::Pubkey, rent::Rent,
+    },
+    solana_program_test::*,
+    solana_sdk::{
+        account::Account,
+        signature::{Keypair, Signer},
+        transaction::Transaction,
+    },
+    spl_associated_token_account::get_associated_token_address,
+    spl_token::state::{Account as TokenAccount, AccountState, Mint},
+};
+
+const AMOUNT: u64 = 200;
+const DURATION: i64 = 10;
+const START_AMOUNT: u64 = 201;
+const USER_BALANCE: u64 = 10_000;
+const FEE: state::FeePolicy = state::FeePolicy {
+    fixed: 1,
+    basis_points: 100,
+};
+// FEE applied to AMOUNT
+const FEE_AMOUNT: u64 = 3;
+const MAX_AMOUNT: u64 = 2 * AMOUNT;
+const POLICY: state::PlanPolicy = state::PlanPolicy {
+    allow_pause: true,
+    grace_period: 0,
+    trial_periods: 0,
+    catch_up: state::CatchUpPolicy::Reanchor,
+    prepay_discounts: [state::PrepayDiscount {
+        min_periods: 0,
+        basis_points: 0,
+    }; state::PREPAY_DISCOUNT_TIERS],
+    referral_basis_points: 0,
+    referral_renewals: 0,
+};
+
+const PLAN_ID: u64 = 0;
+
+struct Addresses {
+    plan: Pubkey,
+    trial_record: Pubkey,
+    subscription: Pubkey,
+    subscription_mint: Pubkey,
+    deposit_vault: Pubkey,
+    payee_deposit_account: Pubkey,
+    user_deposit_account: Pubkey,
+    user_subscription_token_account: Pubkey,
+}
+
+fn find_plan_address(program_id: &Pubkey, payee: &Pubkey, id: u64) -> (Pubkey, u8) {
+    Pubkey::find_program_address(&[b"plan", payee.as_ref(), &id.to_le_bytes()], program_id)
+}
+
+fn find_addresses(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    payee: &Pubkey,
+    deposit_mint: &Pubkey,
+    index: u64,
+) -> Addresses {
+    let (plan, _) = find_plan_address(program_id, payee, PLAN_ID);
+    let (subscription, _) = Pubkey::find_program_address(
+        &[
+            b"subscription_metadata",
+            plan.as_ref(),
+            &index.to_le_bytes(),
+        ],
+        program_id,
+    );
+    let (trial_record, _) =
+        Pubkey::find_program_address(&[b"trial", plan.as_ref(), user.as_ref()], program_id);
+    let (subscription_mint, _) =
+        Pubkey::find_program_address(&[b"subscription_mint", subscription.as_ref()], program_id);
+
+    Addresses {
+        plan,
+        trial_record,
+        subscription,
+        subscription_mint,
+        deposit_vault: get_associated_token_address(&subscription, deposit_mint),
+        payee_deposit_account: get_associated_token_address(payee, deposit_mint),
+        user_deposit_account: get_associated_token_address(user, deposit_mint),
+        user_subscription_token_account: get_associated_token_address(user, &subscription_mint),
+    }
+}
+
+fn packed_account<T: Pack>(data: T, owner: &Pubkey) -> Account {
+    let mut bytes = vec![0; T::get_packed_len()];
+    T::pack(data, &mut bytes).unwrap();
+    Account {
+        lamports: Rent::default().minimum_balance(bytes.len()),
+        data: bytes,
+        owner: *owner,
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+/// A plan billing `AMOUNT` every `DURATION`, with its bump left to `add_plan`.
+fn test_plan(
+    payee: &Pubkey,
+    deposit_mint: &Pubkey,
+    id: u64,
+    count: u64,
+    policy: state::PlanPolicy,
+) -> state::Plan {
+    state::Plan {
+        bump: 0,
+        active: true,
+        payee: *payee,
+        id,
+        deposit_mint: *deposit_mint,
+        amount: AMOUNT,
+        duration: DURATION,
+        interval: state::BillingInterval::Duration,
+        fee: FEE,
+        policy,
+        splits: [state::SplitRecipient::default(); state::MAX_SPLIT_RECIPIENTS],
+        pending_amount: 0,
+        pending_amount_time: 0,
+        count,
+        name: [0; state::PLAN_NAME_LEN],
+        uri: [0; state::PLAN_URI_LEN],
+    }
+}
+
+/// Adds `plan` at its PDA and returns the address.
+fn add_plan(program_test: &mut ProgramTest, program_id: &Pubkey, mut plan: state::Plan) -> Pubkey {
+    let (address, bump) = find_plan_address(program_id, &plan.payee, plan.id);
+    plan.bump = bump;
+    let data = plan.try_to_vec().unwrap();
+    program_test.add_account(
+        address,
+        Account {
+            lamports: Rent::default().minimum_balance(data.len()),
+            data,
+            owner: *program_id,
+            executable: false,
+            rent_epoch: 0,
+        },
+    );
+    address
+}
+
+/// Sets up a program test with a funded user, a fresh deposit mint, and a
+/// payee plan whose counter is at `plan_count`.
+fn setup(
+    program_id: &Pubkey,
+    user: &Keypair,
+    payee: &Pubkey,
+    deposit_mint: &Pubkey,
+    plan_count: u64,
+) -> ProgramTest {
+    setup_with_policy(program_id, user, payee, deposit_mint, plan_count, POLICY)
+}
+
+/// Same as `setup`, with the plan following `policy`.
+fn setup_with_policy(
+    program_id: &Pubkey,
+    user: &Keypair,
+    payee: &Pubkey,
+    deposit_mint: &Pubkey,
+    plan_count: u64,
+    policy: state::PlanPolicy,
+) -> ProgramTest {
+    setup_with_plan(
+        program_id,
+        user,
+        deposit_mint,
+        test_plan(payee, deposit_mint, PLAN_ID, plan_count, policy),
+    )
+}
+
+/// Same as `setup`, with `plan` as the payee plan.
+fn setup_with_plan(
+    program_id: &Pubkey,
+    user: &Keypair,
+    deposit_mint: &Pubkey,
+    plan: state::Plan,
+) -> ProgramTest {
+    let mut program_test = ProgramTest::new(
+        "buoyant",
+        *program_id,
+        processor!(Processor::process_instruction),
+    );
+
+    add_plan(&mut program_test, program_id, plan);
+
+    program_test.add_account(
+        user.pubkey(),
+        Account {
+            lamports: 1_000_000_000,
+            ..Account::default()
+        },
+    );
+    program_test.add_account(
+        *deposit_mint,
+        packed_account(
+            Mint {
+                mint_authority: COption::None,
+                supply: USER_BALANCE,
+                decimals: 0,
+                is_initialized: true,
+                freeze_authority: COption::None,
+            },
+            &spl_token::id(),
+        ),
+    );
+    program_test.add_account(
+        get_associated_token_address(&user.pubkey(), deposit_mint),
+        packed_account(
+            TokenAccount {
+                mint: *deposit_mint,
+                owner: user.pubkey(),
+                amount: USER_BALANCE,
+                state: AccountState::Initialized,
+                ..TokenAccount::default()
+            },
+            &spl_token::id(),
+        ),
+    );
+
+    program_test
+}
+
+fn initialize2_instruction(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    payee: &Pubkey,
+    deposit_mint: &Pubkey,
+    index: u64,
+) -> solana_program::instruction::Instruction {
+    initialize2_term_instruction(program_id, user, payee, deposit_mint, index, 0, 0, 0)
+}
+
+fn initialize2_term_instruction(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    payee: &Pubkey,
+    deposit_mint: &Pubkey,
+    index: u64,
+    max_renewals: u64,
+    end_time: i64,
+    start_time: i64,
+) -> solana_program::instruction::Instruction {
+    let addresses = find_addresses(program_id, user, payee, deposit_mint, index);
+    instruction::initialize2(
+        program_id,
+        user,
+        &addresses.user_deposit_account,
+        &addresses.user_subscription_token_account,
+        payee,
+        &addresses.payee_deposit_account,
+        &addresses.subscription,
+        &addresses.plan,
+        &addresses.trial_record,
+        &addresses.subscription_mint,
+        &addresses.deposit_vault,
+        deposit_mint,
+        &spl_token::id(),
+        START_AMOUNT,
+        index,
+        MAX_AMOUNT,
+        max_renewals,
+        end_time,
+        start_time,
+        false,
+        &[],
+        None,
+    )
+}
+
+/// The temporary account a native SOL refund is unwrapped through.
+fn find_refund_address(program_id: &Pubkey, subscription: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"refund", subscription.as_ref()], program_id).0
+}
+
+fn renew2_instruction(
+    program_id: &Pubkey,
+    caller: &Pubkey,
+    user: &Pubkey,
+    payee: &Pubkey,
+    deposit_mint: &Pubkey,
+    index: u64,
+) -> solana_program::instruction::Instruction {
+    let addresses = find_addresses(program_id, user, payee, deposit_mint, index);
+    let refund_account = if *deposit_mint == spl_token::native_mint::id() {
+        find_refund_address(program_id, &addresses.subscription)
+    } else {
+        addresses.user_deposit_account
+    };
+    instruction::renew2(
+        program_id,
+        caller,
+        &get_associated_token_address(caller, deposit_mint),
+        payee,
+        &addresses.payee_deposit_account,
+        &addresses.subscription,
+        &addresses.plan,
+        &addresses.deposit_vault,
+        deposit_mint,
+        &spl_token::id(),
+        &[],
+        None,
+        None,
+        Some((
+            user,
+            &addresses.user_subscription_token_account,
+            &refund_account,
+        )),
+    )
+}
+
+fn close_instruction(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    payee: &Pubkey,
+    deposit_mint: &Pubkey,
+    index: u64,
+) -> solana_program::instruction::Instruction {
+    let addresses = find_addresses(program_id, user, payee, deposit_mint, index);
+    instruction::close(
+        program_id,
+        user,
+        &addresses.user_deposit_account,
+        &addresses.user_subscription_token_account,
+        &addresses.subscription_mint,
+        &addresses.subscription,
+        &addresses.deposit_vault,
+        deposit_mint,
+        &spl_token::id(),
+    )
+}
+
+/// Subscribes `user` with renewals pulled from their deposit token account,
+/// approving the subscription to pull `approved`.
+fn delegated_initialize2_instructions(
+    program_id: &Pubkey,
+    user: &Pubkey,
+    payee: &Pubkey,
+    deposit_mint: &Pubkey,
+    approved: u64,
+) -> Vec<solana_program::instruction::Instruction> {
+    let addresses = find_addresses(program_id, user, payee, deposit_mint, 0);
+    vec![
+        instruction::initialize2(
+            program_id,
+            user,
+            &addresses.user_deposit_account,
+            &addresses.user_subscription_token_account,
+            payee,
+            &addresses.payee_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.trial_record,
+            &addresses.subscription_mint,
+            &addresses.deposit_vault,
+            deposit_mint,
+            &spl_token::id(),
+            0,
+            0,
+            MAX_AMOUNT,
+            0,
+            0,
+            0,
+            true,
+            &[],
+            None,
+        ),
+        spl_token::instruction::approve(
+            &spl_token::id(),
+            &addresses.user_deposit_account,
+            &addresses.subscription,
+            user,
+            &[],
+            approved,
+        )
+        .unwrap(),
+    ]
+}
+
+fn delegated_renew2_instruction(
+    program_id: &Pubkey,
+    caller: &Pubkey,
+    user: &Pubkey,
+    payee: &Pubkey,
+    deposit_mint: &Pubkey,
+) -> solana_program::instruction::Instruction {
+    let addresses = find_addresses(program_id, user, payee, deposit_mint, 0);
+    instruction::renew2(
+        program_id,
+        caller,
+        &get_associated_token_address(caller, deposit_mint),
+        payee,
+        &addresses.payee_deposit_account,
+        &addresses.subscription,
+        &addresses.plan,
+        &addresses.deposit_vault,
+        deposit_mint,
+        &spl_token::id(),
+        &[],
+        Some(&addresses.user_deposit_account),
+        None,
+        None,
+    )
+}
+
+/// Moves the clock past the end of the current period.
+async fn warp_past_period(context: &mut ProgramTestContext) {
+    let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp += DURATION;
+    context.set_sysvar(&clock);
+}
+
+async fn token_amount(banks_client: &mut BanksClient, address: &Pubkey) -> u64 {
+    banks_client
+        .get_packed_account_data::<TokenAccount>(*address)
+        .await
+        .unwrap()
+        .amount
+}
+
+#[tokio::test]
+async fn test_initialize2_first_subscription() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+
+    let (mut banks_client, payer, recent_blockhash) =
+        setup(&program_id, &user, &payee, &deposit_mint, 0)
+            .start()
+            .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    // metadata
+    let subscription = banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.active);
+    assert_eq!(subscription.mint, addresses.subscription_mint);
+    assert_eq!(subscription.deposit_vault, addresses.deposit_vault);
+    assert_eq!(subscription.index, 0);
+    let (_, subscription_bump) = Pubkey::find_program_address(
+        &[
+            b"subscription_metadata",
+            addresses.plan.as_ref(),
+            &0u64.to_le_bytes(),
+        ],
+        &program_id,
+    );
+    assert_eq!(subscription.bump, subscription_bump);
+    assert_eq!(subscription.plan, addresses.plan);
+
+    // plan counter
+    let plan = banks_client
+        .get_account_data_with_borsh::<state::Plan>(addresses.plan)
+        .await
+        .unwrap();
+    assert_eq!(plan.count, 1);
+
+    // balances
+    assert_eq!(
+        token_amount(&mut banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut banks_client, &addresses.deposit_vault).await,
+        START_AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut banks_client, &addresses.user_deposit_account).await,
+        USER_BALANCE - AMOUNT - START_AMOUNT
+    );
+    assert_eq!(
+        token_amount(
+            &mut banks_client,
+            &addresses.user_subscription_token_account
+        )
+        .await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_initialize2_second_subscription() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+
+    let (mut banks_client, payer, recent_blockhash) =
+        setup(&program_id, &user, &payee, &deposit_mint, 0)
+            .start()
+            .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 1),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 1);
+    let plan = banks_client
+        .get_account_data_with_borsh::<state::Plan>(addresses.plan)
+        .await
+        .unwrap();
+    assert_eq!(plan.count, 2);
+    assert_eq!(
+        token_amount(&mut banks_client, &addresses.payee_deposit_account).await,
+        2 * AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_initialize2_reused_index() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    // state left behind by a closed subscription at index 0: the plan counter
+    // has moved past it and its ownership mint exists with no supply
+    let mut program_test = setup(&program_id, &user, &payee, &deposit_mint, 1);
+    program_test.add_account(
+        addresses.subscription_mint,
+        packed_account(
+            Mint {
+                mint_authority: COption::Some(addresses.subscription),
+                supply: 0,
+                decimals: 0,
+                is_initialized: true,
+                freeze_authority: COption::Some(addresses.subscription),
+            },
+            &spl_token::id(),
+        ),
+    );
+
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    // counter is untouched, the closed index is active again
+    let plan = banks_client
+        .get_account_data_with_borsh::<state::Plan>(addresses.plan)
+        .await
+        .unwrap();
+    assert_eq!(plan.count, 1);
+
+    let subscription = banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.active);
+    assert_eq!(subscription.index, 0);
+    assert_eq!(
+        token_amount(
+            &mut banks_client,
+            &addresses.user_subscription_token_account
+        )
+        .await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_initialize2_rejects_used_and_skipped_index() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+
+    let (mut banks_client, payer, recent_blockhash) =
+        setup(&program_id, &user, &payee, &deposit_mint, 0)
+            .start()
+            .await;
+
+    // index past the plan counter
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            1,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+
+    // index still in use
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    // paid by the user so the retry isn't deduplicated
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+}
+
+#[tokio::test]
+async fn test_renew2_early() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &context.payer.pubkey(),
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert!(context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .is_err());
+}
+
+#[tokio::test]
+async fn test_renew2_expire_keeps_rent() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription_lamports = context
+        .banks_client
+        .get_balance(addresses.subscription)
+        .await
+        .unwrap();
+
+    // the vault only holds start_amount, which is short of a renewal
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(!subscription.active);
+
+    assert_eq!(
+        token_amount(
+            &mut context.banks_client,
+            &get_associated_token_address(&caller, &deposit_mint)
+        )
+        .await,
+        FEE_AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        START_AMOUNT - FEE_AMOUNT
+    );
+    assert_eq!(
+        context
+            .banks_client
+            .get_balance(addresses.subscription)
+            .await
+            .unwrap(),
+        subscription_lamports
+    );
+}
+
+#[tokio::test]
+async fn test_renew2_renew() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    // initialize, then top up the vault for another period
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                AMOUNT,
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let next_renew_time = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap()
+        .next_renew_time;
+
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.active);
+    assert!(subscription.next_renew_time > next_renew_time);
+
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        2 * AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        START_AMOUNT + AMOUNT - AMOUNT - FEE_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_close_and_reuse_index() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let (mut banks_client, payer, recent_blockhash) =
+        setup(&program_id, &user, &payee, &deposit_mint, 0)
+            .start()
+            .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    let user_lamports = banks_client.get_balance(user.pubkey()).await.unwrap();
+    let reclaimable_lamports = banks_client
+        .get_balance(addresses.subscription)
+        .await
+        .unwrap()
+        + banks_client
+            .get_balance(addresses.deposit_vault)
+            .await
+            .unwrap()
+        + banks_client
+            .get_balance(addresses.user_subscription_token_account)
+            .await
+            .unwrap();
+
+    let mut transaction = Transaction::new_with_payer(
+        &[close_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    // deposit and rent recovered, accounts gone
+    assert_eq!(
+        token_amount(&mut banks_client, &addresses.user_deposit_account).await,
+        USER_BALANCE - AMOUNT
+    );
+    assert_eq!(
+        banks_client.get_balance(user.pubkey()).await.unwrap(),
+        user_lamports + reclaimable_lamports
+    );
+    assert!(banks_client
+        .get_account(addresses.subscription)
+        .await
+        .unwrap()
+        .is_none());
+    assert!(banks_client
+        .get_account(addresses.deposit_vault)
+        .await
+        .unwrap()
+        .is_none());
+    let subscription_mint = banks_client
+        .get_packed_account_data::<Mint>(addresses.subscription_mint)
+        .await
+        .unwrap();
+    assert_eq!(subscription_mint.supply, 0);
+
+    // index 0 can be initialized again without moving the counter
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    let plan = banks_client
+        .get_account_data_with_borsh::<state::Plan>(addresses.plan)
+        .await
+        .unwrap();
+    assert_eq!(plan.count, 1);
+    assert_eq!(
+        token_amount(
+            &mut banks_client,
+            &addresses.user_subscription_token_account
+        )
+        .await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_plan_lifecycle() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Keypair::new();
+    let deposit_mint = Pubkey::new_unique();
+
+    let mut program_test = setup(&program_id, &user, &payee.pubkey(), &deposit_mint, 0);
+    program_test.add_account(
+        payee.pubkey(),
+        Account {
+            lamports: 1_000_000_000,
+            ..Account::default()
+        },
+    );
+    let (mut banks_client, payer, recent_blockhash) = program_test.start().await;
+
+    // create a second plan for the same payee
+    let plan_id = PLAN_ID + 1;
+    let (plan, _) = find_plan_address(&program_id, &payee.pubkey(), plan_id);
+
+    // a monthly plan can't claim a shorter nominal period
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::create_plan(
+            &program_id,
+            &payee.pubkey(),
+            &plan,
+            &deposit_mint,
+            plan_id,
+            AMOUNT,
+            DURATION,
+            state::BillingInterval::Month,
+            FEE,
+            POLICY,
+            [state::SplitRecipient::default(); state::MAX_SPLIT_RECIPIENTS],
+            "basic".to_string(),
+            "https://example.com/basic".to_string(),
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &payee], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::create_plan(
+            &program_id,
+            &payee.pubkey(),
+            &plan,
+            &deposit_mint,
+            plan_id,
+            AMOUNT,
+            DURATION,
+            state::BillingInterval::Duration,
+            FEE,
+            POLICY,
+            [state::SplitRecipient::default(); state::MAX_SPLIT_RECIPIENTS],
+            "basic".to_string(),
+            "https://example.com/basic".to_string(),
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &payee], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    let plan_data = banks_client
+        .get_account_data_with_borsh::<state::Plan>(plan)
+        .await
+        .unwrap();
+    assert!(plan_data.active);
+    assert_eq!(plan_data.payee, payee.pubkey());
+    assert_eq!(plan_data.id, plan_id);
+    assert_eq!(plan_data.amount, AMOUNT);
+    assert_eq!(plan_data.count, 0);
+    assert_eq!(&plan_data.name[..5], b"basic");
+
+    // only the payee may update it
+    let new_fee = state::FeePolicy {
+        fixed: 2,
+        basis_points: 0,
+    };
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::update_plan(
+            &program_id,
+            &user.pubkey(),
+            &plan,
+            new_fee,
+            POLICY,
+            "pro".to_string(),
+            String::new(),
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::update_plan(
+            &program_id,
+            &payee.pubkey(),
+            &plan,
+            new_fee,
+            POLICY,
+            "pro".to_string(),
+            String::new(),
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &payee], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    let plan_data = banks_client
+        .get_account_data_with_borsh::<state::Plan>(plan)
+        .await
+        .unwrap();
+    assert_eq!(plan_data.fee, new_fee);
+    assert_eq!(&plan_data.name[..4], b"pro\0");
+    assert_eq!(plan_data.uri, [0; state::PLAN_URI_LEN]);
+
+    // a retired plan takes no new subscriptions
+    let addresses = find_addresses(
+        &program_id,
+        &user.pubkey(),
+        &payee.pubkey(),
+        &deposit_mint,
+        0,
+    );
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::retire_plan(
+            &program_id,
+            &payee.pubkey(),
+            &addresses.plan,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &payee], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee.pubkey(),
+            &deposit_mint,
+            0,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+}
+
+#[tokio::test]
+async fn test_pause_and_resume() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    // initialize with enough for another period, then pause right away
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                AMOUNT,
+            )
+            .unwrap(),
+            instruction::pause(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_subscription_token_account,
+                &addresses.subscription,
+                &addresses.plan,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.paused);
+    let remaining = subscription.next_renew_time - subscription.paused_at;
+
+    // cranks can't renew while paused, even once the period is over
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert!(context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .is_err());
+
+    // resuming carries over the rest of the period
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::resume(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_subscription_token_account,
+            &addresses.subscription,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let now = context
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp;
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(!subscription.paused);
+    assert!(subscription.active);
+    assert_eq!(subscription.next_renew_time, now + remaining);
+
+    // renews normally once the carried over period is up, paid by the user
+    // so the earlier attempt isn't deduplicated
+    warp_past_period(&mut context).await;
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        2 * AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_pause_rejects_due_renewal() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // the period is up, pausing now would carry over an unpaid period
+    warp_past_period(&mut context).await;
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::pause(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_subscription_token_account,
+            &addresses.subscription,
+            &addresses.plan,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert!(context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .is_err());
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(!subscription.paused);
+}
+
+#[tokio::test]
+async fn test_cancel_at_period_end() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    // fund another period, then cancel at period end
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                AMOUNT,
+            )
+            .unwrap(),
+            instruction::set_cancel_at_period_end(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_subscription_token_account,
+                &addresses.subscription,
+                true,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.active);
+    assert!(subscription.cancel_at_period_end);
+
+    // the crank expires it instead of charging, and the owner gets the rest
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(!subscription.active);
+    assert!(!subscription.cancel_at_period_end);
+
+    assert_eq!(
+        token_amount(
+            &mut context.banks_client,
+            &get_associated_token_address(&caller, &deposit_mint)
+        )
+        .await,
+        FEE_AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        0
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.user_deposit_account).await,
+        USER_BALANCE - AMOUNT - FEE_AMOUNT
+    );
+}
+
+const GRACE_POLICY: state::PlanPolicy = state::PlanPolicy {
+    allow_pause: true,
+    grace_period: DURATION / 2,
+    ..POLICY
+};
+
+#[tokio::test]
+async fn test_renew2_grace_period_keeps_anchor() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup_with_policy(&program_id, &user, &payee, &deposit_mint, 0, GRACE_POLICY)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let next_renew_time = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap()
+        .next_renew_time;
+
+    // short on funds within the grace period: past due, nobody paid
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.active);
+    assert!(subscription.past_due);
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        START_AMOUNT
+    );
+
+    // a top up within the grace period renews from the original anchor
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                AMOUNT,
+            )
+            .unwrap(),
+            renew2_instruction(
+                &program_id,
+                &user.pubkey(),
+                &user.pubkey(),
+                &payee,
+                &deposit_mint,
+                0,
+            ),
+        ],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.active);
+    assert!(!subscription.past_due);
+    assert_eq!(subscription.next_renew_time, next_renew_time + DURATION);
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        2 * AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_renew2_expire_after_grace_period() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup_with_policy(&program_id, &user, &payee, &deposit_mint, 0, GRACE_POLICY)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // a full period later the grace period is long over
+    warp_past_period(&mut context).await;
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(!subscription.active);
+    assert!(!subscription.past_due);
+    assert_eq!(
+        token_amount(
+            &mut context.banks_client,
+            &get_associated_token_address(&caller, &deposit_mint)
+        )
+        .await,
+        FEE_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_cancel_at_period_end_clears_past_due() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup_with_policy(&program_id, &user, &payee, &deposit_mint, 0, GRACE_POLICY)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // short on funds within the grace period: past due
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // cancelling ends it at the next crank, which clears past due as well
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            instruction::set_cancel_at_period_end(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_subscription_token_account,
+                &addresses.subscription,
+                true,
+            ),
+            renew2_instruction(
+                &program_id,
+                &caller,
+                &user.pubkey(),
+                &payee,
+                &deposit_mint,
+                0,
+            ),
+        ],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(!subscription.active);
+    assert!(!subscription.past_due);
+    assert!(!subscription.cancel_at_period_end);
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        0
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.user_deposit_account).await,
+        USER_BALANCE - AMOUNT - FEE_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_initialize2_free_trial_once() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let policy = state::PlanPolicy {
+        trial_periods: 2,
+        ..POLICY
+    };
+    let (mut banks_client, payer, recent_blockhash) =
+        setup_with_policy(&program_id, &user, &payee, &deposit_mint, 0, policy)
+            .start()
+            .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    // active and owned, but nothing paid and first renewal after the trial
+    let now = banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp;
+    let subscription = banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.active);
+    assert_eq!(subscription.next_renew_time, now + 2 * DURATION);
+    assert_eq!(
+        token_amount(&mut banks_client, &addresses.payee_deposit_account).await,
+        0
+    );
+    assert_eq!(
+        token_amount(&mut banks_client, &addresses.user_deposit_account).await,
+        USER_BALANCE - START_AMOUNT
+    );
+    assert_eq!(
+        token_amount(
+            &mut banks_client,
+            &addresses.user_subscription_token_account
+        )
+        .await,
+        1
+    );
+    assert!(banks_client
+        .get_account(addresses.trial_record)
+        .await
+        .unwrap()
+        .is_some());
+
+    // subscribing again to the same plan is charged
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            1,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    let subscription = banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(
+            find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 1).subscription,
+        )
+        .await
+        .unwrap();
+    assert_eq!(subscription.next_renew_time, now + DURATION);
+    assert_eq!(
+        token_amount(&mut banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_change_plan_prorates() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    // twice the price for twice as long
+    let mut program_test = setup(&program_id, &user, &payee, &deposit_mint, 0);
+    let upgrade = add_plan(
+        &mut program_test,
+        &program_id,
+        state::Plan {
+            amount: 2 * AMOUNT,
+            duration: 2 * DURATION,
+            ..test_plan(&payee, &deposit_mint, PLAN_ID + 1, 0, POLICY)
+        },
+    );
+    let mut context = program_test.start_with_context().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                2 * AMOUNT,
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // halfway through the period, half of it is credited
+    let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp += DURATION / 2;
+    context.set_sysvar(&clock);
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::change_plan(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_subscription_token_account,
+            &addresses.subscription,
+            &upgrade,
+            &addresses.deposit_vault,
+            &addresses.payee_deposit_account,
+            &deposit_mint,
+            &spl_token::id(),
+            2 * AMOUNT,
+            &[],
+            None,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert_eq!(subscription.plan, upgrade);
+    assert_eq!(subscription.origin_plan, addresses.plan);
+    assert_eq!(subscription.amount, 2 * AMOUNT);
+    assert_eq!(subscription.duration, 2 * DURATION);
+    assert_eq!(
+        subscription.next_renew_time,
+        clock.unix_timestamp + 2 * DURATION
+    );
+
+    let credit = AMOUNT / 2;
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT + 2 * AMOUNT - credit
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        START_AMOUNT + 2 * AMOUNT - (2 * AMOUNT - credit)
+    );
+
+    // same ownership token
+    assert_eq!(
+        token_amount(
+            &mut context.banks_client,
+            &addresses.user_subscription_token_account
+        )
+        .await,
+        1
+    );
+}
+
+#[tokio::test]
+async fn test_change_plan_pulls_from_delegated_account() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut program_test = setup(&program_id, &user, &payee, &deposit_mint, 0);
+    let upgrade = add_plan(
+        &mut program_test,
+        &program_id,
+        state::Plan {
+            amount: 2 * AMOUNT,
+            duration: 2 * DURATION,
+            ..test_plan(&payee, &deposit_mint, PLAN_ID + 1, 0, POLICY)
+        },
+    );
+    let mut context = program_test.start_with_context().await;
+
+    // nothing in the vault, the subscription may pull the upgrade
+    let mut transaction = Transaction::new_with_payer(
+        &delegated_initialize2_instructions(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            2 * AMOUNT,
+        ),
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp += DURATION / 2;
+    context.set_sysvar(&clock);
+
+    let change_plan = |max_amount_per_period: u64| {
+        instruction::change_plan(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_subscription_token_account,
+            &addresses.subscription,
+            &upgrade,
+            &addresses.deposit_vault,
+            &addresses.payee_deposit_account,
+            &deposit_mint,
+            &spl_token::id(),
+            max_amount_per_period,
+            &[],
+            Some(&addresses.user_deposit_account),
+        )
+    };
+
+    // the new price must be within the maximum
+    let mut transaction =
+        Transaction::new_with_payer(&[change_plan(AMOUNT)], Some(&context.payer.pubkey()));
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert!(context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .is_err());
+
+    let mut transaction =
+        Transaction::new_with_payer(&[change_plan(2 * AMOUNT)], Some(&context.payer.pubkey()));
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // the charge comes straight from the user, the vault is untouched
+    let charge = 2 * AMOUNT - AMOUNT / 2;
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT + charge
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.user_deposit_account).await,
+        USER_BALANCE - AMOUNT - charge
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        0
+    );
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert_eq!(subscription.plan, upgrade);
+    assert_eq!(subscription.amount, 2 * AMOUNT);
+}
+
+#[tokio::test]
+async fn test_change_plan_after_extend() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut program_test = setup(&program_id, &user, &payee, &deposit_mint, 0);
+    let upgrade = add_plan(
+        &mut program_test,
+        &program_id,
+        state::Plan {
+            amount: 2 * AMOUNT,
+            ..test_plan(&payee, &deposit_mint, PLAN_ID + 1, 0, POLICY)
+        },
+    );
+    let mut context = program_test.start_with_context().await;
+
+    // two more periods prepaid on top of the first
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                2 * AMOUNT,
+            )
+            .unwrap(),
+            instruction::extend(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_deposit_account,
+                &addresses.subscription,
+                &addresses.plan,
+                &addresses.payee_deposit_account,
+                &deposit_mint,
+                &spl_token::id(),
+                2,
+                &[],
+                None,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let change_plan = instruction::change_plan(
+        &program_id,
+        &user.pubkey(),
+        &addresses.user_subscription_token_account,
+        &addresses.subscription,
+        &upgrade,
+        &addresses.deposit_vault,
+        &addresses.payee_deposit_account,
+        &deposit_mint,
+        &spl_token::id(),
+        2 * AMOUNT,
+        &[],
+        None,
+    );
+
+    // the prepaid periods would be lost
+    let mut transaction =
+        Transaction::new_with_payer(&[change_plan.clone()], Some(&context.payer.pubkey()));
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert!(context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .is_err());
+
+    // halfway through the last prepaid period, half of it is credited, paid
+    // by the user so the earlier attempt isn't deduplicated
+    let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp += 2 * DURATION + DURATION / 2;
+    context.set_sysvar(&clock);
+
+    let mut transaction = Transaction::new_with_payer(&[change_plan], Some(&user.pubkey()));
+    transaction.sign(&[&user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert_eq!(subscription.plan, upgrade);
+    assert_eq!(
+        subscription.next_renew_time,
+        clock.unix_timestamp + DURATION
+    );
+    let credit = AMOUNT / 2;
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT + 2 * AMOUNT + 2 * AMOUNT - credit
+    );
+}
+
+#[tokio::test]
+async fn test_scheduled_price_change() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Keypair::new();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(
+        &program_id,
+        &user.pubkey(),
+        &payee.pubkey(),
+        &deposit_mint,
+        0,
+    );
+
+    let mut context = setup(&program_id, &user, &payee.pubkey(), &deposit_mint, 0)
+        .start_with_context()
+        .await;
+    let now = context
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp;
+
+    // less than a period of notice
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::schedule_price_change(
+            &program_id,
+            &payee.pubkey(),
+            &addresses.plan,
+            2 * AMOUNT,
+            now + DURATION - 1,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &payee], context.last_blockhash);
+    assert!(context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .is_err());
+
+    // subscribe, fund three periods' worth, and double the price two periods
+    // from now
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(
+                &program_id,
+                &user.pubkey(),
+                &payee.pubkey(),
+                &deposit_mint,
+                0,
+            ),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                3 * AMOUNT,
+            )
+            .unwrap(),
+            instruction::schedule_price_change(
+                &program_id,
+                &payee.pubkey(),
+                &addresses.plan,
+                2 * AMOUNT,
+                now + 2 * DURATION,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user, &payee], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // the next renewal still charges the old price and records the new one
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee.pubkey(),
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert_eq!(subscription.amount, AMOUNT);
+    assert_eq!(subscription.pending_amount, 2 * AMOUNT);
+    assert_eq!(subscription.pending_amount_time, now + 2 * DURATION);
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        2 * AMOUNT
+    );
+
+    // the one after charges the new price
+    warp_past_period(&mut context).await;
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &user.pubkey(),
+            &payee.pubkey(),
+            &deposit_mint,
+            0,
+        )],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert_eq!(subscription.amount, 2 * AMOUNT);
+    assert_eq!(subscription.pending_amount, 0);
+    assert_eq!(subscription.pending_amount_time, 0);
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        4 * AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_renew2_expires_above_max_amount() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Keypair::new();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(
+        &program_id,
+        &user.pubkey(),
+        &payee.pubkey(),
+        &deposit_mint,
+        0,
+    );
+
+    let mut context = setup(&program_id, &user, &payee.pubkey(), &deposit_mint, 0)
+        .start_with_context()
+        .await;
+    let now = context
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp;
+
+    // plenty of funds, but the price goes past what the user agreed to
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(
+                &program_id,
+                &user.pubkey(),
+                &payee.pubkey(),
+                &deposit_mint,
+                0,
+            ),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                4 * AMOUNT,
+            )
+            .unwrap(),
+            instruction::schedule_price_change(
+                &program_id,
+                &payee.pubkey(),
+                &addresses.plan,
+                MAX_AMOUNT + 1,
+                now + DURATION,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user, &payee], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee.pubkey(),
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // expired with the fee on the maximum, nothing else leaves the vault
+    let max_fee = FEE.fixed + MAX_AMOUNT / 100;
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(!subscription.active);
+    assert_eq!(
+        token_amount(
+            &mut context.banks_client,
+            &get_associated_token_address(&caller, &deposit_mint)
+        )
+        .await,
+        max_fee
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        START_AMOUNT + 4 * AMOUNT - max_fee
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_renew2_ends_after_max_renewals() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    // a single renewal, with more than enough funds for several
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_term_instruction(
+                &program_id,
+                &user.pubkey(),
+                &payee,
+                &deposit_mint,
+                0,
+                1,
+                0,
+                0,
+            ),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                4 * AMOUNT,
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.active);
+    assert_eq!(subscription.renewal_count, 1);
+
+    // the term is over, so the next crank ends it and refunds the user
+    warp_past_period(&mut context).await;
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(!subscription.active);
+    assert_eq!(subscription.renewal_count, 1);
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        0
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        2 * AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.user_deposit_account).await,
+        USER_BALANCE - 2 * AMOUNT - FEE_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_renew2_refunds_current_token_holder() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let buyer = Pubkey::new_unique();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+    let buyer_subscription_token_account =
+        get_associated_token_address(&buyer, &addresses.subscription_mint);
+    let buyer_deposit_account = get_associated_token_address(&buyer, &deposit_mint);
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    // a single period, then the user sells the subscription token
+    let now = context
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp;
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_term_instruction(
+                &program_id,
+                &user.pubkey(),
+                &payee,
+                &deposit_mint,
+                0,
+                0,
+                now + DURATION,
+                0,
+            ),
+            spl_associated_token_account::create_associated_token_account(
+                &context.payer.pubkey(),
+                &buyer,
+                &addresses.subscription_mint,
+            ),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_subscription_token_account,
+                &buyer_subscription_token_account,
+                &user.pubkey(),
+                &[],
+                1,
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // the user no longer holds the token, so can't take the refund
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert!(context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .is_err());
+
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::renew2(
+            &program_id,
+            &caller,
+            &get_associated_token_address(&caller, &deposit_mint),
+            &payee,
+            &addresses.payee_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.deposit_vault,
+            &deposit_mint,
+            &spl_token::id(),
+            &[],
+            None,
+            None,
+            Some((
+                &buyer,
+                &buyer_subscription_token_account,
+                &buyer_deposit_account,
+            )),
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    assert!(
+        !context
+            .banks_client
+            .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+            .await
+            .unwrap()
+            .active
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &buyer_deposit_account).await,
+        START_AMOUNT - FEE_AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.user_deposit_account).await,
+        USER_BALANCE - START_AMOUNT - AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_initialize2_scheduled_start() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+    let now = context
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp;
+    let start_time = now + DURATION;
+
+    // the vault pays the first charge, so it must hold it with two fees
+    let start_amount = AMOUNT + 2 * FEE_AMOUNT;
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::initialize2(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_deposit_account,
+            &addresses.user_subscription_token_account,
+            &payee,
+            &addresses.payee_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.trial_record,
+            &addresses.subscription_mint,
+            &addresses.deposit_vault,
+            &deposit_mint,
+            &spl_token::id(),
+            start_amount,
+            0,
+            MAX_AMOUNT,
+            0,
+            0,
+            start_time,
+            false,
+            &[],
+            None,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(!subscription.active);
+    assert_eq!(subscription.start_time, start_time);
+    assert_eq!(subscription.next_renew_time, start_time);
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        0
+    );
+
+    // nothing to charge before the start
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert!(context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .is_err());
+
+    // the first renewal at the start charges the vault and activates it
+    warp_past_period(&mut context).await;
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.active);
+    assert_eq!(subscription.start_time, 0);
+    assert_eq!(subscription.renewal_count, 0);
+    assert!(subscription.next_renew_time >= start_time + DURATION);
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        FEE_AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_renew2_calendar_month_clamps_to_month_end() {
+    // 2024-01-31T12:00:00Z, 2024-02-29T12:00:00Z and 2024-03-31T12:00:00Z
+    const JAN_31_2024: i64 = 1_706_702_400;
+    const FEB_29_2024: i64 = 1_709_208_000;
+    const MAR_31_2024: i64 = 1_711_886_400;
+
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut plan = test_plan(&payee, &deposit_mint, PLAN_ID, 0, GRACE_POLICY);
+    plan.interval = state::BillingInterval::Month;
+    let mut context = setup_with_plan(&program_id, &user, &deposit_mint, plan)
+        .start_with_context()
+        .await;
+    let mut clock = context.banks_client.get_sysvar::<Clock>().await.unwrap();
+    clock.unix_timestamp = JAN_31_2024;
+    context.set_sysvar(&clock);
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                AMOUNT,
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert_eq!(subscription.anchor_day, 31);
+    assert_eq!(subscription.next_renew_time, FEB_29_2024);
+
+    // the anchor day comes back once the month is long enough
+    clock.unix_timestamp = FEB_29_2024;
+    context.set_sysvar(&clock);
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.active);
+    assert_eq!(subscription.anchor_day, 31);
+    assert_eq!(subscription.next_renew_time, MAR_31_2024);
+}
+
+/// Subscribes under `catch_up`, misses a renewal and renews two periods late,
+/// returning the subscription, when it was first due and what the payee got.
+async fn renew2_after_missed_period(
+    catch_up: state::CatchUpPolicy,
+) -> (state::Subscription2, i64, u64) {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let policy = state::PlanPolicy { catch_up, ..POLICY };
+    let mut context = setup_with_policy(&program_id, &user, &payee, &deposit_mint, 0, policy)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                2 * AMOUNT,
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    let next_renew_time = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap()
+        .next_renew_time;
+
+    // no one renews at the first due time
+    warp_past_period(&mut context).await;
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    let payee_amount =
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await;
+    (subscription, next_renew_time, payee_amount)
+}
+
+#[tokio::test]
+async fn test_renew2_charges_missed_periods() {
+    let (subscription, next_renew_time, payee_amount) =
+        renew2_after_missed_period(state::CatchUpPolicy::ChargeMissed).await;
+    assert!(subscription.active);
+    assert_eq!(subscription.renewal_count, 2);
+    assert_eq!(subscription.next_renew_time, next_renew_time + 2 * DURATION);
+    assert_eq!(payee_amount, 3 * AMOUNT);
+}
+
+#[tokio::test]
+async fn test_renew2_skips_missed_periods() {
+    let (subscription, next_renew_time, payee_amount) =
+        renew2_after_missed_period(state::CatchUpPolicy::SkipMissed).await;
+    assert!(subscription.active);
+    assert_eq!(subscription.renewal_count, 1);
+    assert_eq!(subscription.next_renew_time, next_renew_time + 2 * DURATION);
+    assert_eq!(payee_amount, 2 * AMOUNT);
+}
+
+#[tokio::test]
+async fn test_extend_prepays_with_discount() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut policy = POLICY;
+    policy.prepay_discounts[0] = state::PrepayDiscount {
+        min_periods: 3,
+        basis_points: 1_000,
+    };
+    let mut context = setup_with_policy(&program_id, &user, &payee, &deposit_mint, 0, policy)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    let next_renew_time = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap()
+        .next_renew_time;
+
+    // three periods at 10% off, straight to the payee
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::extend(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.payee_deposit_account,
+            &deposit_mint,
+            &spl_token::id(),
+            3,
+            &[],
+            None,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.active);
+    assert_eq!(subscription.renewal_count, 3);
+    assert_eq!(subscription.next_renew_time, next_renew_time + 3 * DURATION);
+
+    let prepaid = 3 * AMOUNT * 9 / 10;
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT + prepaid
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        START_AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.user_deposit_account).await,
+        USER_BALANCE - START_AMOUNT - AMOUNT - prepaid
+    );
+}
+
+#[tokio::test]
+async fn test_extend_records_scheduled_price_change() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Keypair::new();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(
+        &program_id,
+        &user.pubkey(),
+        &payee.pubkey(),
+        &deposit_mint,
+        0,
+    );
+
+    let mut context = setup(&program_id, &user, &payee.pubkey(), &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee.pubkey(),
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // the payee schedules a new price, then the user prepays across it at
+    // today's price
+    let now = context
+        .banks_client
+        .get_sysvar::<Clock>()
+        .await
+        .unwrap()
+        .unix_timestamp;
+    let effective_time = now + 2 * DURATION;
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            instruction::schedule_price_change(
+                &program_id,
+                &payee.pubkey(),
+                &addresses.plan,
+                2 * AMOUNT,
+                effective_time,
+            ),
+            instruction::extend(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_deposit_account,
+                &addresses.subscription,
+                &addresses.plan,
+                &addresses.payee_deposit_account,
+                &deposit_mint,
+                &spl_token::id(),
+                3,
+                &[],
+                None,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &payee, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert_eq!(subscription.amount, AMOUNT);
+    assert_eq!(subscription.pending_amount, 2 * AMOUNT);
+    assert_eq!(subscription.pending_amount_time, effective_time);
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT + 3 * AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_extend_pays_referral_commission() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let referrer = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+    let referrer_deposit_account = get_associated_token_address(&referrer, &deposit_mint);
+
+    // 10% of the first two renewals
+    let policy = state::PlanPolicy {
+        referral_basis_points: 1_000,
+        referral_renewals: 2,
+        ..POLICY
+    };
+    let mut context = setup_with_policy(&program_id, &user, &payee, &deposit_mint, 0, policy)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            instruction::initialize2(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_deposit_account,
+                &addresses.user_subscription_token_account,
+                &payee,
+                &addresses.payee_deposit_account,
+                &addresses.subscription,
+                &addresses.plan,
+                &addresses.trial_record,
+                &addresses.subscription_mint,
+                &addresses.deposit_vault,
+                &deposit_mint,
+                &spl_token::id(),
+                START_AMOUNT,
+                0,
+                MAX_AMOUNT,
+                0,
+                0,
+                0,
+                false,
+                &[],
+                Some(&referrer),
+            ),
+            spl_associated_token_account::create_associated_token_account(
+                &context.payer.pubkey(),
+                &referrer,
+                &deposit_mint,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let extend = |periods: u32, referral_accounts: Option<(&Pubkey, &Pubkey)>| {
+        instruction::extend(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.payee_deposit_account,
+            &deposit_mint,
+            &spl_token::id(),
+            periods,
+            &[],
+            referral_accounts,
+        )
+    };
+
+    // the referrer is owed commission on prepaid periods too
+    let mut transaction =
+        Transaction::new_with_payer(&[extend(3, None)], Some(&context.payer.pubkey()));
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert!(context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .is_err());
+
+    // two of the three periods are commissioned
+    let mut transaction = Transaction::new_with_payer(
+        &[extend(3, Some((&referrer, &referrer_deposit_account)))],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    let commission = 2 * AMOUNT / 10;
+    assert_eq!(
+        token_amount(&mut context.banks_client, &referrer_deposit_account).await,
+        commission
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT + 3 * AMOUNT - commission
+    );
+
+    // the referral is used up, later periods don't need the referrer
+    let mut transaction =
+        Transaction::new_with_payer(&[extend(1, None)], Some(&context.payer.pubkey()));
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &referrer_deposit_account).await,
+        commission
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        2 * AMOUNT + 3 * AMOUNT - commission
+    );
+    assert_eq!(
+        context
+            .banks_client
+            .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+            .await
+            .unwrap()
+            .renewal_count,
+        4
+    );
+}
+
+#[tokio::test]
+async fn test_extend_rejects_paused_subscription() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            instruction::pause(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_subscription_token_account,
+                &addresses.subscription,
+                &addresses.plan,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // prepaying is refused until the subscription is resumed
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::extend(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.payee_deposit_account,
+            &deposit_mint,
+            &spl_token::id(),
+            1,
+            &[],
+            None,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert!(context
+        .banks_client
+        .process_transaction(transaction)
+        .await
+        .is_err());
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert!(subscription.paused);
+    assert_eq!(subscription.renewal_count, 0);
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_initialize2_splits_first_payment() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let recipients = [
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+        Pubkey::new_unique(),
+    ];
+    let mut plan = test_plan(&payee, &deposit_mint, PLAN_ID, 0, POLICY);
+    for (split, (recipient, basis_points)) in plan
+        .splits
+        .iter_mut()
+        .zip(recipients.iter().zip([6_667, 2_222, 1_111]))
+    {
+        *split = state::SplitRecipient {
+            recipient: *recipient,
+            basis_points,
+        };
+    }
+    let mut context = setup_with_plan(&program_id, &user, &deposit_mint, plan)
+        .start_with_context()
+        .await;
+
+    let split_accounts: Vec<(Pubkey, Pubkey)> = recipients
+        .iter()
+        .map(|recipient| {
+            (
+                *recipient,
+                get_associated_token_address(recipient, &deposit_mint),
+            )
+        })
+        .collect();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::initialize2(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_deposit_account,
+            &addresses.user_subscription_token_account,
+            &payee,
+            &addresses.payee_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.trial_record,
+            &addresses.subscription_mint,
+            &addresses.deposit_vault,
+            &deposit_mint,
+            &spl_token::id(),
+            START_AMOUNT,
+            0,
+            MAX_AMOUNT,
+            0,
+            0,
+            0,
+            false,
+            &split_accounts,
+            None,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // shares are rounded down, with the dust going to the first recipient
+    for ((_, recipient_deposit_account), share) in split_accounts.iter().zip([134, 44, 22]) {
+        assert_eq!(
+            token_amount(&mut context.banks_client, recipient_deposit_account).await,
+            share
+        );
+    }
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.user_deposit_account).await,
+        USER_BALANCE - START_AMOUNT - AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_renew2_pays_referral_commission() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let referrer = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+    let referrer_deposit_account = get_associated_token_address(&referrer, &deposit_mint);
+
+    // 10% of the first renewal only
+    let policy = state::PlanPolicy {
+        referral_basis_points: 1_000,
+        referral_renewals: 1,
+        ..POLICY
+    };
+    let mut context = setup_with_policy(&program_id, &user, &payee, &deposit_mint, 0, policy)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            instruction::initialize2(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_deposit_account,
+                &addresses.user_subscription_token_account,
+                &payee,
+                &addresses.payee_deposit_account,
+                &addresses.subscription,
+                &addresses.plan,
+                &addresses.trial_record,
+                &addresses.subscription_mint,
+                &addresses.deposit_vault,
+                &deposit_mint,
+                &spl_token::id(),
+                START_AMOUNT,
+                0,
+                MAX_AMOUNT,
+                0,
+                0,
+                0,
+                false,
+                &[],
+                Some(&referrer),
+            ),
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &user.pubkey(),
+                &[],
+                2 * AMOUNT,
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert_eq!(subscription.referrer, referrer);
+    assert_eq!(subscription.referral_basis_points, 1_000);
+    assert_eq!(subscription.referral_renewals, 1);
+
+    // the first renewal pays the referrer out of the payee's share
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::renew2(
+            &program_id,
+            &caller,
+            &get_associated_token_address(&caller, &deposit_mint),
+            &payee,
+            &addresses.payee_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.deposit_vault,
+            &deposit_mint,
+            &spl_token::id(),
+            &[],
+            None,
+            Some((&referrer, &referrer_deposit_account)),
+            None,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &referrer_deposit_account).await,
+        AMOUNT / 10
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        2 * AMOUNT - AMOUNT / 10
+    );
+
+    // later renewals don't need the referrer
+    warp_past_period(&mut context).await;
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::renew2(
+            &program_id,
+            &caller,
+            &get_associated_token_address(&caller, &deposit_mint),
+            &payee,
+            &addresses.payee_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.deposit_vault,
+            &deposit_mint,
+            &spl_token::id(),
+            &[],
+            None,
+            None,
+            None,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &referrer_deposit_account).await,
+        AMOUNT / 10
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        3 * AMOUNT - AMOUNT / 10
+    );
+}
+
+#[tokio::test]
+async fn test_renew2_pulls_from_delegated_account() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    // nothing in the vault, the subscription may pull a single renewal
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            instruction::initialize2(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_deposit_account,
+                &addresses.user_subscription_token_account,
+                &payee,
+                &addresses.payee_deposit_account,
+                &addresses.subscription,
+                &addresses.plan,
+                &addresses.trial_record,
+                &addresses.subscription_mint,
+                &addresses.deposit_vault,
+                &deposit_mint,
+                &spl_token::id(),
+                0,
+                0,
+                MAX_AMOUNT,
+                0,
+                0,
+                0,
+                true,
+                &[],
+                None,
+            ),
+            spl_token::instruction::approve(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.subscription,
+                &user.pubkey(),
+                &[],
+                AMOUNT + FEE_AMOUNT,
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    let subscription = context
+        .banks_client
+        .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+        .await
+        .unwrap();
+    assert_eq!(subscription.funding_account, addresses.user_deposit_account);
+
+    let caller = context.payer.pubkey();
+    let renew2 = |caller: &Pubkey| {
+        instruction::renew2(
+            &program_id,
+            caller,
+            &get_associated_token_address(caller, &deposit_mint),
+            &payee,
+            &addresses.payee_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.deposit_vault,
+            &deposit_mint,
+            &spl_token::id(),
+            &[],
+            Some(&addresses.user_deposit_account),
+            None,
+            None,
+        )
+    };
+
+    // the renewal and its fee come straight from the user
+    warp_past_period(&mut context).await;
+    let mut transaction = Transaction::new_with_payer(&[renew2(&caller)], Some(&caller));
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert!(
+        context
+            .banks_client
+            .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+            .await
+            .unwrap()
+            .active
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        2 * AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.user_deposit_account).await,
+        USER_BALANCE - 2 * AMOUNT - FEE_AMOUNT
+    );
+    assert_eq!(
+        token_amount(
+            &mut context.banks_client,
+            &get_associated_token_address(&caller, &deposit_mint)
+        )
+        .await,
+        FEE_AMOUNT
+    );
+
+    // the delegation is used up, so the next renewal expires it
+    warp_past_period(&mut context).await;
+    let mut transaction =
+        Transaction::new_with_payer(&[renew2(&user.pubkey())], Some(&user.pubkey()));
+    transaction.sign(&[&user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert!(
+        !context
+            .banks_client
+            .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+            .await
+            .unwrap()
+            .active
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        2 * AMOUNT
+    );
+}
+
+#[tokio::test]
+async fn test_renew2_expires_closed_funding_account() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut context = setup(&program_id, &user, &payee, &deposit_mint, 0)
+        .start_with_context()
+        .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &delegated_initialize2_instructions(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            AMOUNT + FEE_AMOUNT,
+        ),
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // the user empties and closes the account renewals are pulled from
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            spl_token::instruction::transfer(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &addresses.payee_deposit_account,
+                &user.pubkey(),
+                &[],
+                USER_BALANCE - AMOUNT,
+            )
+            .unwrap(),
+            spl_token::instruction::close_account(
+                &spl_token::id(),
+                &addresses.user_deposit_account,
+                &user.pubkey(),
+                &user.pubkey(),
+                &[],
+            )
+            .unwrap(),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert!(context
+        .banks_client
+        .get_account(addresses.user_deposit_account)
+        .await
+        .unwrap()
+        .is_none());
+
+    // a closed funding account is unfunded, so the renewal expires it
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[delegated_renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert!(
+        !context
+            .banks_client
+            .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+            .await
+            .unwrap()
+            .active
+    );
+}
+
+#[tokio::test]
+async fn test_native_sol_wraps_and_unwraps() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = spl_token::native_mint::id();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut program_test = ProgramTest::new(
+        "buoyant",
+        program_id,
+        processor!(Processor::process_instruction),
+    );
+    add_plan(
+        &mut program_test,
+        &program_id,
+        test_plan(&payee, &deposit_mint, PLAN_ID, 0, POLICY),
+    );
+    program_test.add_account(
+        user.pubkey(),
+        Account {
+            lamports: 1_000_000_000,
+            ..Account::default()
+        },
+    );
+    program_test.add_account(
+        deposit_mint,
+        packed_account(
+            Mint {
+                mint_authority: COption::None,
+                supply: 0,
+                decimals: 9,
+                is_initialized: true,
+                freeze_authority: COption::None,
+            },
+            &spl_token::id(),
+        ),
+    );
+    let mut context = program_test.start_with_context().await;
+
+    // the user only holds lamports, the payment is wrapped and the wrapped
+    // account closed again
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        START_AMOUNT
+    );
+    assert!(context
+        .banks_client
+        .get_account(addresses.user_deposit_account)
+        .await
+        .unwrap()
+        .is_none());
+
+    // lamports sent to the vault count once the renewal syncs them
+    let caller = context.payer.pubkey();
+    warp_past_period(&mut context).await;
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            solana_program::system_instruction::transfer(
+                &user.pubkey(),
+                &addresses.deposit_vault,
+                AMOUNT,
+            ),
+            renew2_instruction(
+                &program_id,
+                &caller,
+                &user.pubkey(),
+                &payee,
+                &deposit_mint,
+                0,
+            ),
+        ],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        2 * AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        START_AMOUNT - FEE_AMOUNT
+    );
+
+    // closing unwraps the vault straight to the user
+    let user_lamports = context
+        .banks_client
+        .get_balance(user.pubkey())
+        .await
+        .unwrap();
+    let reclaimable_lamports = context
+        .banks_client
+        .get_balance(addresses.subscription)
+        .await
+        .unwrap()
+        + context
+            .banks_client
+            .get_balance(addresses.deposit_vault)
+            .await
+            .unwrap()
+        + context
+            .banks_client
+            .get_balance(addresses.user_subscription_token_account)
+            .await
+            .unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[close_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        context
+            .banks_client
+            .get_balance(user.pubkey())
+            .await
+            .unwrap(),
+        user_lamports + reclaimable_lamports
+    );
+    assert!(context
+        .banks_client
+        .get_account(addresses.user_deposit_account)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_native_sol_refund_unwraps() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = spl_token::native_mint::id();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+    let refund_account = find_refund_address(&program_id, &addresses.subscription);
+
+    let mut program_test = ProgramTest::new(
+        "buoyant",
+        program_id,
+        processor!(Processor::process_instruction),
+    );
+    add_plan(
+        &mut program_test,
+        &program_id,
+        test_plan(&payee, &deposit_mint, PLAN_ID, 0, POLICY),
+    );
+    program_test.add_account(
+        user.pubkey(),
+        Account {
+            lamports: 1_000_000_000,
+            ..Account::default()
+        },
+    );
+    program_test.add_account(
+        deposit_mint,
+        packed_account(
+            Mint {
+                mint_authority: COption::None,
+                supply: 0,
+                decimals: 9,
+                is_initialized: true,
+                freeze_authority: COption::None,
+            },
+            &spl_token::id(),
+        ),
+    );
+    let mut context = program_test.start_with_context().await;
+
+    // deposit more than a token account's rent, then cancel at period end
+    let deposit = 10 * Rent::default().minimum_balance(TokenAccount::LEN);
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            solana_program::system_instruction::transfer(
+                &user.pubkey(),
+                &addresses.deposit_vault,
+                deposit,
+            ),
+            instruction::set_cancel_at_period_end(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_subscription_token_account,
+                &addresses.subscription,
+                true,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // the owner gets the refund as lamports, the caller the fee and the rent
+    // it fronted as wrapped SOL
+    let user_lamports = context
+        .banks_client
+        .get_balance(user.pubkey())
+        .await
+        .unwrap();
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let mut transaction = Transaction::new_with_payer(
+        &[renew2_instruction(
+            &program_id,
+            &caller,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    assert!(
+        !context
+            .banks_client
+            .get_account_data_with_borsh::<state::Subscription2>(addresses.subscription)
+            .await
+            .unwrap()
+            .active
+    );
+    assert_eq!(
+        context
+            .banks_client
+            .get_balance(user.pubkey())
+            .await
+            .unwrap(),
+        user_lamports + START_AMOUNT + deposit - FEE_AMOUNT
+    );
+    assert_eq!(
+        token_amount(
+            &mut context.banks_client,
+            &get_associated_token_address(&caller, &deposit_mint)
+        )
+        .await,
+        FEE_AMOUNT + Rent::default().minimum_balance(TokenAccount::LEN)
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        0
+    );
+    assert!(context
+        .banks_client
+        .get_account(refund_account)
+        .await
+        .unwrap()
+        .is_none());
+}
+
+#[tokio::test]
+async fn test_deposit_wraps_native_sol() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = spl_token::native_mint::id();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let mut program_test = ProgramTest::new(
+        "buoyant",
+        program_id,
+        processor!(Processor::process_instruction),
+    );
+    add_plan(
+        &mut program_test,
+        &program_id,
+        test_plan(&payee, &deposit_mint, PLAN_ID, 0, POLICY),
+    );
+    program_test.add_account(
+        user.pubkey(),
+        Account {
+            lamports: 1_000_000_000,
+            ..Account::default()
+        },
+    );
+    program_test.add_account(
+        deposit_mint,
+        packed_account(
+            Mint {
+                mint_authority: COption::None,
+                supply: 0,
+                decimals: 9,
+                is_initialized: true,
+                freeze_authority: COption::None,
+            },
+            &spl_token::id(),
+        ),
+    );
+    let mut context = program_test.start_with_context().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+
+    // the user's empty wrapped account is topped up with lamports first
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            spl_associated_token_account::create_associated_token_account(
+                &context.payer.pubkey(),
+                &user.pubkey(),
+                &deposit_mint,
+            ),
+            instruction::deposit(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_deposit_account,
+                &addresses.deposit_vault,
+                &deposit_mint,
+                &spl_token::id(),
+                AMOUNT,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.deposit_vault).await,
+        START_AMOUNT + AMOUNT
+    );
+    assert_eq!(
+        token_amount(&mut context.banks_client, &addresses.user_deposit_account).await,
+        0
+    );
+}
+
+#[tokio::test]
+async fn test_sync_metadata_rejects_invalid_accounts() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+
+    let (mut banks_client, payer, recent_blockhash) =
+        setup(&program_id, &user, &payee, &deposit_mint, 0)
+            .start()
+            .await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[initialize2_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer, &user], recent_blockhash);
+    assert_matches!(banks_client.process_transaction(transaction).await, Ok(()));
+
+    let (token_metadata, _) = metadata::find_metadata_address(&addresses.subscription_mint);
+    let (deposit_mint_metadata, _) = metadata::find_metadata_address(&deposit_mint);
+    let other_plan = find_plan_address(&program_id, &payee, PLAN_ID + 1).0;
+
+    // another plan, another mint, or a metadata account not derived from the
+    // subscription mint
+    for (plan, subscription_mint, token_metadata) in [
+        (&other_plan, &addresses.subscription_mint, &token_metadata),
+        (&addresses.plan, &deposit_mint, &deposit_mint_metadata),
+        (
+            &addresses.plan,
+            &addresses.subscription_mint,
+            &deposit_mint_metadata,
+        ),
+    ] {
+        let mut transaction = Transaction::new_with_payer(
+            &[instruction::sync_metadata(
+                &program_id,
+                &payer.pubkey(),
+                &user.pubkey(),
+                &addresses.user_subscription_token_account,
+                &addresses.subscription,
+                plan,
+                subscription_mint,
+                token_metadata,
+            )],
+            Some(&payer.pubkey()),
+        );
+        transaction.sign(&[&payer, &user], recent_blockhash);
+        assert!(banks_client.process_transaction(transaction).await.is_err());
+    }
+
+    // only the holder of the ownership token can sync its metadata
+    let payer_subscription_token_account =
+        get_associated_token_address(&payer.pubkey(), &addresses.subscription_mint);
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            spl_associated_token_account::create_associated_token_account(
+                &payer.pubkey(),
+                &payer.pubkey(),
+                &addresses.subscription_mint,
+            ),
+            instruction::sync_metadata(
+                &program_id,
+                &payer.pubkey(),
+                &payer.pubkey(),
+                &payer_subscription_token_account,
+                &addresses.subscription,
+                &addresses.plan,
+                &addresses.subscription_mint,
+                &token_metadata,
+            ),
+        ],
+        Some(&payer.pubkey()),
+    );
+    transaction.sign(&[&payer], recent_blockhash);
+    assert!(banks_client.process_transaction(transaction).await.is_err());
+}
+
+/// The URI of the ownership token metadata at `address`, owned by the token
+/// metadata program.
+async fn metadata_uri(banks_client: &mut BanksClient, address: &Pubkey) -> String {
+    let account = banks_client.get_account(*address).await.unwrap().unwrap();
+    assert_eq!(account.owner, metadata::mpl_token_metadata::id());
+    // key, update authority, mint, then name and symbol ahead of the uri
+    let mut data = &account.data[1 + 32 + 32..];
+    let _name = String::deserialize(&mut data).unwrap();
+    let _symbol = String::deserialize(&mut data).unwrap();
+    String::deserialize(&mut data)
+        .unwrap()
+        .trim_end_matches('\0')
+        .to_string()
+}
+
+#[tokio::test]
+async fn test_sync_metadata_and_reuse_index() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let addresses = find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0);
+    let (token_metadata, _) = metadata::find_metadata_address(&addresses.subscription_mint);
+
+    let mut plan = test_plan(&payee, &deposit_mint, PLAN_ID, 0, POLICY);
+    plan.name = utils::to_fixed_bytes("Pro").unwrap();
+    plan.uri = utils::to_fixed_bytes("https://example.com/pro.json").unwrap();
+    // the token metadata program is loaded from tests/fixtures
+    let mut program_test = setup_with_plan(&program_id, &user, &deposit_mint, plan);
+    program_test.add_program(
+        "mpl_token_metadata",
+        metadata::mpl_token_metadata::id(),
+        None,
+    );
+    let mut context = program_test.start_with_context().await;
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            instruction::sync_metadata(
+                &program_id,
+                &context.payer.pubkey(),
+                &user.pubkey(),
+                &addresses.user_subscription_token_account,
+                &addresses.subscription,
+                &addresses.plan,
+                &addresses.subscription_mint,
+                &token_metadata,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        metadata_uri(&mut context.banks_client, &token_metadata).await,
+        "https://example.com/pro.json?period=10s&status=active"
+    );
+    let account = context
+        .banks_client
+        .get_account(token_metadata)
+        .await
+        .unwrap()
+        .unwrap();
+    assert_eq!(account.data[1..33], addresses.subscription.to_bytes());
+    assert!(String::from_utf8_lossy(&account.data).contains("Pro"));
+
+    // syncing again updates the status in place
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            instruction::pause(
+                &program_id,
+                &user.pubkey(),
+                &addresses.user_subscription_token_account,
+                &addresses.subscription,
+                &addresses.plan,
+            ),
+            instruction::sync_metadata(
+                &program_id,
+                &user.pubkey(),
+                &user.pubkey(),
+                &addresses.user_subscription_token_account,
+                &addresses.subscription,
+                &addresses.plan,
+                &addresses.subscription_mint,
+                &token_metadata,
+            ),
+        ],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        metadata_uri(&mut context.banks_client, &token_metadata).await,
+        "https://example.com/pro.json?period=10s&status=paused"
+    );
+
+    // the metadata outlives Close, and the index can still be reused since
+    // the subscription kept the mint authority
+    let mut transaction = Transaction::new_with_payer(
+        &[close_instruction(
+            &program_id,
+            &user.pubkey(),
+            &payee,
+            &deposit_mint,
+            0,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        metadata_uri(&mut context.banks_client, &token_metadata).await,
+        "https://example.com/pro.json?period=10s&status=paused"
+    );
+
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            initialize2_instruction(&program_id, &user.pubkey(), &payee, &deposit_mint, 0),
+            instruction::sync_metadata(
+                &program_id,
+                &user.pubkey(),
+                &user.pubkey(),
+                &addresses.user_subscription_token_account,
+                &addresses.subscription,
+                &addresses.plan,
+                &addresses.subscription_mint,
+                &token_metadata,
+            ),
+        ],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        token_amount(
+            &mut context.banks_client,
+            &addresses.user_subscription_token_account
+        )
+        .await,
+        1
+    );
+    assert_eq!(
+        metadata_uri(&mut context.banks_client, &token_metadata).await,
+        "https://example.com/pro.json?period=10s&status=active"
+    );
+}
+
+/// Transfer fee of the spl-token-2022 deposit mint: 1%, at most 5.
+const TRANSFER_FEE_BASIS_POINTS: u16 = 100;
+const MAXIMUM_TRANSFER_FEE: u64 = 5;
+
+/// An spl-token-2022 mint with a transfer fee config.
+fn token_2022_mint_account(supply: u64) -> Account {
+    let mut data = vec![0; TokenAccount::LEN];
+    Mint {
+        supply,
+        is_initialized: true,
+        ..Mint::default()
+    }
+    .pack_into_slice(&mut data[..Mint::LEN]);
+    // account type, then TransferFeeConfig: its authorities, the withheld
+    // amount, and the older and newer fees
+    data.push(1);
+    data.extend_from_slice(&1u16.to_le_bytes());
+    data.extend_from_slice(&108u16.to_le_bytes());
+    data.extend_from_slice(&[0; 32 + 32 + 8]);
+    for _ in 0..2 {
+        data.extend_from_slice(&0u64.to_le_bytes());
+        data.extend_from_slice(&MAXIMUM_TRANSFER_FEE.to_le_bytes());
+        data.extend_from_slice(&TRANSFER_FEE_BASIS_POINTS.to_le_bytes());
+    }
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: token::spl_token_2022::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+/// An spl-token-2022 token account of a mint with a transfer fee config.
+fn token_2022_account(mint: &Pubkey, owner: &Pubkey, amount: u64) -> Account {
+    let mut data = vec![0; TokenAccount::LEN];
+    TokenAccount {
+        mint: *mint,
+        owner: *owner,
+        amount,
+        state: AccountState::Initialized,
+        ..TokenAccount::default()
+    }
+    .pack_into_slice(&mut data);
+    // account type, then TransferFeeAmount: the withheld amount
+    data.push(2);
+    data.extend_from_slice(&2u16.to_le_bytes());
+    data.extend_from_slice(&8u16.to_le_bytes());
+    data.extend_from_slice(&0u64.to_le_bytes());
+    Account {
+        lamports: Rent::default().minimum_balance(data.len()),
+        data,
+        owner: token::spl_token_2022::id(),
+        executable: false,
+        rent_epoch: 0,
+    }
+}
+
+/// Returns the value of an spl-token-2022 account's extension, which follow
+/// the base account, padded to a token account's length, and an account type
+/// byte, as type-length-value entries.
+fn token_2022_extension(data: &[u8], extension_type: u16) -> &[u8] {
+    let mut offset = TokenAccount::LEN + 1;
+    loop {
+        let entry_type = u16::from_le_bytes(data[offset..offset + 2].try_into().unwrap());
+        let len = u16::from_le_bytes(data[offset + 2..offset + 4].try_into().unwrap()) as usize;
+        offset += 4;
+        if entry_type == extension_type {
+            return &data[offset..offset + len];
+        }
+        offset += len;
+    }
+}
+
+/// Returns the amount and withheld transfer fees of an spl-token-2022 token
+/// account.
+async fn token_2022_amounts(banks_client: &mut BanksClient, address: &Pubkey) -> (u64, u64) {
+    let data = banks_client
+        .get_account(*address)
+        .await
+        .unwrap()
+        .unwrap()
+        .data;
+    // TransferFeeAmount
+    let withheld = token_2022_extension(&data, 2).try_into().unwrap();
+    (
+        TokenAccount::unpack_from_slice(&data).unwrap().amount,
+        u64::from_le_bytes(withheld),
+    )
+}
+
+#[tokio::test]
+async fn test_token_2022_transfer_fee() {
+    let program_id = Pubkey::new_unique();
+    let user = Keypair::new();
+    let payee = Pubkey::new_unique();
+    let deposit_mint = Pubkey::new_unique();
+    let token_program = token::spl_token_2022::id();
+    let addresses = Addresses {
+        deposit_vault: token::get_associated_token_address(
+            &find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0).subscription,
+            &deposit_mint,
+            &token_program,
+        ),
+        payee_deposit_account: token::get_associated_token_address(
+            &payee,
+            &deposit_mint,
+            &token_program,
+        ),
+        user_deposit_account: token::get_associated_token_address(
+            &user.pubkey(),
+            &deposit_mint,
+            &token_program,
+        ),
+        ..find_addresses(&program_id, &user.pubkey(), &payee, &deposit_mint, 0)
+    };
+
+    // spl-token-2022 and an associated token account program supporting it
+    // are loaded from tests/fixtures
+    let mut program_test = setup(&program_id, &user, &payee, &deposit_mint, 0);
+    program_test.add_program("spl_token_2022", token_program, None);
+    program_test.add_program(
+        "spl_associated_token_account",
+        spl_associated_token_account::id(),
+        None,
+    );
+    program_test.add_account(deposit_mint, token_2022_mint_account(USER_BALANCE));
+    program_test.add_account(
+        addresses.user_deposit_account,
+        token_2022_account(&deposit_mint, &user.pubkey(), USER_BALANCE),
+    );
+    let mut context = program_test.start_with_context().await;
+
+    // the first payment is grossed up so the payee receives it in full, the
+    // start amount isn't
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::initialize2(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_deposit_account,
+            &addresses.user_subscription_token_account,
+            &payee,
+            &addresses.payee_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.trial_record,
+            &addresses.subscription_mint,
+            &addresses.deposit_vault,
+            &deposit_mint,
+            &token_program,
+            START_AMOUNT,
+            0,
+            MAX_AMOUNT,
+            0,
+            0,
+            0,
+            false,
+            &[],
+            None,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        token_2022_amounts(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        (AMOUNT, 3)
+    );
+    assert_eq!(
+        token_2022_amounts(&mut context.banks_client, &addresses.deposit_vault).await,
+        (START_AMOUNT - 3, 3)
+    );
+
+    // a top up is grossed up too, the maximum fee applies
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::deposit(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_deposit_account,
+            &addresses.deposit_vault,
+            &deposit_mint,
+            &token_program,
+            2 * AMOUNT,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    let vault_amount = START_AMOUNT - 3 + 2 * AMOUNT;
+    assert_eq!(
+        token_2022_amounts(&mut context.banks_client, &addresses.deposit_vault).await,
+        (vault_amount, 3 + MAXIMUM_TRANSFER_FEE)
+    );
+    assert_eq!(
+        token_2022_amounts(&mut context.banks_client, &addresses.user_deposit_account).await,
+        (
+            USER_BALANCE - (AMOUNT + 3) - START_AMOUNT - (2 * AMOUNT + MAXIMUM_TRANSFER_FEE),
+            0
+        )
+    );
+
+    // the renewal is grossed up out of the vault, the caller's fee isn't
+    warp_past_period(&mut context).await;
+    let caller = context.payer.pubkey();
+    let caller_deposit_account =
+        token::get_associated_token_address(&caller, &deposit_mint, &token_program);
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::renew2(
+            &program_id,
+            &caller,
+            &caller_deposit_account,
+            &payee,
+            &addresses.payee_deposit_account,
+            &addresses.subscription,
+            &addresses.plan,
+            &addresses.deposit_vault,
+            &deposit_mint,
+            &token_program,
+            &[],
+            None,
+            None,
+            None,
+        )],
+        Some(&caller),
+    );
+    transaction.sign(&[&context.payer], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert_eq!(
+        token_2022_amounts(&mut context.banks_client, &addresses.payee_deposit_account).await,
+        (2 * AMOUNT, 2 * 3)
+    );
+    assert_eq!(
+        token_2022_amounts(&mut context.banks_client, &caller_deposit_account).await,
+        (FEE_AMOUNT - 1, 1)
+    );
+    let vault_amount = vault_amount - (AMOUNT + 3) - FEE_AMOUNT;
+    assert_eq!(
+        token_2022_amounts(&mut context.banks_client, &addresses.deposit_vault).await,
+        (vault_amount, 3 + MAXIMUM_TRANSFER_FEE)
+    );
+
+    // closing sweeps the vault, harvests its withheld fees to the mint and
+    // closes it
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::close(
+            &program_id,
+            &user.pubkey(),
+            &addresses.user_deposit_account,
+            &addresses.user_subscription_token_account,
+            &addresses.subscription_mint,
+            &addresses.subscription,
+            &addresses.deposit_vault,
+            &deposit_mint,
+            &token_program,
+        )],
+        Some(&context.payer.pubkey()),
+    );
+    transaction.sign(&[&context.payer, &user], context.last_blockhash);
+    assert_matches!(
+        context.banks_client.process_transaction(transaction).await,
+        Ok(())
+    );
+    assert!(context
+        .banks_client
+        .get_account(addresses.deposit_vault)
+        .await
+        .unwrap()
+        .is_none());
+    let sweep_fee = 4;
+    assert_eq!(
+        token_2022_amounts(&mut context.banks_client, &addresses.user_deposit_account).await,
+        (
+            USER_BALANCE - (AMOUNT + 3) - START_AMOUNT - (2 * AMOUNT + MAXIMUM_TRANSFER_FEE)
+                + vault_amount
+                - sweep_fee,
+            sweep_fee
+        )
+    );
+    // TransferFeeConfig, the withheld amount follows its two authorities
+    let mint_data = context
+        .banks_client
+        .get_account(deposit_mint)
+        .await
+        .unwrap()
+        .unwrap()
+        .data;
+    let withheld = token_2022_extension(&mint_data, 1)[64..72]
+        .try_into()
+        .unwrap();
+    assert_eq!(u64::from_le_bytes(withheld), 3 + MAXIMUM_TRANSFER_FEE);
+}
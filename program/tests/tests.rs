@@ -1,7 +1,7 @@
 #![cfg(feature = "test-bpf")]
 
 use {
-    solana_sdk::{signature::{Signer, Keypair}, transaction::Transaction},
+    solana_sdk::{signature::{Signer, Keypair}, system_instruction, transaction::Transaction},
     assert_matches::*,
     solana_program::pubkey::Pubkey,
     solana_validator::test_validator::*,
@@ -73,13 +73,231 @@ fn test_initialize() {
     assert_matches!(rpc_client.send_and_confirm_transaction(&transaction), Ok(_));
 }
 
+#[test]
+fn test_withdraw() {
+    solana_logger::setup_with_default("solana_program_runtime=debug");
+    let program_pubkey = Pubkey::new_unique();
+
+    // Params
+    let payee = Keypair::new();
+    let amount: u64 = 1_000_000; // 0.001 SOL per renewal
+    let duration: i64 = 1_000; // long enough to stay active throughout
+    let payee_pubkey = payee.pubkey();
+    let stranger = Keypair::new();
+
+    // Configure test validator
+    let (test_validator, user) = TestValidatorGenesis::default()
+        .add_program("buoyant", program_pubkey)
+        .start();
+    let rpc_client = test_validator.get_rpc_client();
+
+    // Find uninitialized counter and subscription PDAs
+    let counter_seeds = &[
+        b"subscription_counter",
+        payee_pubkey.as_ref(),
+        &amount.to_le_bytes(),
+        &duration.to_le_bytes(),
+    ];
+    let (counter, _) = Pubkey::find_program_address(counter_seeds, &program_pubkey);
+    let count: u64 = 0;
+    let subscription_seeds = &[
+        b"subscription_metadata",
+        payee_pubkey.as_ref(),
+        &amount.to_le_bytes(),
+        &duration.to_le_bytes(),
+        &count.to_le_bytes(),
+    ];
+    let (sub, _) = Pubkey::find_program_address(subscription_seeds, &program_pubkey);
+
+    // first renewal mints the ownership token from the subscription's first mint
+    let renewal_count: u64 = 0;
+    let mint_seeds = &[
+        b"subscription_mint",
+        sub.as_ref(),
+        &renewal_count.to_le_bytes(),
+    ];
+    let (subscription_mint, _) = Pubkey::find_program_address(mint_seeds, &program_pubkey);
+
+    // Find token accounts
+    let mint = native_mint::id(); // SPL token address
+    let vault = get_associated_token_address(&sub, &mint);
+    let payee_vault = get_associated_token_address(&payee_pubkey, &mint);
+    let user_vault = get_associated_token_address(&user.pubkey(), &mint);
+    let user_subscription_token = get_associated_token_address(&user.pubkey(), &subscription_mint);
+    let stranger_vault = get_associated_token_address(&stranger.pubkey(), &mint);
+    let stranger_subscription_token = get_associated_token_address(&stranger.pubkey(), &subscription_mint);
+
+    // initialize, deposit three periods and renew into an active subscription
+    let blockhash = rpc_client.get_latest_blockhash().unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            instruction::initialize(
+                &program_pubkey,
+                &user.pubkey(),
+                &counter,
+                &sub,
+                &vault,
+                &mint,
+                &payee_pubkey,
+                amount,
+                duration
+            ),
+            spl_associated_token_account::create_associated_token_account(
+                &user.pubkey(),
+                &user.pubkey(),
+                &mint,
+            ),
+            instruction::deposit(
+                &program_pubkey,
+                &user.pubkey(),
+                &user_vault,
+                &vault,
+                &mint,
+                &spl_token::id(),
+                3 * amount,
+            ),
+            instruction::renew(
+                &program_pubkey,
+                &user.pubkey(),
+                &sub,
+                &mint,
+                &vault,
+                &payee_pubkey,
+                &payee_vault,
+                &user_vault,
+                &subscription_mint,
+                &user_subscription_token,
+                &user_subscription_token,
+                &user.pubkey(),
+                count,
+            ),
+        ],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], blockhash);
+    assert_matches!(rpc_client.send_and_confirm_transaction(&transaction), Ok(_));
+
+    let subscription_bytes = rpc_client.get_account_data(&sub).unwrap();
+    let subscription_data = state::Subscription::try_from_slice(&subscription_bytes[..]).unwrap();
+    assert_eq!(subscription_data.active, true);
+    assert_eq!(subscription_data.mint, Some(subscription_mint));
+    assert_eq!(rpc_client.get_token_account_balance(&vault).unwrap().amount, (2 * amount).to_string());
+
+    // withdrawing down to the next renewal is allowed
+    let blockhash = rpc_client.get_latest_blockhash().unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::withdraw(
+            &program_pubkey,
+            &user.pubkey(),
+            &user_vault,
+            &user_subscription_token,
+            &vault,
+            &sub,
+            &spl_token::id(),
+            amount,
+            count,
+            false,
+        ).unwrap()],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], blockhash);
+    assert_matches!(rpc_client.send_and_confirm_transaction(&transaction), Ok(_));
+    assert_eq!(rpc_client.get_token_account_balance(&vault).unwrap().amount, amount.to_string());
+
+    // but not past it while active
+    let blockhash = rpc_client.get_latest_blockhash().unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::withdraw(
+            &program_pubkey,
+            &user.pubkey(),
+            &user_vault,
+            &user_subscription_token,
+            &vault,
+            &sub,
+            &spl_token::id(),
+            1,
+            count,
+            false,
+        ).unwrap()],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], blockhash);
+    assert!(rpc_client.send_and_confirm_transaction(&transaction).is_err());
+
+    // someone without the ownership token can't withdraw, even when cancelling
+    let blockhash = rpc_client.get_latest_blockhash().unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[
+            system_instruction::transfer(&user.pubkey(), &stranger.pubkey(), 1_000_000_000),
+            spl_associated_token_account::create_associated_token_account(
+                &user.pubkey(),
+                &stranger.pubkey(),
+                &mint,
+            ),
+            spl_associated_token_account::create_associated_token_account(
+                &user.pubkey(),
+                &stranger.pubkey(),
+                &subscription_mint,
+            ),
+        ],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], blockhash);
+    assert_matches!(rpc_client.send_and_confirm_transaction(&transaction), Ok(_));
+
+    let blockhash = rpc_client.get_latest_blockhash().unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::withdraw(
+            &program_pubkey,
+            &stranger.pubkey(),
+            &stranger_vault,
+            &stranger_subscription_token,
+            &vault,
+            &sub,
+            &spl_token::id(),
+            amount,
+            count,
+            true,
+        ).unwrap()],
+        Some(&stranger.pubkey()),
+    );
+    transaction.sign(&[&stranger], blockhash);
+    assert!(rpc_client.send_and_confirm_transaction(&transaction).is_err());
+
+    // cancelling lets the owner withdraw everything and deactivates
+    let blockhash = rpc_client.get_latest_blockhash().unwrap();
+    let mut transaction = Transaction::new_with_payer(
+        &[instruction::withdraw(
+            &program_pubkey,
+            &user.pubkey(),
+            &user_vault,
+            &user_subscription_token,
+            &vault,
+            &sub,
+            &spl_token::id(),
+            amount,
+            count,
+            true,
+        ).unwrap()],
+        Some(&user.pubkey()),
+    );
+    transaction.sign(&[&user], blockhash);
+    assert_matches!(rpc_client.send_and_confirm_transaction(&transaction), Ok(_));
+    assert_eq!(rpc_client.get_token_account_balance(&vault).unwrap().amount, "0");
+
+    let subscription_bytes = rpc_client.get_account_data(&sub).unwrap();
+    let subscription_data = state::Subscription::try_from_slice(&subscription_bytes[..]).unwrap();
+    assert_eq!(subscription_data.active, false);
+}
+
 #[test]
 fn test_initialize2() {
     solana_logger::setup_with_default("solana_program_runtime=debug");
     let program_id = Pubkey::new_unique();
 
     // Params
-    let payee = Pubkey::new_unique();
+    let payee_keypair = Keypair::new();
+    let payee = payee_keypair.pubkey();
     let amount: u64 = 200; // 200 [token] deposited into vault
     let duration: i64 = 10; // subscription recurrs every 10 seconds
     let start_amount: u64 = 201;
@@ -90,22 +308,26 @@ fn test_initialize2() {
         .start();
     let rpc_client = test_validator.get_rpc_client();
 
-    // Find uninitialized counter PDA
-    let counter_seeds = &[
-        b"subscription_counter",
+    // Find uninitialized plan PDA
+    let plan_id: u64 = 0;
+    let plan_seeds = &[
+        b"plan",
         payee.as_ref(),
-        &amount.to_le_bytes(),
-        &duration.to_le_bytes(),
+        &plan_id.to_le_bytes(),
+    ];
+    let (plan, _) = Pubkey::find_program_address(plan_seeds, &program_id);
+    let trial_record_seeds = &[
+        b"trial",
+        plan.as_ref(),
+        user.pubkey().as_ref(),
     ];
-    let (subscription_counter, _) = Pubkey::find_program_address(counter_seeds, &program_id);
+    let (trial_record, _) = Pubkey::find_program_address(trial_record_seeds, &program_id);
 
     // Find uninitialized subscription PDA
     let count: u64 = 0;
     let subscription_seeds = &[
         b"subscription_metadata",
-        payee.as_ref(),
-        &amount.to_le_bytes(),
-        &duration.to_le_bytes(),
+        plan.as_ref(),
         &count.to_le_bytes(),
     ];
     let (subscription, _) = Pubkey::find_program_address(subscription_seeds, &program_id);
@@ -126,25 +348,62 @@ fn test_initialize2() {
     // Send and confirm txn
     let blockhash = rpc_client.get_latest_blockhash().unwrap();
     let mut transaction = Transaction::new_with_payer(
-        &[instruction::initialize2(
-            &program_id,
-            &user.pubkey(),
-            &user_deposit_account,
-            &user_subscription_token_account,
-            &payee,
-            &payee_deposit_account,
-            &subscription,
-            &subscription_counter,
-            &subscription_mint,
-            &deposit_vault,
-            &deposit_mint,
-            amount,
-            duration,
-            start_amount,
-        )],
+        &[
+            system_instruction::transfer(&user.pubkey(), &payee, 1_000_000_000),
+            instruction::create_plan(
+                &program_id,
+                &payee,
+                &plan,
+                &deposit_mint,
+                plan_id,
+                amount,
+                duration,
+                state::BillingInterval::Duration,
+                state::FeePolicy {
+                    fixed: 0,
+                    basis_points: 100,
+                },
+                state::PlanPolicy {
+                    allow_pause: false,
+                    grace_period: 0,
+                    trial_periods: 0,
+                    catch_up: state::CatchUpPolicy::Reanchor,
+                    prepay_discounts: [state::PrepayDiscount::default(); state::PREPAY_DISCOUNT_TIERS],
+                    referral_basis_points: 0,
+                    referral_renewals: 0,
+                },
+                [state::SplitRecipient::default(); state::MAX_SPLIT_RECIPIENTS],
+                String::from("test plan"),
+                String::new(),
+            ),
+            instruction::initialize2(
+                &program_id,
+                &user.pubkey(),
+                &user_deposit_account,
+                &user_subscription_token_account,
+                &payee,
+                &payee_deposit_account,
+                &subscription,
+                &plan,
+                &trial_record,
+                &subscription_mint,
+                &deposit_vault,
+                &deposit_mint,
+                &spl_token::id(),
+                start_amount,
+                count,
+                amount,
+                0,
+                0,
+                0,
+                false,
+                &[],
+                None,
+            ),
+        ],
         Some(&user.pubkey()),
     );
-    transaction.sign(&[&user], blockhash);
+    transaction.sign(&[&user, &payee_keypair], blockhash);
 
     assert_matches!(rpc_client.send_and_confirm_transaction(&transaction), Ok(_));
 
@@ -161,7 +420,8 @@ fn test_renew2() {
     let program_id = Pubkey::new_unique();
 
     // Params
-    let payee = Pubkey::new_unique();
+    let payee_keypair = Keypair::new();
+    let payee = payee_keypair.pubkey();
     let amount: u64 = 200; // 200 [token] deposited into vault
     let duration: i64 = 1; // subscription recurrs every 1 seconds
     let start_amount: u64 = 201;
@@ -172,22 +432,26 @@ fn test_renew2() {
         .start();
     let rpc_client = test_validator.get_rpc_client();
 
-    // Find uninitialized counter PDA
-    let counter_seeds = &[
-        b"subscription_counter",
+    // Find uninitialized plan PDA
+    let plan_id: u64 = 0;
+    let plan_seeds = &[
+        b"plan",
         payee.as_ref(),
-        &amount.to_le_bytes(),
-        &duration.to_le_bytes(),
+        &plan_id.to_le_bytes(),
+    ];
+    let (plan, _) = Pubkey::find_program_address(plan_seeds, &program_id);
+    let trial_record_seeds = &[
+        b"trial",
+        plan.as_ref(),
+        user.pubkey().as_ref(),
     ];
-    let (subscription_counter, _) = Pubkey::find_program_address(counter_seeds, &program_id);
+    let (trial_record, _) = Pubkey::find_program_address(trial_record_seeds, &program_id);
 
     // Find uninitialized subscription PDA
     let count: u64 = 0;
     let subscription_seeds = &[
         b"subscription_metadata",
-        payee.as_ref(),
-        &amount.to_le_bytes(),
-        &duration.to_le_bytes(),
+        plan.as_ref(),
         &count.to_le_bytes(),
     ];
     let (subscription, _) = Pubkey::find_program_address(subscription_seeds, &program_id);
@@ -208,25 +472,62 @@ fn test_renew2() {
     // Send and confirm txn
     let blockhash = rpc_client.get_latest_blockhash().unwrap();
     let mut transaction = Transaction::new_with_payer(
-        &[instruction::initialize2(
-            &program_id,
-            &user.pubkey(),
-            &user_deposit_account,
-            &user_subscription_token_account,
-            &payee,
-            &payee_deposit_account,
-            &subscription,
-            &subscription_counter,
-            &subscription_mint,
-            &deposit_vault,
-            &deposit_mint,
-            amount,
-            duration,
-            start_amount,
-        )],
+        &[
+            system_instruction::transfer(&user.pubkey(), &payee, 1_000_000_000),
+            instruction::create_plan(
+                &program_id,
+                &payee,
+                &plan,
+                &deposit_mint,
+                plan_id,
+                amount,
+                duration,
+                state::BillingInterval::Duration,
+                state::FeePolicy {
+                    fixed: 0,
+                    basis_points: 100,
+                },
+                state::PlanPolicy {
+                    allow_pause: false,
+                    grace_period: 0,
+                    trial_periods: 0,
+                    catch_up: state::CatchUpPolicy::Reanchor,
+                    prepay_discounts: [state::PrepayDiscount::default(); state::PREPAY_DISCOUNT_TIERS],
+                    referral_basis_points: 0,
+                    referral_renewals: 0,
+                },
+                [state::SplitRecipient::default(); state::MAX_SPLIT_RECIPIENTS],
+                String::from("test plan"),
+                String::new(),
+            ),
+            instruction::initialize2(
+                &program_id,
+                &user.pubkey(),
+                &user_deposit_account,
+                &user_subscription_token_account,
+                &payee,
+                &payee_deposit_account,
+                &subscription,
+                &plan,
+                &trial_record,
+                &subscription_mint,
+                &deposit_vault,
+                &deposit_mint,
+                &spl_token::id(),
+                start_amount,
+                count,
+                amount,
+                0,
+                0,
+                0,
+                false,
+                &[],
+                None,
+            ),
+        ],
         Some(&user.pubkey()),
     );
-    transaction.sign(&[&user], blockhash);
+    transaction.sign(&[&user, &payee_keypair], blockhash);
     assert_matches!(rpc_client.send_and_confirm_transaction(&transaction), Ok(_));
     println!("Successfully called initialize2 instruction.");
 
@@ -239,8 +540,14 @@ fn test_renew2() {
             &payee,
             &payee_deposit_account,
             &subscription,
+            &plan,
             &deposit_vault,
             &deposit_mint,
+            &spl_token::id(),
+            &[],
+            None,
+            None,
+            None,
         )],
         Some(&user.pubkey()),
     );